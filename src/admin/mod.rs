@@ -0,0 +1,228 @@
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    http::{self, Request, Response},
+    router::{BackendAction, BackendStatus},
+    Proxy,
+};
+
+#[derive(Deserialize)]
+struct CommandRequest {
+    command: String,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    ok: bool,
+    message: &'a str,
+}
+
+/// The admin API, every request needs `Authorization: Bearer <token>`:
+/// - `GET /backends`, `GET /backends/<name>`: status
+/// - `GET /backends/<name>/logs`: the last console lines
+/// - `POST /backends/<name>/start`
+/// - `POST /backends/<name>/stop`: sends `stop` to the console
+/// - `POST /backends/<name>/kill`: SIGKILL, for when `stop` doesn't work
+/// - `POST /backends/<name>/command` with `{"command": "say hi"}`
+pub async fn serve(listener: TcpListener, proxy: Arc<Proxy>, shutdown: CancellationToken) {
+    http::serve(listener, shutdown, "ADMIN", move |request: Request| {
+        let proxy = proxy.clone();
+        async move { answer(&proxy, request) }
+    })
+    .await
+}
+
+fn answer(proxy: &Proxy, request: Request) -> Response {
+    if !authorized(&proxy.config.admin.token, &request) {
+        return message(401, false, "missing or wrong bearer token");
+    }
+    let segments: Vec<&str> = request.path.split('/').filter(|x| !x.is_empty()).collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["backends"]) => {
+            let backends: Vec<BackendStatus> =
+                proxy.router.backends().iter().map(|x| x.status()).collect();
+            json(200, &backends)
+        }
+        ("GET", ["backends", name]) => match proxy.router.backend(name) {
+            Some(backend) => json(200, &backend.status()),
+            None => message(404, false, "no such backend"),
+        },
+        ("GET", ["backends", name, "logs"]) => match proxy.router.backend(name) {
+            Some(backend) => json(200, &backend.handler.lock().unwrap().console_history()),
+            None => message(404, false, "no such backend"),
+        },
+        ("POST", ["backends", name, action]) => {
+            let backend = match proxy.router.backend(name) {
+                Some(x) => x,
+                None => return message(404, false, "no such backend"),
+            };
+            let backend_action = match *action {
+                "start" => BackendAction::Start,
+                "stop" => BackendAction::Stop,
+                "kill" => BackendAction::Kill,
+                "command" => match serde_json::from_slice::<CommandRequest>(&request.body) {
+                    Ok(body) => BackendAction::Command(body.command),
+                    Err(err) => return message(400, false, &format!("bad command body: {err}")),
+                },
+                _ => return message(404, false, "unknown action"),
+            };
+            match backend.apply(backend_action, "ADMIN") {
+                Ok(()) => message(202, true, &format!("{action} sent")),
+                Err(err) => message(409, false, &err.to_string()),
+            }
+        }
+        (_, ["backends", ..]) => message(405, false, "method not allowed"),
+        _ => message(404, false, "not found"),
+    }
+}
+
+/// Compares every byte, so the time it takes doesn't leak how much of the token matched.
+fn authorized(token: &str, request: &Request) -> bool {
+    let given = match request
+        .header("authorization")
+        .and_then(|x| x.strip_prefix("Bearer "))
+    {
+        Some(x) => x.trim().as_bytes(),
+        None => return false,
+    };
+    let token = token.as_bytes();
+    given.len() == token.len() && given.iter().zip(token).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn json<T: serde::Serialize>(status: u16, body: &T) -> Response {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::create(status, "application/json", body),
+        Err(err) => message(500, false, &err.to_string()),
+    }
+}
+
+fn message(status: u16, ok: bool, message: &str) -> Response {
+    let body = serde_json::to_vec(&Message { ok, message }).unwrap_or_default();
+    Response::create(status, "application/json", body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config, metrics::Metrics, ratelimit::RateLimiter, router::Router,
+        sessions::SessionTable,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    /// Sends one request to the admin API of a proxy with a stopped `survival` backend,
+    /// returns the status code and the body.
+    async fn request(method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, String) {
+        let config = Config::parse(
+            "[admin]\nbind = \"127.0.0.1:0\"\ntoken = \"secret\"\n[[backends]]\nname = \"survival\"",
+        )
+        .unwrap();
+        let proxy = Arc::new(Proxy {
+            router: Router::create(&config),
+            rate_limiter: RateLimiter::create(&config.rate_limit),
+            metrics: Metrics::create(),
+            sessions: SessionTable::create(),
+            config,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve(listener, proxy, shutdown.clone()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let authorization = match token {
+            Some(token) => format!("Authorization: Bearer {token}\r\n"),
+            None => String::new(),
+        };
+        let head = format!(
+            "{method} {path} HTTP/1.1\r\n{authorization}Content-Length: {}\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        shutdown.cancel();
+        server.await.unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, body.to_owned())
+    }
+
+    #[tokio::test]
+    async fn needs_the_token() {
+        assert_eq!(request("GET", "/backends", None, "").await.0, 401);
+        assert_eq!(request("GET", "/backends", Some("secreT"), "").await.0, 401);
+        assert_eq!(
+            request("GET", "/backends", Some("secret!"), "").await.0,
+            401
+        );
+    }
+
+    #[tokio::test]
+    async fn backend_status() {
+        let (status, body) = request("GET", "/backends", Some("secret"), "").await;
+        assert_eq!(status, 200);
+        let backends: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(backends[0]["name"], "survival");
+        assert_eq!(backends[0]["running"], false);
+        assert_eq!(backends[0]["players_online"], serde_json::Value::Null);
+
+        let (status, body) = request("GET", "/backends/survival", Some("secret"), "").await;
+        assert_eq!(status, 200);
+        assert!(body.starts_with("{\"name\":\"survival\""));
+        assert_eq!(
+            request("GET", "/backends/creative", Some("secret"), "")
+                .await
+                .0,
+            404
+        );
+    }
+
+    #[tokio::test]
+    async fn actions_on_a_stopped_server() {
+        for action in ["stop", "kill"] {
+            let path = format!("/backends/survival/{action}");
+            assert_eq!(request("POST", &path, Some("secret"), "").await.0, 409);
+        }
+        let (status, body) = request(
+            "POST",
+            "/backends/survival/command",
+            Some("secret"),
+            r#"{"command": "say hi"}"#,
+        )
+        .await;
+        assert_eq!(status, 409);
+        assert!(body.starts_with("{\"ok\":false"));
+    }
+
+    #[tokio::test]
+    async fn bad_requests() {
+        let command = "/backends/survival/command";
+        assert_eq!(
+            request("POST", command, Some("secret"), "say hi").await.0,
+            400
+        );
+        assert_eq!(
+            request("POST", "/backends/survival/restart", Some("secret"), "")
+                .await
+                .0,
+            404
+        );
+        assert_eq!(
+            request("DELETE", "/backends/survival", Some("secret"), "")
+                .await
+                .0,
+            405
+        );
+        assert_eq!(request("GET", "/", Some("secret"), "").await.0, 404);
+    }
+}
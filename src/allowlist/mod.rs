@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use serde_derive::Deserialize;
+
+use crate::{config::BackendConfig, types::Uuid};
+
+/// The players allowed to start a sleeping backend by joining it.
+pub struct AllowList {
+    /// Usernames or uuids straight from the config
+    entries: Vec<String>,
+    /// `whitelist.json` / `ops.json` style files, read on every check so edits apply without a restart
+    files: Vec<PathBuf>,
+}
+
+/// The part of a `whitelist.json` / `ops.json` entry the proxy cares about.
+#[derive(Deserialize)]
+struct PlayerEntry {
+    uuid: Option<String>,
+    name: Option<String>,
+}
+
+impl AllowList {
+    pub fn create(backend: &BackendConfig) -> AllowList {
+        AllowList {
+            entries: backend.start_allow.clone(),
+            files: backend.start_allow_files.clone(),
+        }
+    }
+
+    /// An allow list without entries and files lets everybody in.
+    pub fn is_enabled(&self) -> bool {
+        !self.entries.is_empty() || !self.files.is_empty()
+    }
+
+    pub fn is_allowed(&self, name: &str, uuid: Option<Uuid>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let uuid = uuid.map(|x| AllowList::normalize_uuid(&x.to_string()));
+        let matches = |entry_name: Option<&str>, entry_uuid: Option<&str>| {
+            entry_name.is_some_and(|x| x.eq_ignore_ascii_case(name))
+                || entry_uuid
+                    .is_some_and(|x| uuid.as_deref() == Some(&AllowList::normalize_uuid(x)))
+        };
+        if self
+            .entries
+            .iter()
+            .any(|entry| matches(Some(entry), Some(entry)))
+        {
+            return true;
+        }
+        self.files.iter().any(|path| {
+            AllowList::read_file(path)
+                .iter()
+                .any(|entry| matches(entry.name.as_deref(), entry.uuid.as_deref()))
+        })
+    }
+
+    fn read_file(path: &PathBuf) -> Vec<PlayerEntry> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(err) => {
+                println!("PROXY: can't read allow list {}: {err}", path.display());
+                return Vec::new();
+            }
+        };
+        match serde_json::from_str(&text) {
+            Ok(x) => x,
+            Err(err) => {
+                println!("PROXY: can't parse allow list {}: {err}", path.display());
+                Vec::new()
+            }
+        }
+    }
+
+    /// Uuids can be written with or without dashes.
+    fn normalize_uuid(uuid: &str) -> String {
+        uuid.replace('-', "").to_lowercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Codec;
+
+    fn allow_list(entries: &[&str], files: Vec<PathBuf>) -> AllowList {
+        AllowList {
+            entries: entries.iter().map(|x| x.to_string()).collect(),
+            files,
+        }
+    }
+
+    fn uuid(hex: &str) -> Option<Uuid> {
+        let value = u128::from_str_radix(&hex.replace('-', ""), 16).unwrap();
+        Uuid::parse(&mut value.to_be_bytes().into_iter()).ok()
+    }
+
+    #[test]
+    fn empty_lets_everybody_in() {
+        let list = allow_list(&[], Vec::new());
+        assert!(!list.is_enabled());
+        assert!(list.is_allowed("anybody", None));
+    }
+
+    #[test]
+    fn names_and_uuids_from_the_config() {
+        let list = allow_list(&["Notch", "853c80ef3c3749fdaa49938b674adae6"], Vec::new());
+        assert!(list.is_allowed("notch", None));
+        assert!(list.is_allowed("jeb_", uuid("853c80ef-3c37-49fd-aa49-938b674adae6")));
+        assert!(!list.is_allowed("jeb_", None));
+        assert!(!list.is_allowed("Dinnerbone", uuid("61699b2e-d327-4a01-9f1e-0ea8c3f06bc6")));
+    }
+
+    #[test]
+    fn whitelist_json_is_read_on_every_check() {
+        let path =
+            std::env::temp_dir().join(format!("mc-proxy-whitelist-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[{"uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5", "name": "Notch"}]"#,
+        )
+        .unwrap();
+        let list = allow_list(&[], vec![path.clone()]);
+        assert!(list.is_enabled());
+        assert!(list.is_allowed("NOTCH", None));
+        assert!(list.is_allowed("renamed", uuid("069a79f444e94726a5befca90e38aaf5")));
+        assert!(!list.is_allowed("jeb_", None));
+
+        std::fs::write(&path, r#"[{"name": "jeb_"}]"#).unwrap();
+        assert!(list.is_allowed("jeb_", None));
+        // A broken or missing file allows nobody
+        std::fs::write(&path, "not json").unwrap();
+        assert!(!list.is_allowed("jeb_", None));
+        std::fs::remove_file(&path).unwrap();
+        assert!(!list.is_allowed("jeb_", None));
+    }
+}
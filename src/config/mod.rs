@@ -0,0 +1,997 @@
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde_derive::Deserialize;
+
+use crate::{packets::ReadLimits, template, versions, ProtocolState};
+
+/// The whole proxy configuration, usually read from `mc-proxy.toml`.
+///
+/// Every section has defaults, so an empty file (or no file at all) is a valid config.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listeners: Vec<ListenerConfig>,
+    pub backends: Vec<BackendConfig>,
+    pub routing: RoutingConfig,
+    pub versions: VersionsConfig,
+    pub lifecycle: LifecycleConfig,
+    pub console: ConsoleConfig,
+    pub limbo: LimboConfig,
+    pub status: StatusConfig,
+    pub messages: MessagesConfig,
+    pub proxy: ProxyConfig,
+    pub timeouts: TimeoutsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub metrics: MetricsConfig,
+    pub admin: AdminConfig,
+    pub control: ControlConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Addr to bind to
+    pub bind: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    /// Unique, names the backend in logs, the admin api, `mc-proxy ctl` and the saved status
+    pub name: String,
+    /// The hostnames from the client handshake routed to this backend.
+    /// `*.example.com` matches every subdomain, `*` matches everything else.
+    pub hostnames: Vec<String>,
+    /// The address the minecraft server is running on
+    pub address: String,
+    /// Passed to `bash` to start the minecraft server
+    pub start_command: String,
+    /// Overrides `messages.offline_motd` for this backend
+    pub offline_motd: Option<String>,
+    /// Usernames or uuids allowed to start the server by joining,
+    /// together with `start_allow_files`. Both empty means everybody can.
+    pub start_allow: Vec<String>,
+    /// `whitelist.json` / `ops.json` files of the server, their players can start it too
+    pub start_allow_files: Vec<PathBuf>,
+    /// The directory of the server, its `server.properties` and `server-icon.png`
+    /// fill the status until the server answered one itself
+    pub server_dir: Option<PathBuf>,
+    /// Which joins this backend gets, two backends can share a hostname
+    /// if one takes the fresh joins and the other the transferred ones
+    pub joins: JoinPolicy,
+    /// Pass transferred logins on as transfers instead of plain logins,
+    /// the server needs `accepts-transfers=true` then
+    pub forward_transfers: bool,
+}
+
+/// Transferred joins come from a 1.20.5+ Transfer packet (the limbo sends those too),
+/// fresh ones are everything else, status pings included.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinPolicy {
+    #[default]
+    All,
+    Fresh,
+    Transferred,
+}
+
+impl JoinPolicy {
+    pub fn takes(self, transferred: bool) -> bool {
+        match self {
+            JoinPolicy::All => true,
+            JoinPolicy::Fresh => !transferred,
+            JoinPolicy::Transferred => transferred,
+        }
+    }
+    fn overlaps(self, other: JoinPolicy) -> bool {
+        self.takes(false) && other.takes(false) || self.takes(true) && other.takes(true)
+    }
+}
+
+/// What clients get when their hostname matches no backend.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingConfig {
+    /// Status description for unknown hostnames
+    pub unknown_motd: String,
+    /// Login disconnect for unknown hostnames
+    pub unknown_disconnect: String,
+}
+
+/// The client versions let through, as release names like `1.20.5`.
+/// Clients outside get `messages.unsupported_motd` and `messages.disconnect_unsupported_version`
+/// without a server being touched. Every version is let through when both are left out.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct VersionsConfig {
+    /// The oldest supported release
+    pub min: Option<String>,
+    /// The newest supported release, releases with the same protocol are supported too
+    pub max: Option<String>,
+}
+
+/// All values are in seconds.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LifecycleConfig {
+    /// How often the running server gets polled for online players
+    pub poll_interval: u64,
+    /// How long the server has to be empty before it gets stopped
+    pub idle_timeout: u64,
+    /// How long to wait after starting the server before the idle timer starts counting
+    pub grace_period: u64,
+}
+
+/// What the proxy looks for in the console output of the server.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsoleConfig {
+    /// A line matching this means the server is ready for players
+    pub ready_pattern: String,
+    /// Lines matching this report start progress, the `percent` group is used if there is one
+    pub progress_pattern: String,
+    /// How many of the last lines are kept for `ctl logs` and the admin api
+    pub history_lines: usize,
+    /// Print the console output of the server to the proxy's stdout too
+    pub mirror: bool,
+}
+
+/// 1.20.5 - 1.21.8 clients that start the server are held in an empty world
+/// instead of being disconnected, and transferred once it accepts connections.
+/// Other clients always get the login disconnects.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimboConfig {
+    pub enabled: bool,
+    /// Seconds to wait for the server before giving up on it
+    pub max_wait: u64,
+    /// Seconds between keep alives, the client gives up after 30 seconds without one
+    pub keep_alive_interval: u64,
+    /// `host:port` the players get transferred to, the address they joined with when left out
+    pub transfer_to: Option<String>,
+}
+
+/// How status pings of a running server are answered.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusConfig {
+    /// Seconds a polled status is answered from the cache without asking the server,
+    /// 0 always asks it. The polling refreshes it every `lifecycle.poll_interval`.
+    pub cache_ttl: u64,
+    /// Where the last status of every backend is saved (as `<name>.json`),
+    /// its favicon, version and max players are shown while the server sleeps
+    pub save_dir: Option<PathBuf>,
+}
+
+/// Every message is a template, see `template::Vars` for the syntax:
+/// - `{rev}`: the commit hash of the proxy
+/// - `{hostname}`: the address the client connected to
+/// - `{player}`: the username, in the login disconnects
+/// - `{state}`: stopped, starting, ready, stopping or crashed
+/// - `{players}`, `{max}`, `{version}`: from the last status of the server
+/// - `{uptime}`, `{idle_remaining}`: how long the server runs, and has left while empty
+/// - `{progress}`, `{elapsed}`, `{eta}`: while it's starting
+/// - `{motd}`: from `server.properties` in `server_dir`
+/// - `{client_version}`: the release of the client, like `1.21`
+/// - `{supported}`: the releases `[versions]` lets through, like `1.20.5 - 1.21.1`
+///
+/// Placeholders that aren't known at the time render empty,
+/// `{?eta}...{/eta}` shows its part only when `{eta}` is known, `{!eta}...{/eta}` when it isn't.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessagesConfig {
+    /// Status description while the server is not running
+    pub offline_motd: String,
+    /// Status description while the server is starting
+    pub starting_motd: String,
+    /// Shown instead of the ping bars while the server is starting,
+    /// leave it empty to show the ping bars
+    pub starting_version: String,
+    /// Status description while the server is shutting down
+    pub stopping_motd: String,
+    /// Status description after the server exited with an error
+    pub crashed_motd: String,
+    /// Appended to the status description of the running server
+    pub online_motd_suffix: String,
+    /// Login disconnect when the join started the server
+    pub disconnect_starting: String,
+    /// Login disconnect when the server was already starting
+    pub disconnect_already_starting: String,
+    /// Login disconnect when the server is shutting down, it starts again once it's down
+    pub disconnect_stopping: String,
+    /// Login disconnect when the player isn't allowed to start the server
+    pub disconnect_not_allowed: String,
+    /// Disconnect for players in the limbo when the server didn't come up in time (or exited)
+    pub disconnect_limbo_failed: String,
+    /// Disconnect for players in the limbo when they get kicked or the proxy shuts down
+    pub disconnect_kicked: String,
+    /// Status description for rate limited status pings
+    pub rate_limited_motd: String,
+    /// Login disconnect for connections over `connections_per_ip` or `max_sessions`
+    pub disconnect_rate_limited: String,
+    /// Status description for clients outside `[versions]`
+    pub unsupported_motd: String,
+    /// Login disconnect for clients outside `[versions]`
+    pub disconnect_unsupported_version: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Max amount of bytes moved by a single `splice` call
+    pub buffer_size: usize,
+    /// Longer packets are dropped before the proxy reads them, only checked before splicing starts
+    pub max_packet_size: usize,
+}
+
+/// How long a single packet read may take in each protocol state, in seconds.
+/// A client that stays silent longer gets disconnected.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    pub handshake: u64,
+    pub status: u64,
+    pub login: u64,
+}
+
+/// Every limit is off when left out.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// New connections per client ip, connections over it never get a session
+    pub connections_per_ip: Option<BucketConfig>,
+    /// Status pings per client ip, on top of `connections_per_ip`
+    pub status_per_ip: Option<BucketConfig>,
+    /// Max concurrent sessions over all listeners, connections over it never get a session
+    pub max_sessions: Option<usize>,
+    /// What over the limit status pings get
+    pub status_over_limit: OverLimitStatus,
+}
+
+/// A token bucket: `burst` tokens at most, refilled by `per_minute` tokens every minute.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverLimitStatus {
+    /// Answer with `messages.rate_limited_motd`, never touching the backend
+    #[default]
+    Canned,
+    /// Close the connection without a reply
+    Drop,
+}
+
+/// The prometheus endpoint, off unless `bind` is set.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Addr to serve `/metrics` on
+    pub bind: Option<String>,
+}
+
+/// The HTTP admin API, off unless `bind` is set.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Addr to serve the API on
+    pub bind: Option<String>,
+    /// Requests need `Authorization: Bearer <token>`, required when `bind` is set
+    pub token: String,
+}
+
+/// The unix control socket `mc-proxy ctl` talks to, off unless `socket` is set.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    /// Created when the proxy starts, only its owner can connect
+    pub socket: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listeners: vec![ListenerConfig {
+                bind: "127.0.0.1:7878".to_owned(),
+            }],
+            backends: vec![BackendConfig::default()],
+            routing: RoutingConfig::default(),
+            versions: VersionsConfig::default(),
+            lifecycle: LifecycleConfig::default(),
+            console: ConsoleConfig::default(),
+            limbo: LimboConfig::default(),
+            status: StatusConfig::default(),
+            messages: MessagesConfig::default(),
+            proxy: ProxyConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            metrics: MetricsConfig::default(),
+            admin: AdminConfig::default(),
+            control: ControlConfig::default(),
+        }
+    }
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig {
+            name: "default".to_owned(),
+            hostnames: vec!["*".to_owned()],
+            address: "127.0.0.1:25565".to_owned(),
+            start_command: "minecraft-server".to_owned(),
+            offline_motd: None,
+            start_allow: Vec::new(),
+            start_allow_files: Vec::new(),
+            server_dir: None,
+            joins: JoinPolicy::All,
+            forward_transfers: false,
+        }
+    }
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        RoutingConfig {
+            unknown_motd: "§cUnknown server address§r".to_owned(),
+            unknown_disconnect: "There is no server at this address".to_owned(),
+        }
+    }
+}
+
+impl Default for LifecycleConfig {
+    fn default() -> Self {
+        LifecycleConfig {
+            poll_interval: 10,
+            idle_timeout: 600,
+            grace_period: 600,
+        }
+    }
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        ConsoleConfig {
+            ready_pattern: r#"\]: Done \([0-9.]+s\)! For help, type "help""#.to_owned(),
+            progress_pattern: r"Preparing spawn area: (?P<percent>[0-9]+)%".to_owned(),
+            history_lines: 200,
+            mirror: true,
+        }
+    }
+}
+
+impl Default for LimboConfig {
+    fn default() -> Self {
+        LimboConfig {
+            enabled: true,
+            max_wait: 300,
+            keep_alive_interval: 10,
+            transfer_to: None,
+        }
+    }
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        StatusConfig {
+            cache_ttl: 15,
+            save_dir: None,
+        }
+    }
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        MessagesConfig {
+            offline_motd: "Server is currently §onot§r running. \n§aJoin to start it!§r - §dTami§r with §d<3§r §8(rev: {rev})§r".to_owned(),
+            starting_motd: "§aServer is starting...§r {progress} please wait{?eta}, eta {eta}{/eta}\n - §dTami§r with §d<3§r §8(rev: {rev})§r".to_owned(),
+            starting_version: "§6Starting {progress} {elapsed}".to_owned(),
+            stopping_motd: "§6Server is stopping...§r join to start it again\n - §dTami§r with §d<3§r §8(rev: {rev})§r".to_owned(),
+            crashed_motd: "Server §ccrashed§r. \n§aJoin to start it again!§r - §dTami§r with §d<3§r §8(rev: {rev})§r".to_owned(),
+            online_motd_suffix: "\n    §6Rusty proxy§r §d<3§r version §8(rev: {rev})".to_owned(),
+            disconnect_starting: "Okayyy_starting_it_now...§d<3§r".to_owned(),
+            disconnect_already_starting: "Starting...§d<3§r".to_owned(),
+            disconnect_stopping: "Stopping..._it_starts_again_right_after§d<3§r".to_owned(),
+            disconnect_not_allowed: "The server is asleep and {player} can't wake it up".to_owned(),
+            disconnect_limbo_failed: "§cThe server didn't come up ({state}), try again in a bit".to_owned(),
+            disconnect_kicked: "§cThe proxy closed the connection".to_owned(),
+            rate_limited_motd: "§cToo many requests, slow down§r".to_owned(),
+            disconnect_rate_limited: "Too many connections, try again in a minute".to_owned(),
+            unsupported_motd: "§cPlease use Minecraft {supported}§r".to_owned(),
+            disconnect_unsupported_version: "§cPlease use Minecraft {supported}{?client_version}, not {client_version}{/client_version}".to_owned(),
+        }
+    }
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            buffer_size: 1024 * 512,
+            // The biggest length a 3 byte `VarInt` can hold, vanilla's limit too
+            max_packet_size: 2097151,
+        }
+    }
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        TimeoutsConfig {
+            handshake: 5,
+            status: 10,
+            login: 10,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "can't read config file: {err}"),
+            ConfigError::Parse(err) => write!(f, "can't parse config file: {err}"),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {reason}"),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Config::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        toml::from_str(text).map_err(ConfigError::Parse)
+    }
+
+    /// Checks the values serde can't, call it after the cli overrides are applied.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.listeners.is_empty() {
+            return Err(ConfigError::Invalid(
+                "at least one listener is required".to_owned(),
+            ));
+        }
+        for listener in &self.listeners {
+            if listener.bind.parse::<SocketAddr>().is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "listener bind address `{}` is not an ip:port",
+                    listener.bind
+                )));
+            }
+        }
+        for (name, bind) in [("metrics", &self.metrics.bind), ("admin", &self.admin.bind)] {
+            if let Some(bind) = bind {
+                if bind.parse::<SocketAddr>().is_err() {
+                    return Err(ConfigError::Invalid(format!(
+                        "{name} bind address `{bind}` is not an ip:port"
+                    )));
+                }
+            }
+        }
+        if self.admin.bind.is_some() && self.admin.token.is_empty() {
+            return Err(ConfigError::Invalid(
+                "admin token is required when the admin api is enabled".to_owned(),
+            ));
+        }
+        if self.backends.is_empty() {
+            return Err(ConfigError::Invalid(
+                "at least one backend is required".to_owned(),
+            ));
+        }
+        let mut seen_hostnames: Vec<(String, JoinPolicy)> = Vec::new();
+        for (i, backend) in self.backends.iter().enumerate() {
+            backend.validate()?;
+            if self.backends[..i].iter().any(|x| x.name == backend.name) {
+                return Err(ConfigError::Invalid(format!(
+                    "backend name `{}` is used more than once",
+                    backend.name
+                )));
+            }
+            for hostname in &backend.hostnames {
+                let hostname = hostname.to_lowercase();
+                if seen_hostnames
+                    .iter()
+                    .any(|(seen, joins)| *seen == hostname && joins.overlaps(backend.joins))
+                {
+                    return Err(ConfigError::Invalid(format!(
+                        "hostname `{hostname}` is routed to more than one backend"
+                    )));
+                }
+                seen_hostnames.push((hostname, backend.joins));
+            }
+        }
+        for (name, pattern) in [
+            ("ready_pattern", &self.console.ready_pattern),
+            ("progress_pattern", &self.console.progress_pattern),
+        ] {
+            if let Err(err) = regex::Regex::new(pattern) {
+                return Err(ConfigError::Invalid(format!("console {name}: {err}")));
+            }
+        }
+        let routing = [
+            ("unknown_motd", &self.routing.unknown_motd),
+            ("unknown_disconnect", &self.routing.unknown_disconnect),
+        ];
+        let offline_motds = self
+            .backends
+            .iter()
+            .filter_map(|x| Some(("offline_motd", x.offline_motd.as_ref()?)));
+        for (name, message) in self
+            .messages
+            .templates()
+            .into_iter()
+            .chain(routing)
+            .chain(offline_motds)
+        {
+            if let Err(err) = template::check(message) {
+                return Err(ConfigError::Invalid(format!("message {name}: {err}")));
+            }
+        }
+        self.versions.validate()?;
+        if !(1..=20).contains(&self.limbo.keep_alive_interval) {
+            return Err(ConfigError::Invalid(
+                "limbo keep_alive_interval must be between 1 and 20 seconds".to_owned(),
+            ));
+        }
+        if let Some(transfer_to) = &self.limbo.transfer_to {
+            if LimboConfig::split_host_port(transfer_to).is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "limbo transfer_to `{transfer_to}` is not a host:port"
+                )));
+            }
+        }
+        if self.lifecycle.poll_interval == 0 {
+            return Err(ConfigError::Invalid(
+                "lifecycle poll_interval must be at least 1 second".to_owned(),
+            ));
+        }
+        for (name, bucket) in [
+            ("connections_per_ip", &self.rate_limit.connections_per_ip),
+            ("status_per_ip", &self.rate_limit.status_per_ip),
+        ] {
+            if bucket.as_ref().is_some_and(|x| x.burst == 0) {
+                return Err(ConfigError::Invalid(format!(
+                    "rate_limit {name}: burst can't be 0, leave the limit out to disable it"
+                )));
+            }
+        }
+        if self.timeouts.handshake == 0 || self.timeouts.status == 0 || self.timeouts.login == 0 {
+            return Err(ConfigError::Invalid(
+                "timeouts must be at least 1 second".to_owned(),
+            ));
+        }
+        if self.proxy.max_packet_size == 0 {
+            return Err(ConfigError::Invalid(
+                "proxy max_packet_size can't be 0".to_owned(),
+            ));
+        }
+        if self.proxy.buffer_size == 0 {
+            return Err(ConfigError::Invalid(
+                "proxy buffer_size can't be 0".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// The limits for reading a packet while the connection is in `state`.
+    pub fn read_limits(&self, state: ProtocolState) -> ReadLimits {
+        let timeout = match state {
+            ProtocolState::Handshaking => self.timeouts.handshake,
+            ProtocolState::Status => self.timeouts.status,
+            _ => self.timeouts.login,
+        };
+        ReadLimits {
+            timeout: Duration::from_secs(timeout),
+            max_size: self.proxy.max_packet_size,
+        }
+    }
+}
+
+impl BackendConfig {
+    /// Where `status.save_dir` keeps the last status of this backend.
+    pub fn status_file(&self, status: &StatusConfig) -> Option<PathBuf> {
+        let dir = status.save_dir.as_ref()?;
+        Some(dir.join(format!("{}.json", self.name.replace('/', "_"))))
+    }
+    fn validate(&self) -> Result<(), ConfigError> {
+        match self.address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => (),
+            _ => {
+                return Err(ConfigError::Invalid(format!(
+                    "backend `{}`: address `{}` is not a host:port",
+                    self.name, self.address
+                )))
+            }
+        }
+        if self.start_command.is_empty() {
+            return Err(ConfigError::Invalid(format!(
+                "backend `{}`: start_command can't be empty",
+                self.name
+            )));
+        }
+        if self.hostnames.is_empty() {
+            return Err(ConfigError::Invalid(format!(
+                "backend `{}`: no hostnames, it would never be used",
+                self.name
+            )));
+        }
+        for hostname in &self.hostnames {
+            let wildcard_ok = match hostname.strip_prefix('*') {
+                Some(rest) => rest.is_empty() || rest.starts_with('.') && !rest.contains('*'),
+                None => !hostname.contains('*'),
+            };
+            if hostname.is_empty() || !wildcard_ok {
+                return Err(ConfigError::Invalid(format!(
+                    "backend `{}`: hostname `{hostname}` must be `*`, `*.domain` or a plain hostname",
+                    self.name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VersionsConfig {
+    fn min_protocol(&self) -> Option<i32> {
+        versions::protocol(self.min.as_ref()?)
+    }
+    fn max_protocol(&self) -> Option<i32> {
+        versions::protocol(self.max.as_ref()?)
+    }
+    /// Compares the protocol numbers, so releases newer than the table still get past a `min`
+    /// without a `max`. Snapshots (`0x40000000` and up) count as newer than every release.
+    pub fn supports(&self, protocol_version: i32) -> bool {
+        self.min_protocol().is_none_or(|x| protocol_version >= x)
+            && self.max_protocol().is_none_or(|x| protocol_version <= x)
+    }
+    /// For `{supported}`: `1.20.5 - 1.21.1`, `1.20.5+` or `1.21.1 and older`,
+    /// empty without limits.
+    pub fn supported(&self) -> String {
+        let oldest = self.min_protocol().and_then(versions::name);
+        let newest = self.max_protocol().and_then(versions::last_name);
+        match (oldest, newest) {
+            (Some(oldest), Some(newest)) if oldest == newest => oldest.to_owned(),
+            (Some(oldest), Some(newest)) => format!("{oldest} - {newest}"),
+            (Some(oldest), None) => format!("{oldest}+"),
+            (None, Some(newest)) => format!("{newest} and older"),
+            (None, None) => String::new(),
+        }
+    }
+    /// The version a status for this client shows: its own if it's supported, otherwise
+    /// `{supported}` with the nearest supported protocol, so the client marks it incompatible.
+    pub fn status_version(&self, protocol_version: i32) -> (String, i32) {
+        if self.supports(protocol_version) {
+            let name = versions::name(protocol_version).unwrap_or("???");
+            return (name.to_owned(), protocol_version);
+        }
+        let nearest = match self.max_protocol() {
+            Some(max) if protocol_version > max => max,
+            _ => self.min_protocol().unwrap_or(protocol_version),
+        };
+        (self.supported(), nearest)
+    }
+    fn validate(&self) -> Result<(), ConfigError> {
+        for (name, release) in [("min", &self.min), ("max", &self.max)] {
+            if let Some(release) = release {
+                if versions::protocol(release).is_none() {
+                    return Err(ConfigError::Invalid(format!(
+                        "versions {name}: `{release}` is no release the proxy knows"
+                    )));
+                }
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_protocol(), self.max_protocol()) {
+            if min > max {
+                return Err(ConfigError::Invalid(
+                    "versions min is newer than max".to_owned(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LimboConfig {
+    pub fn split_host_port(address: &str) -> Option<(String, u16)> {
+        let (host, port) = address.rsplit_once(':')?;
+        if host.is_empty() {
+            return None;
+        }
+        Some((host.to_owned(), port.parse().ok()?))
+    }
+}
+
+impl MessagesConfig {
+    fn templates(&self) -> [(&str, &String); 16] {
+        [
+            ("offline_motd", &self.offline_motd),
+            ("starting_motd", &self.starting_motd),
+            ("starting_version", &self.starting_version),
+            ("stopping_motd", &self.stopping_motd),
+            ("crashed_motd", &self.crashed_motd),
+            ("online_motd_suffix", &self.online_motd_suffix),
+            ("disconnect_starting", &self.disconnect_starting),
+            (
+                "disconnect_already_starting",
+                &self.disconnect_already_starting,
+            ),
+            ("disconnect_stopping", &self.disconnect_stopping),
+            ("disconnect_not_allowed", &self.disconnect_not_allowed),
+            ("disconnect_limbo_failed", &self.disconnect_limbo_failed),
+            ("disconnect_kicked", &self.disconnect_kicked),
+            ("rate_limited_motd", &self.rate_limited_motd),
+            ("disconnect_rate_limited", &self.disconnect_rate_limited),
+            ("unsupported_motd", &self.unsupported_motd),
+            (
+                "disconnect_unsupported_version",
+                &self.disconnect_unsupported_version,
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_is_the_default() {
+        let example = Config::parse(include_str!("../../mc-proxy.example.toml")).unwrap();
+        example.validate().unwrap();
+        let default = Config::default();
+        assert_eq!(example.listeners[0].bind, default.listeners[0].bind);
+        assert_eq!(example.backends[0].address, default.backends[0].address);
+        assert_eq!(
+            example.lifecycle.idle_timeout,
+            default.lifecycle.idle_timeout
+        );
+        assert_eq!(example.messages.offline_motd, default.messages.offline_motd);
+        assert_eq!(example.proxy.buffer_size, default.proxy.buffer_size);
+    }
+
+    #[test]
+    fn missing_sections_are_defaults() {
+        let config = Config::parse("[lifecycle]\nidle_timeout = 60").unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.lifecycle.idle_timeout, 60);
+        assert_eq!(config.lifecycle.poll_interval, 10);
+        assert_eq!(config.listeners.len(), 1);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(matches!(
+            Config::parse("[[backends]]\nadress = \"127.0.0.1:25565\""),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            Config::parse("[backnd]"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_values() {
+        for text in [
+            "listeners = []",
+            "[[listeners]]\nbind = \"localhost:7878\"",
+            "backends = []",
+            "[[backends]]\naddress = \"127.0.0.1\"",
+            "[[backends]]\naddress = \":25565\"",
+            "[[backends]]\nstart_command = \"\"",
+            "[[backends]]\nhostnames = []",
+            "[[backends]]\nhostnames = [\"mc.*.com\"]",
+            "[[backends]]\nhostnames = [\"*example.com\"]",
+            "[[backends]]\nhostnames = [\"\"]",
+            "[lifecycle]\npoll_interval = 0",
+            "[proxy]\nbuffer_size = 0",
+            "[rate_limit]\nconnections_per_ip = { burst = 0, per_minute = 1 }",
+            "[timeouts]\nlogin = 0",
+            "[proxy]\nmax_packet_size = 0",
+            "[console]\nready_pattern = \"(\"",
+            "[limbo]\nkeep_alive_interval = 0",
+            "[limbo]\nkeep_alive_interval = 30",
+            "[limbo]\ntransfer_to = \"mc.example.com\"",
+            "[limbo]\ntransfer_to = \":25565\"",
+            "[limbo]\ntransfer_to = \"mc.example.com:99999\"",
+            "[messages]\nstarting_motd = \"{?eta}eta {eta}\"",
+            "[routing]\nunknown_motd = \"{/player}\"",
+            "[[backends]]\noffline_motd = \"{?state}{!motd}{/state}{/motd}\"",
+            "[versions]\nmin = \"1.22\"",
+            "[versions]\nmax = \"21w44a\"",
+            "[versions]\nmin = \"1.21\"\nmax = \"1.20.6\"",
+        ] {
+            let config = Config::parse(text).unwrap();
+            assert!(
+                matches!(config.validate(), Err(ConfigError::Invalid(_))),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_hostname_goes_to_one_backend() {
+        let config = Config::parse(
+            r#"
+            [[backends]]
+            name = "a"
+            hostnames = ["mc.example.com"]
+            [[backends]]
+            name = "b"
+            hostnames = ["*", "MC.example.com"]
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+
+        let two = |joins: &str| {
+            Config::parse(&format!(
+                "[[backends]]\nname = \"a\"\njoins = \"fresh\"\n\
+                 [[backends]]\nname = \"b\"\njoins = \"{joins}\""
+            ))
+            .unwrap()
+            .validate()
+        };
+        assert!(matches!(two("all"), Err(ConfigError::Invalid(_))));
+        assert!(matches!(two("fresh"), Err(ConfigError::Invalid(_))));
+        assert!(two("transferred").is_ok());
+    }
+
+    #[test]
+    fn backend_names_are_unique() {
+        let config = Config::parse(
+            "[[backends]]\nname = \"a\"\nhostnames = [\"a.example.com\"]\n\
+             [[backends]]\nname = \"a\"\nhostnames = [\"b.example.com\"]",
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        // Left out, both are `default`
+        let config = Config::parse(
+            "[[backends]]\nhostnames = [\"a.example.com\"]\n\
+             [[backends]]\nhostnames = [\"b.example.com\"]",
+        )
+        .unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn read_limits_per_state() {
+        let config = Config::parse(
+            "[timeouts]\nhandshake = 1\nstatus = 2\nlogin = 3\n[proxy]\nmax_packet_size = 100",
+        )
+        .unwrap();
+        for (state, seconds) in [
+            (ProtocolState::Handshaking, 1),
+            (ProtocolState::Status, 2),
+            (ProtocolState::Login, 3),
+            (ProtocolState::Transfer, 3),
+        ] {
+            let limits = config.read_limits(state);
+            assert_eq!(limits.timeout, Duration::from_secs(seconds));
+            assert_eq!(limits.max_size, 100);
+        }
+    }
+
+    #[test]
+    fn versions_range() {
+        let versions = |text: &str| Config::parse(text).unwrap().versions;
+        let all = versions("");
+        assert!(all.supports(4) && all.supports(772) && all.supports(0x40000100));
+        assert_eq!(all.supported(), "");
+
+        let range = versions("[versions]\nmin = \"1.20.6\"\nmax = \"1.21\"");
+        assert!(!range.supports(765));
+        assert!(range.supports(766) && range.supports(767));
+        assert!(!range.supports(768));
+        // Snapshots are never let through once there is a limit
+        assert!(!range.supports(0x40000100));
+        assert_eq!(range.supported(), "1.20.5 - 1.21.1");
+        assert_eq!(range.status_version(767), ("1.21".to_owned(), 767));
+        assert_eq!(
+            range.status_version(765),
+            ("1.20.5 - 1.21.1".to_owned(), 766)
+        );
+        assert_eq!(
+            range.status_version(772),
+            ("1.20.5 - 1.21.1".to_owned(), 767)
+        );
+
+        assert_eq!(
+            versions("[versions]\nmin = \"1.21.4\"").supported(),
+            "1.21.4+"
+        );
+        assert_eq!(
+            versions("[versions]\nmax = \"1.8\"").supported(),
+            "1.8.9 and older"
+        );
+        let one = versions("[versions]\nmin = \"1.21.4\"\nmax = \"1.21.4\"");
+        assert_eq!(one.supported(), "1.21.4");
+        assert_eq!(one.status_version(4), ("1.21.4".to_owned(), 769));
+    }
+
+    #[test]
+    fn limbo_transfer_to() {
+        assert_eq!(
+            LimboConfig::split_host_port("mc.example.com:25566"),
+            Some(("mc.example.com".to_owned(), 25566))
+        );
+        assert_eq!(
+            LimboConfig::split_host_port("[::1]:25565"),
+            Some(("[::1]".to_owned(), 25565))
+        );
+    }
+
+    fn versions(min: Option<&str>, max: Option<&str>) -> VersionsConfig {
+        VersionsConfig {
+            min: min.map(str::to_owned),
+            max: max.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn versions_without_limits_let_everything_through() {
+        let config = versions(None, None);
+        assert!(config.supports(4));
+        assert!(config.supports(99999));
+        assert_eq!(config.supported(), "");
+    }
+
+    #[test]
+    fn versions_min_lets_newer_unknown_releases_through() {
+        let config = versions(Some("1.20.5"), None);
+        assert!(config.supports(766));
+        assert!(config.supports(772));
+        // Newer than the table
+        assert!(config.supports(800));
+        assert!(!config.supports(765));
+        assert_eq!(config.supported(), "1.20.5+");
+        assert_eq!(config.status_version(765), ("1.20.5+".to_owned(), 766));
+        assert_eq!(config.status_version(767), ("1.21".to_owned(), 767));
+    }
+
+    #[test]
+    fn versions_min_and_max() {
+        let config = versions(Some("1.21"), Some("1.21"));
+        assert!(config.supports(767));
+        assert!(!config.supports(766));
+        assert!(!config.supports(768));
+        assert!(!config.supports(0x40000100));
+        assert_eq!(config.supported(), "1.21 - 1.21.1");
+        assert_eq!(
+            config.status_version(760),
+            ("1.21 - 1.21.1".to_owned(), 767)
+        );
+        assert_eq!(
+            config.status_version(800),
+            ("1.21 - 1.21.1".to_owned(), 767)
+        );
+
+        let config = versions(None, Some("1.19.4"));
+        assert!(config.supports(47));
+        assert!(!config.supports(763));
+        assert_eq!(config.supported(), "1.19.4 and older");
+    }
+
+    #[test]
+    fn versions_validation() {
+        assert!(versions(Some("1.21"), Some("1.21.1")).validate().is_ok());
+        assert!(versions(Some("1.99"), None).validate().is_err());
+        assert!(versions(Some("1.21.4"), Some("1.21")).validate().is_err());
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    fs::DirBuilder,
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+    path::Path,
+    sync::Arc,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    error::{ProxyError, ProxyResult},
+    router::{Backend, BackendAction},
+    Proxy,
+};
+
+/// One line of JSON per request, answered by one line of [`ControlResponse`].
+/// `backend` can be left out when there is only one.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub enum ControlRequest {
+    Status {
+        backend: Option<String>,
+    },
+    Start {
+        backend: Option<String>,
+    },
+    Stop {
+        backend: Option<String>,
+    },
+    Logs {
+        backend: Option<String>,
+    },
+    Cmd {
+        backend: Option<String>,
+        text: String,
+    },
+    Sessions,
+    Kick {
+        id: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl ControlResponse {
+    fn data<T: serde::Serialize>(data: T) -> ControlResponse {
+        match serde_json::to_value(data) {
+            Ok(data) => ControlResponse {
+                ok: true,
+                error: None,
+                data: Some(data),
+            },
+            Err(err) => ControlResponse::error(err.to_string()),
+        }
+    }
+
+    fn done() -> ControlResponse {
+        ControlResponse {
+            ok: true,
+            error: None,
+            data: None,
+        }
+    }
+
+    fn error(error: String) -> ControlResponse {
+        ControlResponse {
+            ok: false,
+            error: Some(error),
+            data: None,
+        }
+    }
+}
+
+/// Binds the control socket, replacing a stale one from an earlier run.
+/// Only the owner can connect, the file permissions are the access control.
+///
+/// The socket is created with the umask permissions, so it's bound in a private directory
+/// and only moved to `path` once it's narrowed down: nobody else can ever connect to it.
+pub fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file name"))?;
+    let private_dir = path.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    DirBuilder::new().mode(0o700).create(&private_dir)?;
+    let private_path = private_dir.join("socket");
+    let listener = UnixListener::bind(&private_path).and_then(|listener| {
+        std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(0o600))?;
+        // Replaces a stale socket from an earlier run
+        std::fs::rename(&private_path, path)?;
+        Ok(listener)
+    });
+    std::fs::remove_file(&private_path).ok();
+    std::fs::remove_dir(&private_dir)?;
+    listener
+}
+
+pub async fn serve(listener: UnixListener, proxy: Arc<Proxy>, shutdown: CancellationToken) {
+    loop {
+        let stream = tokio::select! {
+            _ = shutdown.cancelled() => return,
+            res = listener.accept() => match res {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    eprintln!("CONTROL: Error encountered while resolving listener connection: {err}");
+                    continue;
+                }
+            },
+        };
+        let proxy = proxy.clone();
+        tokio::spawn(async move {
+            match answer(stream, &proxy).await {
+                Ok(()) | Err(ProxyError::Closed) => (),
+                Err(err) => println!("CONTROL: {err}"),
+            }
+        });
+    }
+}
+
+async fn answer(stream: UnixStream, proxy: &Proxy) -> ProxyResult<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => handle(request, proxy),
+            Err(err) => ControlResponse::error(format!("bad request: {err}")),
+        };
+        let mut response =
+            serde_json::to_vec(&response).map_err(|err| ProxyError::Malformed(err.to_string()))?;
+        response.push(b'\n');
+        write.write_all(&response).await?;
+    }
+    Ok(())
+}
+
+fn handle(request: ControlRequest, proxy: &Proxy) -> ControlResponse {
+    let (backend, action) = match request {
+        ControlRequest::Status { backend: None } => {
+            let backends: Vec<_> = proxy.router.backends().iter().map(|x| x.status()).collect();
+            return ControlResponse::data(backends);
+        }
+        ControlRequest::Status { backend } => {
+            return match find_backend(proxy, backend) {
+                Ok(backend) => ControlResponse::data(backend.status()),
+                Err(err) => ControlResponse::error(err),
+            };
+        }
+        ControlRequest::Logs { backend } => {
+            return match find_backend(proxy, backend) {
+                Ok(backend) => {
+                    ControlResponse::data(backend.handler.lock().unwrap().console_history())
+                }
+                Err(err) => ControlResponse::error(err),
+            };
+        }
+        ControlRequest::Sessions => return ControlResponse::data(proxy.sessions.list()),
+        ControlRequest::Kick { id } => {
+            return if proxy.sessions.kick(id) {
+                println!("CONTROL: kicking session {id}");
+                ControlResponse::done()
+            } else {
+                ControlResponse::error(format!("no session with id {id}"))
+            };
+        }
+        ControlRequest::Start { backend } => (backend, BackendAction::Start),
+        ControlRequest::Stop { backend } => (backend, BackendAction::Stop),
+        ControlRequest::Cmd { backend, text } => (backend, BackendAction::Command(text)),
+    };
+    let backend = match find_backend(proxy, backend) {
+        Ok(x) => x,
+        Err(err) => return ControlResponse::error(err),
+    };
+    match backend.apply(action, "CONTROL") {
+        Ok(()) => ControlResponse::done(),
+        Err(err) => ControlResponse::error(err.to_string()),
+    }
+}
+
+fn find_backend(proxy: &Proxy, name: Option<String>) -> Result<Arc<Backend>, String> {
+    match name {
+        Some(name) => proxy
+            .router
+            .backend(&name)
+            .ok_or_else(|| format!("no backend named {name}")),
+        None => match proxy.router.backends() {
+            [backend] => Ok(backend.clone()),
+            _ => Err("there is more than one backend, pick one with --backend".to_owned()),
+        },
+    }
+}
+
+/// The `mc-proxy ctl` side: sends one request and waits for its answer.
+pub async fn request(path: &Path, request: ControlRequest) -> Result<ControlResponse, String> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|err| format!("can't connect to {}: {err}", path.display()))?;
+    let (read, mut write) = stream.into_split();
+    let mut line = serde_json::to_vec(&request).map_err(|err| err.to_string())?;
+    line.push(b'\n');
+    write
+        .write_all(&line)
+        .await
+        .map_err(|err| format!("can't send the request: {err}"))?;
+    let answer = BufReader::new(read)
+        .lines()
+        .next_line()
+        .await
+        .map_err(|err| format!("can't read the answer: {err}"))?
+        .ok_or_else(|| "the proxy closed the socket without answering".to_owned())?;
+    serde_json::from_str(&answer).map_err(|err| format!("bad answer from the proxy: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config, metrics::Metrics, ratelimit::RateLimiter, router::Router,
+        sessions::SessionTable,
+    };
+
+    fn proxy(config: &str) -> Arc<Proxy> {
+        let config = Config::parse(config).unwrap();
+        Arc::new(Proxy {
+            router: Router::create(&config),
+            rate_limiter: RateLimiter::create(&config.rate_limit),
+            metrics: Metrics::create(),
+            sessions: SessionTable::create(),
+            config,
+        })
+    }
+
+    fn error(response: ControlResponse) -> String {
+        assert!(!response.ok);
+        response.error.unwrap()
+    }
+
+    #[test]
+    fn backend_can_be_left_out_with_one() {
+        let proxy = proxy("");
+        let response = handle(ControlRequest::Status { backend: None }, &proxy);
+        assert!(response.ok);
+        assert_eq!(response.data.unwrap()[0]["name"], "default");
+
+        let response = handle(ControlRequest::Stop { backend: None }, &proxy);
+        assert!(error(response).contains("not running"));
+        let request = ControlRequest::Start {
+            backend: Some("creative".to_owned()),
+        };
+        assert_eq!(error(handle(request, &proxy)), "no backend named creative");
+    }
+
+    #[test]
+    fn backend_has_to_be_named_with_more() {
+        let proxy = proxy(
+            "[[backends]]\nname = \"a\"\nhostnames = [\"a\"]\n\
+             [[backends]]\nname = \"b\"\nhostnames = [\"b\"]",
+        );
+        let request = ControlRequest::Cmd {
+            backend: None,
+            text: "say hi".to_owned(),
+        };
+        assert!(error(handle(request, &proxy)).starts_with("there is more than one backend"));
+        let request = ControlRequest::Status {
+            backend: Some("b".to_owned()),
+        };
+        assert_eq!(handle(request, &proxy).data.unwrap()["name"], "b");
+    }
+
+    #[test]
+    fn sessions_and_kick() {
+        let proxy = proxy("");
+        let cancel = CancellationToken::new();
+        let session = proxy.sessions.register(
+            "127.0.0.1:50000".parse().unwrap(),
+            cancel.clone(),
+            proxy.metrics.connected(),
+        );
+        let response = handle(ControlRequest::Sessions, &proxy);
+        assert_eq!(response.data.unwrap()[0]["id"], session.id());
+
+        assert!(handle(ControlRequest::Kick { id: session.id() }, &proxy).ok);
+        assert!(cancel.is_cancelled());
+        let response = handle(ControlRequest::Kick { id: 1000 }, &proxy);
+        assert_eq!(error(response), "no session with id 1000");
+    }
+
+    #[tokio::test]
+    async fn over_the_socket() {
+        let path = std::env::temp_dir().join(format!("mc-proxy-test-{}.sock", std::process::id()));
+        let listener = bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve(listener, proxy(""), shutdown.clone()));
+
+        let response = request(&path, ControlRequest::Sessions).await.unwrap();
+        assert!(response.ok);
+        assert_eq!(response.data.unwrap(), serde_json::json!([]));
+
+        shutdown.cancel();
+        server.await.unwrap();
+        // The stale socket of the last run is replaced, the private directory is gone again
+        drop(bind(&path).unwrap());
+        let private_dir = path.with_file_name(format!(
+            ".{}.{}",
+            path.file_name().unwrap().to_string_lossy(),
+            std::process::id()
+        ));
+        assert!(!private_dir.exists());
+        std::fs::remove_file(&path).unwrap();
+        assert!(request(&path, ControlRequest::Sessions).await.is_err());
+    }
+}
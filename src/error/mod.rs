@@ -0,0 +1,106 @@
+use std::{fmt::Display, io, time::Duration};
+
+use crate::ProtocolState;
+
+/// Everything that can end a connection (or a request to the managed server) early.
+#[derive(Debug)]
+pub enum ProxyError {
+    Io(io::Error),
+    /// The peer closed the connection
+    Closed,
+    /// A read didn't finish before its deadline
+    Timeout(Duration),
+    /// Bytes that don't decode into what they should
+    Malformed(String),
+    /// A well formed packet that has no place in `state`
+    UnexpectedPacket {
+        state: ProtocolState,
+        id: i32,
+    },
+    /// A packet the client's protocol version doesn't have
+    UnsupportedVersion {
+        what: &'static str,
+        protocol_version: i32,
+    },
+    /// The minecraft server can't be reached
+    BackendUnavailable(String),
+    /// Starting, stopping or talking to the server process failed
+    ServerProcess(String),
+}
+
+pub type ProxyResult<T> = Result<T, ProxyError>;
+
+impl Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::Io(err) => write!(f, "io error: {err}"),
+            ProxyError::Closed => write!(f, "connection closed"),
+            ProxyError::Timeout(after) => write!(f, "timed out after {after:?}"),
+            ProxyError::Malformed(what) => write!(f, "malformed data: {what}"),
+            ProxyError::UnexpectedPacket { state, id } => {
+                write!(f, "unexpected packet {id:#x} in state {state}")
+            }
+            ProxyError::UnsupportedVersion {
+                what,
+                protocol_version,
+            } => write!(f, "{what} doesn't exist in protocol {protocol_version}"),
+            ProxyError::BackendUnavailable(reason) => write!(f, "backend unavailable: {reason}"),
+            ProxyError::ServerProcess(reason) => write!(f, "server process: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+impl From<io::Error> for ProxyError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe => ProxyError::Closed,
+            _ => ProxyError::Io(err),
+        }
+    }
+}
+
+impl ProxyError {
+    pub fn malformed(what: &str) -> ProxyError {
+        ProxyError::Malformed(what.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_closed_peer_is_not_an_io_error() {
+        for kind in [
+            io::ErrorKind::UnexpectedEof,
+            io::ErrorKind::ConnectionReset,
+            io::ErrorKind::BrokenPipe,
+        ] {
+            assert!(matches!(
+                ProxyError::from(io::Error::from(kind)),
+                ProxyError::Closed
+            ));
+        }
+        assert!(matches!(
+            ProxyError::from(io::Error::from(io::ErrorKind::PermissionDenied)),
+            ProxyError::Io(_)
+        ));
+    }
+
+    #[test]
+    fn display() {
+        let err = ProxyError::UnexpectedPacket {
+            state: ProtocolState::Status,
+            id: 0x7a,
+        };
+        assert_eq!(err.to_string(), "unexpected packet 0x7a in state Status");
+        assert_eq!(
+            ProxyError::malformed("VarInt is too big").to_string(),
+            "malformed data: VarInt is too big"
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::{future::Future, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{ProxyError, ProxyResult};
+
+// Just enough HTTP/1.1 for scrapers and curl: one request per connection, no chunked bodies.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+pub struct Request {
+    pub method: String,
+    /// Without the query string
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    async fn read(stream: &mut TcpStream) -> ProxyResult<Request> {
+        tokio::time::timeout(READ_TIMEOUT, Request::parse(stream))
+            .await
+            .map_err(|_| ProxyError::Timeout(READ_TIMEOUT))?
+    }
+
+    async fn parse(stream: &mut TcpStream) -> ProxyResult<Request> {
+        let mut reader = BufReader::new(stream).take(MAX_HEAD_SIZE as u64);
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+                (method.to_owned(), target.to_owned())
+            }
+            _ => return Err(ProxyError::malformed("http request line")),
+        };
+        let path = target.split('?').next().unwrap_or_default().to_owned();
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(ProxyError::malformed("http head ended early"));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| ProxyError::malformed("http header"))?;
+            headers.push((name.trim().to_lowercase(), value.trim().to_owned()));
+        }
+
+        let mut request = Request {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+        let length = match request.header("content-length") {
+            Some(x) => x
+                .parse::<usize>()
+                .map_err(|_| ProxyError::malformed("http content-length"))?,
+            None => 0,
+        };
+        if length > MAX_BODY_SIZE {
+            return Err(ProxyError::Malformed(format!(
+                "http body of {length} bytes is too big"
+            )));
+        }
+        let mut reader = reader.into_inner();
+        request.body.resize(length, 0);
+        reader.read_exact(&mut request.body).await?;
+        Ok(request)
+    }
+
+    /// `name` has to be lowercase
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn create(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response::create(status, "text/plain; charset=utf-8", body)
+    }
+
+    async fn send(&self, stream: &mut TcpStream) -> ProxyResult<()> {
+        let reason = match self.status {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            _ => "Internal Server Error",
+        };
+        let head = format!(
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Answers every connection on `listener` with `handler` until `shutdown` is cancelled.
+pub async fn serve<F, Fut>(
+    listener: TcpListener,
+    shutdown: CancellationToken,
+    name: &'static str,
+    handler: F,
+) where
+    F: Fn(Request) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response> + Send,
+{
+    loop {
+        let (mut stream, addr) = tokio::select! {
+            _ = shutdown.cancelled() => return,
+            res = listener.accept() => match res {
+                Ok(x) => x,
+                Err(err) => {
+                    eprintln!("{name}: Error encountered while resolving listener connection: {err}");
+                    continue;
+                }
+            },
+        };
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(err) = answer(&mut stream, handler).await {
+                println!("{name}: {addr} -- {err}");
+            }
+            stream.shutdown().await.ok();
+        });
+    }
+}
+
+async fn answer<F, Fut>(stream: &mut TcpStream, handler: F) -> ProxyResult<()>
+where
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = Response>,
+{
+    let request = match Request::read(stream).await {
+        Ok(x) => x,
+        Err(ProxyError::Closed) => return Ok(()),
+        Err(err) => {
+            Response::text(400, "bad request\n").send(stream).await.ok();
+            return Err(err);
+        }
+    };
+    handler(request).await.send(stream).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `request` to a server answering with the method, path and body it got.
+    async fn exchange(request: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve(
+            listener,
+            shutdown.clone(),
+            "TEST",
+            |request: Request| async move {
+                let token = request.header("x-token").unwrap_or("-").to_owned();
+                Response::text(
+                    200,
+                    format!(
+                        "{} {} {token} {}",
+                        request.method,
+                        request.path,
+                        String::from_utf8_lossy(&request.body)
+                    ),
+                )
+            },
+        ));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        shutdown.cancel();
+        server.await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn request_with_headers_and_body() {
+        let response =
+            exchange(b"POST /start?now HTTP/1.1\r\nX-Token: abc\r\nContent-Length: 4\r\n\r\nbody")
+                .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 20\r\n"));
+        assert!(response.ends_with("\r\n\r\nPOST /start abc body"));
+    }
+
+    #[tokio::test]
+    async fn bad_requests() {
+        for request in [
+            &b"GET /metrics\r\n\r\n"[..],
+            b"GET /metrics HTTP/1.1\r\nno colon\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: 1048576\r\n\r\n",
+        ] {
+            let response = exchange(request).await;
+            assert!(
+                response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{response}"
+            );
+        }
+    }
+}
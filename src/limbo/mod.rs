@@ -0,0 +1,590 @@
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+
+use crate::{
+    config::LimboConfig,
+    error::{ProxyError, ProxyResult},
+    mincraft_server::ServerState,
+    packets::{
+        clientbound::{
+            configuration::{FinishConfiguration, RegistryData, RegistryEntry},
+            login::LoginSuccess,
+            play::{
+                Disconnect, GameEvent, KeepAlive, Login, PlayerPosition, SetDefaultSpawnPosition,
+                Transfer,
+            },
+        },
+        registry::Registered,
+        serverbound::{
+            configuration::{AcknowledgeFinishConfiguration, PluginMessage},
+            login::LoginAcknowledged,
+        },
+        Packet, ReadLimits,
+    },
+    router::Backend,
+    sessions::SessionHandle,
+    text::TextComponent,
+    types::{nbt::Nbt, Codec, Identifier, Position, VarInt, VarString},
+    ClientConnectionState, ProtocolState, Proxy,
+};
+
+/// 1.20.5, the first version with the Transfer packet.
+const MIN_PROTOCOL: i32 = 766;
+/// 1.21.7 - 1.21.8, the last version the Play packets of the limbo are known for.
+const MAX_PROTOCOL: i32 = 772;
+
+/// How long the client gets to close the connection itself after a Transfer or Disconnect.
+const CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// How long the server gets to accept a connection before the limbo transfers anybody to it.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Above the 256 blocks of the limbo world, so the client doesn't wait for chunks.
+const SPAWN_Y: f64 = 300.0;
+
+/// Whether the limbo speaks `protocol_version`, the other clients get the login disconnects.
+pub fn supports(protocol_version: i32) -> bool {
+    (MIN_PROTOCOL..=MAX_PROTOCOL).contains(&protocol_version)
+}
+
+/// Finishes the login for the client (offline, the proxy has no keys) and the configuration,
+/// then keeps it in an empty world with keep alives until the backend is ready and accepts
+/// connections, and transfers it there.
+pub async fn hold(
+    client_stream: &mut TcpStream,
+    server_state: &ClientConnectionState,
+    backend: &Backend,
+    proxy: &Proxy,
+    session: &mut SessionHandle,
+) -> ProxyResult<()> {
+    let config = &proxy.config;
+    let uuid = server_state
+        .uuid
+        .ok_or_else(|| ProxyError::malformed("1.20.5+ Login Start without a uuid"))?;
+    let protocol_version = server_state.protocol_version;
+    LoginSuccess::create(uuid, server_state.player_name())
+        .send(client_stream, protocol_version)
+        .await?;
+    Packet::read(client_stream, config.read_limits(ProtocolState::Login))
+        .await?
+        .expect::<LoginAcknowledged>(protocol_version)?;
+    session.set_state(ProtocolState::Configuration);
+    configure(client_stream, server_state, proxy).await?;
+    session.set_state(ProtocolState::Play);
+    join(client_stream, protocol_version).await?;
+    println!(
+        "PROXY: {} is waiting in limbo for {}",
+        server_state.player(),
+        backend.config.name
+    );
+
+    let (host, port) = match &config.limbo.transfer_to {
+        Some(transfer_to) => LimboConfig::split_host_port(transfer_to)
+            .ok_or_else(|| ProxyError::malformed("limbo transfer_to"))?,
+        // Forge appends `\0FML\0`, the client has to connect to the plain hostname
+        None => (
+            server_state
+                .server_address
+                .split('\0')
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+            server_state.server_port,
+        ),
+    };
+    let keep_alive_interval = Duration::from_secs(config.limbo.keep_alive_interval);
+    let max_wait = Duration::from_secs(config.limbo.max_wait);
+    // A client that stops answering the keep alives is gone
+    let limits = ReadLimits {
+        timeout: keep_alive_interval * 3,
+        max_size: config.proxy.max_packet_size,
+    };
+
+    let (mut read, mut write) = client_stream.split();
+    // Keep alive answers, teleport confirmations and movement: nothing the limbo needs
+    let drain = async {
+        loop {
+            if let Err(err) = Packet::read(&mut read, limits).await {
+                return err;
+            }
+        }
+    };
+    tokio::pin!(drain);
+    let since = Instant::now();
+    let mut keep_alive = tokio::time::interval(keep_alive_interval);
+    let mut check = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            err = &mut drain => return Err(err),
+            _ = session.cancelled() => {
+                println!(
+                    "PROXY: {} was kicked from limbo; Disconnecting...",
+                    server_state.player()
+                );
+                let reason = server_state
+                    .template_vars(Some(backend))
+                    .render(&config.messages.disconnect_kicked);
+                Disconnect::set_reason(TextComponent::from_legacy(&reason))
+                    .send(&mut write, protocol_version)
+                    .await?;
+                break;
+            }
+            _ = keep_alive.tick() => {
+                KeepAlive::create(since.elapsed().as_millis() as i64)
+                    .send(&mut write, protocol_version)
+                    .await?;
+            }
+            _ = check.tick() => {
+                let (state, gone, addr) = {
+                    let handler = backend.handler.lock().unwrap();
+                    let state = handler.state();
+                    (state, !state.is_alive() && !handler.restart_queued(), handler.addr.clone())
+                };
+                // Ready by the console can be before the port is bound
+                if state == ServerState::Ready && reachable(&addr).await {
+                    println!(
+                        "PROXY: {} waited {:?} in limbo; transferring to {host}:{port}",
+                        server_state.player(),
+                        since.elapsed()
+                    );
+                    Transfer::create(host, port)
+                        .send(&mut write, protocol_version)
+                        .await?;
+                    break;
+                }
+                if gone || since.elapsed() >= max_wait {
+                    println!(
+                        "PROXY: {} gave up on {} in limbo ({state}); Disconnecting...",
+                        server_state.player(),
+                        backend.config.name
+                    );
+                    let reason = server_state
+                        .template_vars(Some(backend))
+                        .render(&config.messages.disconnect_limbo_failed);
+                    Disconnect::set_reason(TextComponent::from_legacy(&reason))
+                        .send(&mut write, protocol_version)
+                        .await?;
+                    break;
+                }
+            }
+        }
+    }
+    // Closing first could reset the connection before the client read the last packet
+    tokio::time::timeout(CLOSE_GRACE, drain).await.ok();
+    Ok(())
+}
+
+/// Whether the server at `addr` accepts a connection within `PROBE_TIMEOUT`.
+async fn reachable(addr: &str) -> bool {
+    matches!(
+        tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(addr)).await,
+        Ok(Ok(_))
+    )
+}
+
+/// Sends the registries and finishes the configuration, the client information and
+/// plugin messages it sends meanwhile are ignored, only the brand is logged.
+async fn configure(
+    client_stream: &mut TcpStream,
+    server_state: &ClientConnectionState,
+    proxy: &Proxy,
+) -> ProxyResult<()> {
+    let protocol_version = server_state.protocol_version;
+    for registry in registries(protocol_version) {
+        registry.send(client_stream, protocol_version).await?;
+    }
+    FinishConfiguration {}
+        .send(client_stream, protocol_version)
+        .await?;
+    let limits = proxy.config.read_limits(ProtocolState::Configuration);
+    loop {
+        let packet = Packet::read(client_stream, limits).await?;
+        if packet
+            .expect::<AcknowledgeFinishConfiguration>(protocol_version)
+            .is_ok()
+        {
+            return Ok(());
+        }
+        if let Ok(message) = packet.expect::<PluginMessage>(protocol_version) {
+            if message.is_brand() {
+                let brand = VarString::parse(&mut message.data.get_value().iter().copied())
+                    .map(|x| x.get_value())
+                    .unwrap_or_default();
+                println!("PROXY: {} plays on {brand:?}", server_state.player());
+            }
+        }
+    }
+}
+
+/// Spawns the player as a spectator above the empty overworld.
+async fn join(client_stream: &mut TcpStream, protocol_version: i32) -> ProxyResult<()> {
+    let overworld = Identifier::from("overworld").unwrap_or_default();
+    Login {
+        entity_id: 1,
+        dimension_names: vec![overworld.clone()],
+        max_players: VarInt::from(1),
+        view_distance: VarInt::from(2),
+        simulation_distance: VarInt::from(2),
+        respawn_screen: true,
+        dimension_type: VarInt::from(0),
+        dimension_name: overworld,
+        game_mode: 3,
+        previous_game_mode: -1,
+        sea_level: VarInt::from(63),
+        ..Login::default()
+    }
+    .send(client_stream, protocol_version)
+    .await?;
+    SetDefaultSpawnPosition {
+        location: Position {
+            x: 0,
+            y: SPAWN_Y as i32,
+            z: 0,
+        },
+        angle: 0.0,
+    }
+    .send(client_stream, protocol_version)
+    .await?;
+    PlayerPosition::create(0.5, SPAWN_Y, 0.5)
+        .send(client_stream, protocol_version)
+        .await?;
+    GameEvent::start_waiting_for_chunks()
+        .send(client_stream, protocol_version)
+        .await?;
+    Ok(())
+}
+
+/// The smallest set of registries the client joins a world with, every entry with its data
+/// so it doesn't matter which packs the client has. Fields a version doesn't know are ignored,
+/// so one entry can carry the old and the new layout.
+fn registries(protocol_version: i32) -> Vec<RegistryData> {
+    let string = |x: &str| Nbt::String(x.to_owned());
+    let overworld = compound([
+        ("has_skylight", Nbt::Byte(1)),
+        ("has_ceiling", Nbt::Byte(0)),
+        ("ultrawarm", Nbt::Byte(0)),
+        ("natural", Nbt::Byte(1)),
+        ("coordinate_scale", Nbt::Double(1.0)),
+        ("bed_works", Nbt::Byte(1)),
+        ("respawn_anchor_works", Nbt::Byte(0)),
+        ("min_y", Nbt::Int(0)),
+        ("height", Nbt::Int(256)),
+        ("logical_height", Nbt::Int(256)),
+        ("infiniburn", string("#minecraft:infiniburn_overworld")),
+        ("effects", string("minecraft:overworld")),
+        ("ambient_light", Nbt::Float(0.0)),
+        ("piglin_safe", Nbt::Byte(0)),
+        ("has_raids", Nbt::Byte(0)),
+        ("monster_spawn_light_level", Nbt::Int(0)),
+        ("monster_spawn_block_light_limit", Nbt::Int(0)),
+    ]);
+    let plains = compound([
+        ("has_precipitation", Nbt::Byte(0)),
+        ("temperature", Nbt::Float(0.8)),
+        ("downfall", Nbt::Float(0.4)),
+        (
+            "effects",
+            compound([
+                ("fog_color", Nbt::Int(0xc0d8ff)),
+                ("water_color", Nbt::Int(0x3f76e4)),
+                ("water_fog_color", Nbt::Int(0x050533)),
+                ("sky_color", Nbt::Int(0x78a7ff)),
+            ]),
+        ),
+    ]);
+    // The client looks every one of these up when it creates the world,
+    // the ones that are newer than its version are just extra entries
+    let damage_types = DAMAGE_TYPES
+        .iter()
+        .map(|name| {
+            let damage_type = compound([
+                ("message_id", string(name)),
+                ("scaling", string("never")),
+                ("exhaustion", Nbt::Float(0.0)),
+            ]);
+            RegistryEntry::create(name, damage_type)
+        })
+        .collect();
+    let wolf = compound([
+        ("wild_texture", string("minecraft:entity/wolf/wolf")),
+        ("tame_texture", string("minecraft:entity/wolf/wolf_tame")),
+        ("angry_texture", string("minecraft:entity/wolf/wolf_angry")),
+        ("biomes", string("minecraft:plains")),
+        // 1.21.5+
+        (
+            "assets",
+            compound([
+                ("wild", string("minecraft:entity/wolf/wolf")),
+                ("tame", string("minecraft:entity/wolf/wolf_tame")),
+                ("angry", string("minecraft:entity/wolf/wolf_angry")),
+            ]),
+        ),
+    ]);
+
+    let mut registries = vec![
+        RegistryData::create(
+            "dimension_type",
+            vec![RegistryEntry::create("overworld", overworld)],
+        ),
+        RegistryData::create(
+            "worldgen/biome",
+            vec![RegistryEntry::create("plains", plains)],
+        ),
+        RegistryData::create("damage_type", damage_types),
+        RegistryData::create("wolf_variant", vec![RegistryEntry::create("pale", wolf)]),
+    ];
+    // 1.21 made paintings data driven
+    if protocol_version >= 767 {
+        let kebab = compound([
+            ("asset_id", string("minecraft:kebab")),
+            ("width", Nbt::Int(1)),
+            ("height", Nbt::Int(1)),
+        ]);
+        registries.push(RegistryData::create(
+            "painting_variant",
+            vec![RegistryEntry::create("kebab", kebab)],
+        ));
+    }
+    // 1.21.5 made the other mob variants data driven, every one needs a default
+    if protocol_version >= 770 {
+        for (registry, name, asset) in [
+            (
+                "pig_variant",
+                "temperate",
+                "minecraft:entity/pig/temperate_pig",
+            ),
+            (
+                "cow_variant",
+                "temperate",
+                "minecraft:entity/cow/temperate_cow",
+            ),
+            (
+                "chicken_variant",
+                "temperate",
+                "minecraft:entity/chicken/temperate_chicken",
+            ),
+            (
+                "frog_variant",
+                "temperate",
+                "minecraft:entity/frog/temperate_frog",
+            ),
+            ("cat_variant", "tabby", "minecraft:entity/cat/tabby"),
+        ] {
+            let variant = compound([("asset_id", string(asset))]);
+            registries.push(RegistryData::create(
+                registry,
+                vec![RegistryEntry::create(name, variant)],
+            ));
+        }
+        let sounds = ["ambient", "death", "growl", "hurt", "pant", "whine"].map(|x| {
+            let sound = string(&format!("minecraft:entity.wolf.{x}"));
+            (format!("{x}_sound"), sound)
+        });
+        registries.push(RegistryData::create(
+            "wolf_sound_variant",
+            vec![RegistryEntry::create(
+                "classic",
+                Nbt::Compound(sounds.into()),
+            )],
+        ));
+    }
+    registries
+}
+
+/// Every vanilla damage type from 1.20.5 to 1.21.8.
+const DAMAGE_TYPES: [&str; 49] = [
+    "arrow",
+    "bad_respawn_point",
+    "cactus",
+    "campfire",
+    "cramming",
+    "dragon_breath",
+    "drown",
+    "dry_out",
+    "ender_pearl",
+    "explosion",
+    "fall",
+    "falling_anvil",
+    "falling_block",
+    "falling_stalactite",
+    "fireball",
+    "fireworks",
+    "fly_into_wall",
+    "freeze",
+    "generic",
+    "generic_kill",
+    "hot_floor",
+    "in_fire",
+    "in_wall",
+    "indirect_magic",
+    "lava",
+    "lightning_bolt",
+    "mace_smash",
+    "magic",
+    "mob_attack",
+    "mob_attack_no_aggro",
+    "mob_projectile",
+    "on_fire",
+    "out_of_world",
+    "outside_border",
+    "player_attack",
+    "player_explosion",
+    "sonic_boom",
+    "spit",
+    "stalagmite",
+    "starve",
+    "sting",
+    "sweet_berry_bush",
+    "thorns",
+    "thrown",
+    "trident",
+    "unattributed_fireball",
+    "wind_charge",
+    "wither",
+    "wither_skull",
+];
+
+fn compound<const N: usize>(entries: [(&str, Nbt); N]) -> Nbt {
+    Nbt::Compound(entries.map(|(name, x)| (name.to_owned(), x)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        metrics::Metrics,
+        ratelimit::RateLimiter,
+        router::Router,
+        sessions::SessionTable,
+        types::{ByteArray, Codec, Uuid},
+    };
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn gives_up_on_a_stopped_server() {
+        let config =
+            Config::parse("[messages]\ndisconnect_limbo_failed = \"{player} {state}\"").unwrap();
+        let proxy = Proxy {
+            router: Router::create(&config),
+            rate_limiter: RateLimiter::create(&config.rate_limit),
+            metrics: Metrics::create(),
+            sessions: SessionTable::create(),
+            config,
+        };
+        let backend = proxy.router.backends()[0].clone();
+        let server_state = ClientConnectionState {
+            state: ProtocolState::Login,
+            protocol_version: 767,
+            username: Some("Notch".to_owned()),
+            uuid: Some(Uuid::parse(&mut [0x11; 16].into_iter()).unwrap()),
+            server_address: "mc.example.com\0FML\0".to_owned(),
+            server_port: 25565,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut stream, addr) = listener.accept().await.unwrap();
+        let mut session = proxy.sessions.register(
+            addr,
+            tokio_util::sync::CancellationToken::new(),
+            proxy.metrics.connected(),
+        );
+
+        let limits = proxy.config.read_limits(ProtocolState::Login);
+        // Owns the stream, so the limbo sees it close right after the Disconnect
+        let client = async move {
+            let success = Packet::read(&mut client, limits).await.unwrap();
+            assert_eq!(success.id.get_int(), 2);
+            LoginAcknowledged {}.send(&mut client, 767).await.unwrap();
+            let mut registries = Vec::new();
+            loop {
+                let packet = Packet::read(&mut client, limits).await.unwrap();
+                match packet.expect::<RegistryData>(767) {
+                    Ok(registry) => registries.push(registry.registry.to_string()),
+                    Err(_) => {
+                        packet.expect::<FinishConfiguration>(767).unwrap();
+                        break;
+                    }
+                }
+            }
+            assert!(registries.contains(&"minecraft:damage_type".to_owned()));
+            // The brand on the way, before the acknowledgement
+            let brand = VarString::from("vanilla".to_owned()).get_data().unwrap();
+            PluginMessage {
+                channel: Identifier::from("brand").unwrap(),
+                data: ByteArray::from(brand),
+            }
+            .send(&mut client, 767)
+            .await
+            .unwrap();
+            AcknowledgeFinishConfiguration {}
+                .send(&mut client, 767)
+                .await
+                .unwrap();
+
+            let mut ids = Vec::new();
+            loop {
+                let packet = Packet::read(&mut client, limits).await.unwrap();
+                if let Ok(disconnect) = packet.expect::<Disconnect>(767) {
+                    return (ids, disconnect.reason.0);
+                }
+                ids.push(packet.id.get_int());
+            }
+        };
+        let (res, (ids, reason)) = tokio::join!(
+            hold(&mut stream, &server_state, &backend, &proxy, &mut session),
+            client
+        );
+        res.unwrap();
+        // Login, spawn position, position and game event, then keep alives
+        assert_eq!(ids[..4], [0x2b, 0x56, 0x40, 0x22]);
+        assert!(ids[4..].iter().all(|x| *x == 0x26));
+        assert_eq!(reason.to_plain(), "Notch stopped");
+        assert_eq!(proxy.sessions.list()[0].state, "play");
+    }
+
+    #[tokio::test]
+    async fn probes_the_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        assert!(reachable(&addr).await);
+        drop(listener);
+        assert!(!reachable(&addr).await);
+    }
+
+    #[test]
+    fn registries_by_version() {
+        let names = |protocol_version| {
+            registries(protocol_version)
+                .into_iter()
+                .map(|x| x.registry.get_path().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(766),
+            [
+                "dimension_type",
+                "worldgen/biome",
+                "damage_type",
+                "wolf_variant"
+            ]
+        );
+        assert_eq!(names(767)[4..], ["painting_variant"]);
+        assert_eq!(names(770).len(), 11);
+        for registry in registries(MAX_PROTOCOL) {
+            let packet = registry.encode(MAX_PROTOCOL).unwrap();
+            assert_eq!(
+                RegistryData::decode(&packet, MAX_PROTOCOL).unwrap(),
+                registry
+            );
+        }
+        let mut damage_types = DAMAGE_TYPES.to_vec();
+        damage_types.dedup();
+        assert_eq!(damage_types.len(), DAMAGE_TYPES.len());
+        assert!(supports(766) && supports(772));
+        assert!(!supports(765) && !supports(773));
+    }
+}
@@ -1,364 +1,924 @@
 extern crate nix;
 
 use std::{
-    io::Write,
-    net::{SocketAddr, TcpListener, TcpStream},
-    os::fd::AsFd,
-    sync::{Arc, Mutex},
-    thread::{self, JoinHandle},
+    fmt::Display,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+mod admin;
+mod allowlist;
+mod config;
+mod control;
+mod error;
+mod http;
+mod limbo;
+mod metrics;
 mod mincraft_server;
 mod packets;
+mod properties;
+mod ratelimit;
+mod router;
+mod sessions;
+mod template;
+mod text;
 mod types;
+mod versions;
 
-use clap::Parser;
-use mincraft_server::MinecraftServerHandler;
-use nix::{
-    fcntl::{splice, SpliceFFlags},
-    unistd::pipe,
-};
+use clap::{Parser, Subcommand};
+use config::{Config, ListenerConfig, OverLimitStatus};
+use error::{ProxyError, ProxyResult};
+use metrics::Metrics;
+use mincraft_server::ServerState;
 use packets::{
-    clientbound::status::StatusStructNew, serverbound::handshake::Handshake, Packet, SendPacket,
+    clientbound::{
+        login::Disconnect,
+        status::{PongResponse, StatusResponse, StatusStructNew, StatusTrait},
+    },
+    registry::{Direction, Registered},
+    serverbound::{
+        handshake::Handshake,
+        login::LoginStart,
+        status::{PingRequest, StatusRequest},
+    },
+    Packet, SendPacket,
+};
+use ratelimit::{RateLimiter, Slot};
+use router::{Backend, Router};
+use sessions::{SessionHandle, SessionTable};
+use template::Vars;
+use text::TextComponent;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
 };
+use tokio_util::sync::CancellationToken;
+use types::Uuid;
 
+/// How long a kicked connection gets to tell the client why, before it is closed.
+const KICK_GRACE: Duration = Duration::from_secs(1);
+/// How long the shutdown waits for the stopping servers to kick their players.
+const PLAYERS_KICK_WAIT: Duration = Duration::from_secs(10);
+
+/// Every flag overrides the matching value from the config file,
+/// `proxy_to` and `start_command` apply to the first backend.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Path to the toml config file
+    #[arg(long, short)]
+    config: Option<PathBuf>,
     /// Addr to bind to
-    #[arg(long, short, default_value = "127.0.0.1:7878")]
-    bind_addr: String,
-    #[arg(default_value = "127.0.0.1:25565")]
-    proxy_to: String,
-    #[arg(long, short, default_value = "minecraft-server")]
-    start_command: String,
+    #[arg(long, short)]
+    bind_addr: Option<String>,
+    proxy_to: Option<String>,
+    #[arg(long, short)]
+    start_command: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Talk to a running proxy over its control socket
+    Ctl {
+        /// Defaults to `control.socket` from the config
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// Needed when the proxy has more than one backend
+        #[arg(long)]
+        backend: Option<String>,
+        #[command(subcommand)]
+        action: CtlAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlAction {
+    /// Show the state of the backends
+    Status,
+    /// Start the server
+    Start,
+    /// Stop the server gracefully
+    Stop,
+    /// Print the last lines of the server console
+    Logs,
+    /// Send a command to the server console
+    Cmd {
+        #[arg(required = true, num_args = 1..)]
+        text: Vec<String>,
+    },
+    /// List the open client connections
+    Sessions,
+    /// Close a client connection, the id is from `sessions`
+    Kick { id: u64 },
 }
 
-fn main() {
-    let args = Args::parse();
-    let listener = TcpListener::bind(&args.bind_addr).expect("Can't bind to address");
-    let mc_server_handler = Arc::new(Mutex::new(MinecraftServerHandler::create(
-        args.start_command.clone(),
-        args.proxy_to.clone(),
-    )));
+impl Args {
+    fn into_config(self) -> Result<Config, config::ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        if let Some(bind) = self.bind_addr {
+            config.listeners = vec![ListenerConfig { bind }];
+        }
+        if let Some(backend) = config.backends.first_mut() {
+            if let Some(address) = self.proxy_to {
+                backend.address = address;
+            }
+            if let Some(start_command) = self.start_command {
+                backend.start_command = start_command;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// `mc-proxy ctl ...`, exits with 1 if the proxy answered with an error.
+async fn ctl(
+    config: Option<PathBuf>,
+    socket: Option<PathBuf>,
+    backend: Option<String>,
+    action: CtlAction,
+) {
+    let socket = match (socket, config) {
+        (Some(socket), _) => Some(socket),
+        (None, Some(path)) => match Config::load(&path) {
+            Ok(config) => config.control.socket,
+            Err(err) => {
+                eprintln!("PROXY: {err}");
+                std::process::exit(1);
+            }
+        },
+        (None, None) => Config::default().control.socket,
+    };
+    let socket = match socket {
+        Some(x) => x,
+        None => {
+            eprintln!(
+                "PROXY: no control socket, pass --socket or set control.socket in the config"
+            );
+            std::process::exit(1);
+        }
+    };
+    let request = match action {
+        CtlAction::Status => control::ControlRequest::Status { backend },
+        CtlAction::Start => control::ControlRequest::Start { backend },
+        CtlAction::Stop => control::ControlRequest::Stop { backend },
+        CtlAction::Logs => control::ControlRequest::Logs { backend },
+        CtlAction::Cmd { text } => control::ControlRequest::Cmd {
+            backend,
+            text: text.join(" "),
+        },
+        CtlAction::Sessions => control::ControlRequest::Sessions,
+        CtlAction::Kick { id } => control::ControlRequest::Kick { id },
+    };
+    match control::request(&socket, request).await {
+        Ok(response) if response.ok => match response.data {
+            Some(data) => println!(
+                "{}",
+                serde_json::to_string_pretty(&data).unwrap_or_default()
+            ),
+            None => println!("ok"),
+        },
+        Ok(response) => {
+            eprintln!("{}", response.error.unwrap_or_default());
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("PROXY: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = Args::parse();
+    if let Some(Command::Ctl {
+        socket,
+        backend,
+        action,
+    }) = args.command.take()
+    {
+        return ctl(args.config, socket, backend, action).await;
+    }
+    let config = match args.into_config() {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("PROXY: {err}");
+            std::process::exit(1);
+        }
+    };
+    let proxy = Arc::new(Proxy {
+        router: Router::create(&config),
+        rate_limiter: RateLimiter::create(&config.rate_limit),
+        metrics: Metrics::create(),
+        sessions: SessionTable::create(),
+        config,
+    });
     let commit_hash: &'static str = env!(
         "COMMIT_HASH",
         "No COMMIT_HASH env var during build, but build.rs should always set it?"
     );
 
+    let shutdown = CancellationToken::new();
+    for listener_config in &proxy.config.listeners {
+        let listener = bind_or_exit(&listener_config.bind, "listener").await;
+        tokio::spawn(accept_loop(listener, proxy.clone(), shutdown.clone()));
+    }
+    if let Some(bind) = &proxy.config.metrics.bind {
+        let listener = bind_or_exit(bind, "metrics").await;
+        println!("PROXY: serving metrics on http://{bind}/metrics");
+        tokio::spawn(metrics::serve(listener, proxy.clone(), shutdown.clone()));
+    }
+    if let Some(bind) = &proxy.config.admin.bind {
+        let listener = bind_or_exit(bind, "admin api").await;
+        println!("PROXY: serving the admin api on http://{bind}");
+        tokio::spawn(admin::serve(listener, proxy.clone(), shutdown.clone()));
+    }
+    if let Some(path) = &proxy.config.control.socket {
+        match control::bind(path) {
+            Ok(listener) => {
+                println!("PROXY: control socket at {}", path.display());
+                tokio::spawn(control::serve(listener, proxy.clone(), shutdown.clone()));
+            }
+            Err(err) => {
+                eprintln!("PROXY: Can't bind control socket {}: {err}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
     println!("Listening for connections!(rev: {commit_hash})");
+
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Can't listen for ctrl-c");
+    println!("PROXY: ctrl-c received; stopping the servers");
+    // The servers run in their own process group, so the ctrl-c didn't reach them
+    let mut stopping = false;
+    for backend in proxy.router.backends() {
+        if backend
+            .handler
+            .lock()
+            .unwrap()
+            .stop_minecraft_server()
+            .is_ok()
+        {
+            println!("PROXY: stopping {}", backend.config.name);
+            stopping = true;
+        }
+    }
+    // A stopping server kicks its players itself, the proxy can't talk into their connections
+    let waiting = Instant::now();
+    while stopping && proxy.sessions.playing() > 0 && waiting.elapsed() < PLAYERS_KICK_WAIT {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    println!("PROXY: closing every connection");
+    shutdown.cancel();
+    tokio::time::sleep(KICK_GRACE).await;
+    if let Some(path) = &proxy.config.control.socket {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+async fn bind_or_exit(bind: &str, what: &str) -> TcpListener {
+    match TcpListener::bind(bind).await {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("PROXY: Can't bind {what} to {bind}: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Everything the connection tasks share.
+pub struct Proxy {
+    config: Config,
+    router: Router,
+    rate_limiter: RateLimiter,
+    metrics: Metrics,
+    sessions: SessionTable,
+}
+
+async fn accept_loop(listener: TcpListener, proxy: Arc<Proxy>, shutdown: CancellationToken) {
     loop {
-        match listener.accept() {
-            Ok((str, addr)) => {
-                handle_client_join(mc_server_handler.clone(), str, addr);
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            res = listener.accept() => match res {
+                Ok((str, addr)) => accept(&proxy, str, addr, &shutdown),
+                Err(err) => eprintln!("Error encountered while resolving listener connection: {err}"),
+            },
+        }
+    }
+}
+
+/// Checks the rate limits before spawning anything, so a flood of silent connections can't pile up.
+fn accept(
+    proxy: &Arc<Proxy>,
+    client_stream: TcpStream,
+    addr: SocketAddr,
+    shutdown: &CancellationToken,
+) {
+    proxy.metrics.accepted();
+    match proxy.rate_limiter.admit(addr.ip()) {
+        Ok(slot) => handle_client_join(
+            proxy.clone(),
+            client_stream,
+            addr,
+            slot,
+            shutdown.child_token(),
+        ),
+        Err(rejection) => {
+            println!(
+                "{addr} -- Rate limited by {rejection} ({} rejected so far, {} sessions active); Disconnecting...",
+                proxy.rate_limiter.rejected(rejection),
+                proxy.rate_limiter.active_sessions()
+            );
+            // Too many at once and they are just closed
+            if let Some(slot) = proxy.rate_limiter.rejecter() {
+                handle_rejected(proxy.clone(), client_stream, addr, slot);
             }
-            Err(err) => eprintln!("Error encountered while resolving listener connection: {err}"),
         }
     }
 }
+
+/// Spawns the task for a single client, dropping the task (by `cancel` or by returning) closes both sockets.
+/// `slot` is the `max_sessions` slot the client holds until then.
 pub fn handle_client_join(
-    mc_server_handler: Arc<Mutex<MinecraftServerHandler>>,
-    mut client_stream: TcpStream,
+    proxy: Arc<Proxy>,
+    client_stream: TcpStream,
     client_addr: SocketAddr,
+    slot: Slot,
+    cancel: CancellationToken,
 ) {
-    thread::Builder::new().name("Client Join Handle".to_string()).spawn(move || {
-        println!("{client_addr} -- Connected");
-        let client_packet = match Packet::parse(&mut client_stream) {
-            Some(x) => x,
-            None => {
-                println!("Client HANDSHAKE -> bad packet; Disconnecting...");
-                return;
-            }
+    tokio::spawn(async move {
+        let _slot = slot;
+        let mut session =
+            proxy
+                .sessions
+                .register(client_addr, cancel.clone(), proxy.metrics.connected());
+        println!("{client_addr} -- Connected (session {})", session.id());
+        // Gives a kicked connection the time to tell the client why
+        let kicked = async {
+            cancel.cancelled().await;
+            tokio::time::sleep(KICK_GRACE).await;
         };
+        tokio::select! {
+            _ = kicked => println!("{client_addr} -- Cancelled"),
+            res = client_join(&proxy, client_stream, client_addr, &mut session) => match res {
+                Ok(()) | Err(ProxyError::Closed) => (),
+                Err(err) => println!("{client_addr} -- {err}; Disconnecting..."),
+            },
+        }
+        println!("{client_addr} -- Disconnected");
+    });
+}
 
-        let handshake;
-        let server_state;
-        if client_packet.id.get_int() == 0 {
-            handshake = packets::serverbound::handshake::Handshake::parse(client_packet)
-                .expect("Handshake request from client failed to parse");
-            server_state = match ClientConnectionState::create(&handshake) {
-                Some(x) => x,
-                None => {
-                    println!(
-                        "Client HANDSHAKE: {:#x} Transfer??? Disconnecting...",
-                        handshake.get_next_state()
-                    );
-                    return;
-                }
-            };
-        } else {
-            println!("Client HANDSHAKE -> bad packet; Disconnecting...");
-            return;
+/// Spawns the task telling a client over the rate limits why it gets no session,
+/// `slot` is one of the few rejecter slots.
+fn handle_rejected<S>(proxy: Arc<Proxy>, mut client_stream: S, client_addr: SocketAddr, slot: Slot)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let _slot = slot;
+        match answer_rejected(&proxy.config, &mut client_stream).await {
+            Ok(()) | Err(ProxyError::Closed) => (),
+            Err(err) => println!("{client_addr} -- {err}; Closing..."),
         }
-        let mc_addr = mc_server_handler.lock().unwrap().addr.clone();
-        let mut server_stream = match TcpStream::connect(mc_addr) {
-            Ok(x) => x,
-            Err(_) => {
-                let state = server_state.lock().unwrap().state;
-                match state {
-                    ProtocolState::Handshaking => todo!(),
-                    ProtocolState::Status => {
-                        let client_packet = Packet::parse(&mut client_stream).unwrap();
-                        match client_packet.id.get_int() {
-                            0 => {
-                                println!("Client STATUS: {:#x} Status Request", 0);
-                            }
-                            _ => {
-                                println!(
-                                    "Client STATUS: {:#x} Unknown Id -> Shutdown",
-                                    client_packet.id.get_int()
-                                );
-                                return;
-                            }
-                        };
-
-                        let mut json = StatusStructNew::create();
-                        json.version.protocol = server_state.lock().unwrap().protocol_version;
-                        json.players.max = 1;
-                        let commit_hash: &'static str = env!(
-                            "COMMIT_HASH",
-                            "No COMMIT_HASH env var during build, but build.rs should always set it?"
-                        );
-                        if mc_server_handler.lock().unwrap().running() {
-                            json.description.text =
-                                format!("§aServer is starting...§r please wait\n - §dTami§r with §d<3§r §8(rev: {commit_hash})§r");
-                            json.players.online = 1;
-                        } else {
-                            json.description.text =
-                                format!("Server is currently §onot§r running. \n§aJoin to start it!§r - §dTami§r with §d<3§r §8(rev: {commit_hash})§r");
-                        }
-                        let status_res =
-                            packets::clientbound::status::StatusResponse::set_json(Box::new(json));
-                        status_res.send_packet(&mut client_stream);
-                        if mc_server_handler.lock().unwrap().running() {
-                            let mut client_packet = Packet::parse(&mut client_stream).unwrap();
-                            match client_packet.id.get_int() {
-                                1 => {
-                                    println!("Client STATUS: {:#x} Ping Request (exit)", 1);
-                                    client_packet.send_packet(&mut client_stream);
-                                }
-                                _ => {
-                                    println!(
-                                        "Client STATUS: {:#x} Unknown Id -> Shutdown",
-                                        client_packet.id.get_int()
-                                    );
-                                    return;
-                                }
-                            };
-                        }
-                        println!("Server NOT ONLINE ->  Disconnecting...");
-                        return;
-                    }
-                    ProtocolState::Login => {
-                        let client_packet = Packet::parse(&mut client_stream).unwrap();
-                        //TODO: The underscore bug https://minecraft.wiki/w/Java_Edition_protocol#Type:JSON_Text_Component
-                        let disc_pack;
-                        if mc_server_handler.lock().unwrap().running() {
-                            disc_pack = packets::clientbound::login::Disconnect::set_reason(
-                                "Starting...§d<3§r".to_owned(),
-                            )
-                            .unwrap();
-                        } else {
-                            disc_pack = packets::clientbound::login::Disconnect::set_reason(
-                                "Okayyy_starting_it_now...§d<3§r".to_owned(),
-                            )
-                            .unwrap();
-                        }
-                        disc_pack.send_packet(&mut client_stream);
-
-                        mc_server_handler.lock().unwrap().start_minecraft_server();
-                        println!("Server NOT WORKING ->  Disconnecting...");
-                        return;
-                    }
-                    ProtocolState::Configuration => todo!(),
-                    ProtocolState::Play => todo!(),
-                    ProtocolState::ShutDown => todo!(),
-                    ProtocolState::Transfer => todo!(),
-                }
-            }
-        };
-        handshake.send_packet(&mut server_stream);
+    });
+}
 
-        let client_handle = client_proxy_thread(
-            client_stream.try_clone().unwrap(),
-            server_stream.try_clone().unwrap(),
-            server_state.clone(),
-        );
-        let server_handle = server_proxy_thread(client_stream, server_stream, server_state.clone());
-        match client_handle.join() {
-            Ok(_) => (),
-            Err(_) => server_state.lock().unwrap().state = ProtocolState::ShutDown,
-        };
-        match server_handle.join() {
-            Ok(_) => (),
-            Err(_) => server_state.lock().unwrap().state = ProtocolState::ShutDown,
-        };
-        println!("{client_addr} -- Disconnected");
-    }).unwrap();
+/// Logins get `messages.disconnect_rate_limited`, status pings what `status_over_limit` says.
+async fn answer_rejected<S>(config: &Config, client_stream: &mut S) -> ProxyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let handshake = Packet::read(
+        client_stream,
+        config.read_limits(ProtocolState::Handshaking),
+    )
+    .await?;
+    let handshake = Handshake::parse(&handshake)?;
+    let server_state = match ClientConnectionState::create(&handshake) {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    if server_state.state == ProtocolState::Status
+        && config.rate_limit.status_over_limit == OverLimitStatus::Drop
+    {
+        return Ok(());
+    }
+    answer_without_backend(
+        client_stream,
+        &server_state,
+        &config.messages.rate_limited_motd,
+        &config.messages.disconnect_rate_limited,
+        config,
+    )
+    .await
 }
 
-const BUF_SIZE: usize = 1024 * 512;
-fn client_proxy_thread(
+async fn client_join(
+    proxy: &Proxy,
     mut client_stream: TcpStream,
-    mut server_stream: TcpStream,
-    server_state: Arc<Mutex<ClientConnectionState>>,
-) -> JoinHandle<()> {
-    thread::Builder::new()
-        .name("Client Proxy thread".to_string())
-        .spawn(move || {
-            let mut status_req = false;
-            loop {
-                let state = server_state.lock().unwrap().state.clone();
-                match state {
-                    ProtocolState::Handshaking => {}
-                    ProtocolState::Status => {
-                        let client_packet = Packet::parse(&mut client_stream).unwrap();
-                        match client_packet.id.get_int() {
-                            0 => {
-                                if status_req {
-                                    server_state.lock().unwrap().state = ProtocolState::ShutDown;
-                                    println!(
-                                        "Client STATUS: {:#x} -> Shutdown; status_request spam",
-                                        0
-                                    );
-                                    return;
-                                }
-                                let a = packets::serverbound::status::StatusRequest::parse(
-                                    client_packet,
-                                )
-                                .expect("Couldn't parse statusrequest serverbound???");
-                                a.send_packet(&mut server_stream);
-                                println!("Client STATUS: {:#x} Status Request", 0);
-                                status_req = true;
-                            }
-                            1 => {
-                                println!("Client STATUS: {:#x} Ping Request (exit)", 1);
-                                server_stream.write_all(&client_packet.all).unwrap();
-                                server_stream.flush().unwrap();
-                                return;
-                            }
-                            _ => {
-                                println!(
-                                    "Client STATUS: {:#x} Unknown Id -> Shutdown",
-                                    client_packet.id.get_int()
-                                );
-                                server_state.lock().unwrap().state = ProtocolState::ShutDown;
-                                return;
-                            }
-                        }
-                    }
-                    ProtocolState::Login => {
-                        spliice(
-                            client_stream.try_clone().unwrap(),
-                            server_state.clone(),
-                            server_stream.try_clone().unwrap(),
-                            "Server".to_owned(),
-                        );
-                    }
-                    ProtocolState::ShutDown => {
-                        println!("Client SHUTDOWN: by protocol_state");
-                        return;
-                    }
-                    ProtocolState::Configuration => todo!(),
-                    ProtocolState::Play => todo!(),
-                    ProtocolState::Transfer => todo!(),
-                }
+    client_addr: SocketAddr,
+    session: &mut SessionHandle,
+) -> ProxyResult<()> {
+    let config = &proxy.config;
+    client_stream.set_nodelay(true).ok();
+    // Forwarded as it came, so the server sees whatever the client put after the fields
+    let handshake_packet = Packet::read(
+        &mut client_stream,
+        config.read_limits(ProtocolState::Handshaking),
+    )
+    .await?;
+    let handshake = Handshake::parse(&handshake_packet)?;
+    println!(
+        "{client_addr} -- Handshake for {}:{} from {}",
+        handshake.get_server_address(),
+        handshake.get_server_port(),
+        versions::describe(handshake.get_protocol_version())
+    );
+    let mut server_state = ClientConnectionState::create(&handshake).ok_or_else(|| {
+        ProxyError::Malformed(format!(
+            "handshake with next state {:#x}",
+            handshake.get_next_state()
+        ))
+    })?;
+    session.set_state(server_state.state);
+    if server_state.state == ProtocolState::Status {
+        if let Err(rejection) = proxy.rate_limiter.check_status(client_addr.ip()) {
+            println!(
+                "{client_addr} -- Rate limited by {rejection} ({} rejected so far, {} sessions active); Disconnecting...",
+                proxy.rate_limiter.rejected(rejection),
+                proxy.rate_limiter.active_sessions()
+            );
+            if config.rate_limit.status_over_limit == OverLimitStatus::Canned {
+                // A status ping, the disconnect is never sent
+                answer_without_backend(
+                    &mut client_stream,
+                    &server_state,
+                    &config.messages.rate_limited_motd,
+                    "",
+                    config,
+                )
+                .await?;
             }
-        })
-        .unwrap()
+            return Ok(());
+        }
+    }
+    let login_start = if server_state.logging_in() {
+        let client_packet =
+            Packet::read(&mut client_stream, config.read_limits(ProtocolState::Login)).await?;
+        let login_start = client_packet.expect::<LoginStart>(server_state.protocol_version)?;
+        server_state.set_player(&login_start);
+        session.set_player(server_state.player());
+        println!("{client_addr} -- Login Start: {}", server_state.player());
+        Some(client_packet)
+    } else {
+        None
+    };
+    if !config.versions.supports(server_state.protocol_version) {
+        println!(
+            "{client_addr} -- {} is not supported, only {}; Disconnecting...",
+            versions::describe(server_state.protocol_version),
+            config.versions.supported()
+        );
+        proxy.metrics.rejected_unsupported_version();
+        return answer_without_backend(
+            &mut client_stream,
+            &server_state,
+            &config.messages.unsupported_motd,
+            &config.messages.disconnect_unsupported_version,
+            config,
+        )
+        .await;
+    }
+    let transferred = server_state.state == ProtocolState::Transfer;
+    let backend = match proxy
+        .router
+        .route(&handshake.get_server_address(), transferred)
+    {
+        Some(x) => x,
+        None => {
+            println!(
+                "{client_addr} -- No backend for {}{}; Disconnecting...",
+                handshake.get_server_address(),
+                if transferred { " (transferred)" } else { "" }
+            );
+            proxy.metrics.rejected_unknown_host();
+            return answer_without_backend(
+                &mut client_stream,
+                &server_state,
+                &config.routing.unknown_motd,
+                &config.routing.unknown_disconnect,
+                config,
+            )
+            .await;
+        }
+    };
+    println!("{client_addr} -- Routed to {}", backend.config.name);
+    session.set_backend(backend.config.name.clone());
+    if server_state.state == ProtocolState::Status {
+        let ttl = Duration::from_secs(config.status.cache_ttl);
+        let cached = backend.handler.lock().unwrap().cached_status(ttl);
+        if let Some(status) = cached {
+            let vars = server_state.template_vars(Some(&backend));
+            return cached_status(
+                &mut client_stream,
+                status,
+                server_state.protocol_version,
+                config,
+                &vars,
+            )
+            .await;
+        }
+    }
+    let mc_addr = backend.handler.lock().unwrap().addr.clone();
+    let mut server_stream = match TcpStream::connect(mc_addr).await {
+        Ok(x) => x,
+        Err(_) => {
+            return handle_offline(&mut client_stream, &server_state, &backend, proxy, session)
+                .await;
+        }
+    };
+    server_stream.set_nodelay(true).ok();
+    if transferred && !backend.config.forward_transfers {
+        // The client was transferred to the proxy, for the server it's a plain login
+        handshake
+            .with_next_state(2)
+            .send(&mut server_stream, server_state.protocol_version)
+            .await?;
+    } else {
+        handshake_packet.send_packet(&mut server_stream).await?;
+    }
+    if let Some(login_start) = &login_start {
+        login_start.send_packet(&mut server_stream).await?;
+    }
+
+    match server_state.state {
+        ProtocolState::Status => {
+            let vars = server_state.template_vars(Some(&backend));
+            proxy_status(
+                &mut client_stream,
+                &mut server_stream,
+                server_state.protocol_version,
+                config,
+                &vars,
+            )
+            .await?;
+        }
+        ProtocolState::Login | ProtocolState::Transfer => {
+            session.set_state(ProtocolState::Play);
+            spliice(
+                &mut client_stream,
+                &mut server_stream,
+                config.proxy.buffer_size,
+                &proxy.metrics,
+            )
+            .await;
+            println!("{client_addr} -- {} left", server_state.player());
+        }
+        _ => (),
+    }
+    client_stream.shutdown().await.ok();
+    server_stream.shutdown().await.ok();
+    Ok(())
 }
 
-fn server_proxy_thread(
-    mut client_stream: TcpStream,
-    mut server_stream: TcpStream,
-    server_state: Arc<Mutex<ClientConnectionState>>,
-) -> JoinHandle<()> {
-    thread::Builder::new().name("Server Proxy thread".to_string()).spawn(move || {
-        let mut spam = false;
-        loop {
-            let state = server_state.lock().unwrap().state.clone();
-
-            match state {
-                ProtocolState::Handshaking => {
-                    println!("----------------NOOOOOPE----------------");
-                    panic!();
-                }
-                ProtocolState::Status => {
-                    let server_packet = Packet::parse(&mut server_stream).unwrap();
-                    match server_packet.id.get_int() {
-                        0 => {
-                            if spam {
-                                server_state.lock().unwrap().state = ProtocolState::ShutDown;
-                                println!(
-                                    "Server STATUS: {:#x} -> Shutdown; status_request spam",
-                                    0
-                                );
-                                return;
-                            }
-                            let mut a =
-                                packets::clientbound::status::StatusResponse::parse(server_packet)
-                                    .unwrap();
-                            let commit_hash: &'static str = env!(
-                                "COMMIT_HASH",
-                                "No COMMIT_HASH env var during build, but build.rs should always set it?"
-                            );
-                            if let Some(mut json) = a.get_json() {
-                                let mut motd = json
-                                    .get_description()
-                                    .push_str(&format!("\n    §6Rusty proxy§r §d<3§r version §8(rev: {commit_hash})"));
-
-                                a = packets::clientbound::status::StatusResponse::set_json(json);
-                            } else {
-                                println!("Server STATUS: {}", a.get_string());
-                                println!("Server STATUS: Failed to parse status response json... continuing without parsing");
-                            }
-                            a.send_packet(&mut client_stream);
-                            println!(
-                                "Server STATUS: {:#x} Status Response\t{}",
-                                0,
-                                a.get_string()
-                            );
-                            spam = true;
-                        }
-                        1 => {
-                            println!("Server STATUS: {:#x} Pong Response (exit)", 1);
-                            server_state.lock().unwrap().state = ProtocolState::ShutDown;
-                            client_stream.write_all(&server_packet.all).unwrap();
-                            client_stream.flush().unwrap();
-                            return;
-                        }
-                        _ => {
-                            println!("Server STATUS: {:#x}", server_packet.id.get_int());
-                            client_stream.write_all(&server_packet.all).unwrap();
-                            client_stream.flush().unwrap();
-                        }
-                    }
-                }
-                ProtocolState::Login => {
-                    spliice(
-                        server_stream.try_clone().unwrap(),
-                        server_state.clone(),
-                        client_stream.try_clone().unwrap(),
-                        "Server".to_owned(),
-                    );
+/// The backend isn't reachable, so the proxy answers in its place (and starts it on a login).
+/// Logins of 1.20.5+ clients wait for it in the limbo if that's enabled.
+async fn handle_offline(
+    client_stream: &mut TcpStream,
+    server_state: &ClientConnectionState,
+    backend: &Backend,
+    proxy: &Proxy,
+    session: &mut SessionHandle,
+) -> ProxyResult<()> {
+    let config = &proxy.config;
+    let mc_server_handler = &backend.handler;
+    let limits = config.read_limits(server_state.state);
+    let protocol_version = server_state.protocol_version;
+    match server_state.state {
+        ProtocolState::Status => {
+            Packet::read(client_stream, limits)
+                .await?
+                .expect::<StatusRequest>(protocol_version)?;
+            println!("Client STATUS: Status Request");
+
+            let (state, last_status) = {
+                let handler = mc_server_handler.lock().unwrap();
+                (handler.state(), handler.last_status())
+            };
+            let vars = server_state.template_vars(Some(backend));
+            // The favicon, version and max players of the server look better than nothing
+            let properties = &backend.properties;
+            let mut json = last_status.unwrap_or_else(|| {
+                let mut json = StatusStructNew::create();
+                (json.version.name, json.version.protocol) =
+                    config.versions.status_version(protocol_version);
+                json.players.max = properties.max_players.unwrap_or(1);
+                if let Some(favicon) = &properties.favicon {
+                    json.set_favicon(favicon.clone());
                 }
-                ProtocolState::ShutDown => {
-                    println!("Server SHUTDOWN: by protocol_state");
-                    return;
+                json
+            });
+            let running = state.is_alive();
+            let motd = match state {
+                // Ready but refusing connections is most likely still binding the port
+                ServerState::Starting | ServerState::Ready => &config.messages.starting_motd,
+                ServerState::Stopping => &config.messages.stopping_motd,
+                ServerState::Crashed => &config.messages.crashed_motd,
+                ServerState::Stopped => backend
+                    .config
+                    .offline_motd
+                    .as_ref()
+                    .unwrap_or(&config.messages.offline_motd),
+            };
+            if state == ServerState::Starting && !config.messages.starting_version.is_empty() {
+                json.version.name = vars.render(&config.messages.starting_version);
+                // A protocol no client speaks makes it show the version name instead of the ping bars
+                json.version.protocol = -1;
+            }
+            json.set_description(TextComponent::from_legacy(&vars.render(motd)));
+            json.players.online = if running { 1 } else { 0 };
+            StatusResponse::set_json(Box::new(json))
+                .send(client_stream, protocol_version)
+                .await?;
+            if running {
+                let ping = Packet::read(client_stream, limits)
+                    .await?
+                    .expect::<PingRequest>(protocol_version)?;
+                println!("Client STATUS: Ping Request (exit)");
+                PongResponse {
+                    payload: ping.payload,
                 }
-                ProtocolState::Configuration => todo!(),
-                ProtocolState::Play => todo!(),
-                ProtocolState::Transfer => todo!(),
+                .send(client_stream, protocol_version)
+                .await?;
+            }
+            println!("Server NOT ONLINE ->  Disconnecting...");
+        }
+        ProtocolState::Login | ProtocolState::Transfer => {
+            let state = mc_server_handler.lock().unwrap().state();
+            let starting = matches!(state, ServerState::Starting | ServerState::Ready);
+            let allowed = starting
+                || backend
+                    .allow_list
+                    .is_allowed(&server_state.player_name(), server_state.uuid);
+            let reason = if starting {
+                &config.messages.disconnect_already_starting
+            } else if !allowed {
+                &config.messages.disconnect_not_allowed
+            } else if state == ServerState::Stopping {
+                &config.messages.disconnect_stopping
+            } else {
+                &config.messages.disconnect_starting
             };
+            // The transfer at the end has to come back to this backend. A server that's ready
+            // but refuses connections is broken, or binding the port a bit later than it said
+            let limbo = allowed
+                && state != ServerState::Ready
+                && config.limbo.enabled
+                && limbo::supports(server_state.protocol_version)
+                && backend.config.joins.takes(true);
+            if !limbo {
+                let reason = server_state.template_vars(Some(backend)).render(reason);
+                Disconnect::set_reason(TextComponent::from_legacy(&reason))
+                    .send(client_stream, protocol_version)
+                    .await?;
+            }
+
+            if !allowed {
+                proxy.metrics.rejected_not_allowed();
+                println!(
+                    "PROXY: {} is not allowed to start {}; Disconnecting...",
+                    server_state.player(),
+                    backend.config.name
+                );
+                return Ok(());
+            }
+            if !starting {
+                println!(
+                    "PROXY: {} is waking {} ({state})",
+                    server_state.player(),
+                    backend.config.name
+                );
+                if let Err(err) = mc_server_handler.lock().unwrap().wake() {
+                    println!("PROXY: Failed to start {}: {err}", backend.config.name);
+                }
+            }
+            if limbo {
+                return limbo::hold(client_stream, server_state, backend, proxy, session).await;
+            }
+            if state == ServerState::Ready {
+                println!(
+                    "PROXY: {} is ready but refuses connections; Disconnecting...",
+                    backend.config.name
+                );
+                return Ok(());
+            }
+            println!("Server NOT WORKING ->  Disconnecting...");
+        }
+        ProtocolState::Handshaking
+        | ProtocolState::Configuration
+        | ProtocolState::Play
+        | ProtocolState::ShutDown => (),
+    }
+    Ok(())
+}
+
+/// Answers a client without touching any server: status pings get `motd`, logins get `disconnect`.
+async fn answer_without_backend<S>(
+    client_stream: &mut S,
+    server_state: &ClientConnectionState,
+    motd: &str,
+    disconnect: &str,
+    config: &Config,
+) -> ProxyResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let limits = config.read_limits(server_state.state);
+    let protocol_version = server_state.protocol_version;
+    let mut vars = server_state.template_vars(None);
+    vars.set("supported", config.versions.supported());
+    match server_state.state {
+        ProtocolState::Status => {
+            Packet::read(client_stream, limits)
+                .await?
+                .expect::<StatusRequest>(protocol_version)?;
+            let mut json = StatusStructNew::create();
+            (json.version.name, json.version.protocol) =
+                config.versions.status_version(protocol_version);
+            json.set_description(TextComponent::from_legacy(&vars.render(motd)));
+            StatusResponse::set_json(Box::new(json))
+                .send(client_stream, protocol_version)
+                .await?;
+            let ping = Packet::read(client_stream, limits)
+                .await?
+                .expect::<PingRequest>(protocol_version)?;
+            PongResponse {
+                payload: ping.payload,
+            }
+            .send(client_stream, protocol_version)
+            .await?;
+        }
+        ProtocolState::Login | ProtocolState::Transfer => {
+            let reason = vars.render(disconnect);
+            Disconnect::set_reason(TextComponent::from_legacy(&reason))
+                .send(client_stream, protocol_version)
+                .await?;
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Answers a status ping with the last polled status, the server never sees it.
+async fn cached_status(
+    client_stream: &mut TcpStream,
+    status: StatusResponse,
+    protocol_version: i32,
+    config: &Config,
+    vars: &Vars,
+) -> ProxyResult<()> {
+    let limits = config.read_limits(ProtocolState::Status);
+    Packet::read(client_stream, limits)
+        .await?
+        .expect::<StatusRequest>(protocol_version)?;
+    println!("Client STATUS: Status Request (cached)");
+    with_motd_suffix(status, config, vars)
+        .send(client_stream, protocol_version)
+        .await?;
+    let ping = Packet::read(client_stream, limits)
+        .await?
+        .expect::<PingRequest>(protocol_version)?;
+    println!("Client STATUS: Ping Request (exit)");
+    PongResponse {
+        payload: ping.payload,
+    }
+    .send(client_stream, protocol_version)
+    .await?;
+    Ok(())
+}
+
+/// Appends `online_motd_suffix` to the description, unparsable statuses are passed on as they are.
+/// `{players}`, `{max}` and `{version}` come from `status` itself.
+fn with_motd_suffix(status: StatusResponse, config: &Config, vars: &Vars) -> StatusResponse {
+    match status.get_json() {
+        Ok(mut json) => {
+            let mut vars = vars.clone();
+            vars.set("players", json.get_players_online())
+                .set("max", json.get_players_max())
+                .set("version", json.get_version_name());
+            let suffix = vars.render(&config.messages.online_motd_suffix);
+            json.get_description()
+                .append(TextComponent::from_legacy(&suffix));
+            StatusResponse::set_json(json)
+        }
+        Err(err) => {
+            println!("Server STATUS: {}", status.get_string());
+            println!("Server STATUS: {err}... continuing without parsing");
+            status
+        }
+    }
+}
+
+/// Status is strictly request -> response, so one task walks through it in order:
+/// status request, status response (with the proxy motd appended), ping, pong.
+async fn proxy_status(
+    client_stream: &mut TcpStream,
+    server_stream: &mut TcpStream,
+    protocol_version: i32,
+    config: &Config,
+    vars: &Vars,
+) -> ProxyResult<()> {
+    let limits = config.read_limits(ProtocolState::Status);
+    let client_name = |packet: &Packet| {
+        packet.name(
+            ProtocolState::Status,
+            Direction::Serverbound,
+            protocol_version,
+        )
+    };
+    let server_name = |packet: &Packet| {
+        packet.name(
+            ProtocolState::Status,
+            Direction::Clientbound,
+            protocol_version,
+        )
+    };
+    let mut status_req = false;
+    loop {
+        let client_packet = Packet::read(client_stream, limits).await?;
+        let id = Some(client_packet.id.get_int());
+        if id == StatusRequest::id(protocol_version) {
+            if status_req {
+                println!(
+                    "Client STATUS: {} -> Shutdown; status_request spam",
+                    client_name(&client_packet)
+                );
+                return Ok(());
+            }
+            client_packet
+                .expect::<StatusRequest>(protocol_version)?
+                .send(server_stream, protocol_version)
+                .await?;
+            println!("Client STATUS: {}", client_name(&client_packet));
+            status_req = true;
+
+            let server_packet = Packet::read(server_stream, limits).await?;
+            if Some(server_packet.id.get_int()) != StatusResponse::id(protocol_version) {
+                println!("Server STATUS: {}", server_name(&server_packet));
+                server_packet.send_packet(client_stream).await?;
+                continue;
+            }
+            let a = with_motd_suffix(
+                server_packet.expect::<StatusResponse>(protocol_version)?,
+                config,
+                vars,
+            );
+            a.send(client_stream, protocol_version).await?;
+            println!(
+                "Server STATUS: {}\t{}",
+                server_name(&server_packet),
+                a.get_string()
+            );
+        } else if id == PingRequest::id(protocol_version) {
+            println!("Client STATUS: {} (exit)", client_name(&client_packet));
+            client_packet.send_packet(server_stream).await?;
+            let server_packet = Packet::read(server_stream, limits).await?;
+            println!("Server STATUS: {} (exit)", server_name(&server_packet));
+            server_packet.send_packet(client_stream).await?;
+            return Ok(());
+        } else {
+            return Err(ProxyError::UnexpectedPacket {
+                state: ProtocolState::Status,
+                id: client_packet.id.get_int(),
+            });
         }
-    }).unwrap()
+    }
 }
+
 struct ClientConnectionState {
     state: ProtocolState,
     protocol_version: i32,
+    /// From Login Start, `None` until the client sent it (and always for status pings).
+    username: Option<String>,
+    /// Only sent by 1.19.1+ clients.
+    uuid: Option<Uuid>,
+    /// Where the client connected to, from the handshake
+    server_address: String,
+    server_port: u16,
 }
 impl ClientConnectionState {
-    pub fn create(hand: &Handshake) -> Option<Arc<Mutex<ClientConnectionState>>> {
+    pub fn create(hand: &Handshake) -> Option<ClientConnectionState> {
         let state = match hand.get_next_state() {
             1 => ProtocolState::Status,
             2 => ProtocolState::Login,
@@ -367,14 +927,48 @@ impl ClientConnectionState {
                 return None;
             }
         };
-        Some(Arc::new(Mutex::new(ClientConnectionState {
+        Some(ClientConnectionState {
             state,
-            protocol_version: hand.protocol_version.get_int(),
-        })))
+            protocol_version: hand.get_protocol_version(),
+            username: None,
+            uuid: None,
+            server_address: hand.get_server_address(),
+            server_port: hand.get_server_port(),
+        })
+    }
+    pub fn set_player(&mut self, login_start: &LoginStart) {
+        self.username = Some(login_start.get_name());
+        self.uuid = login_start.get_uuid(self.protocol_version);
+    }
+    /// Transferred logins are proxied like fresh ones.
+    pub fn logging_in(&self) -> bool {
+        matches!(self.state, ProtocolState::Login | ProtocolState::Transfer)
+    }
+    /// The placeholders for messages to this client, see `template::Vars`.
+    fn template_vars(&self, backend: Option<&Backend>) -> Vars {
+        let mut vars = match backend {
+            Some(backend) => backend.template_vars(),
+            None => Vars::create(),
+        };
+        vars.set("hostname", Router::normalize(&self.server_address))
+            .set_opt("player", self.username.as_ref())
+            .set_opt("client_version", versions::name(self.protocol_version));
+        vars
+    }
+    /// The username, or `?` before Login Start.
+    pub fn player_name(&self) -> String {
+        self.username.clone().unwrap_or_else(|| "?".to_owned())
+    }
+    /// The username with the uuid, for logs.
+    pub fn player(&self) -> String {
+        match self.uuid {
+            Some(uuid) => format!("{} ({uuid})", self.player_name()),
+            None => self.player_name(),
+        }
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum ProtocolState {
     Handshaking,
     Status,
@@ -384,9 +978,9 @@ pub enum ProtocolState {
     Play,
     ShutDown,
 }
-impl ToString for ProtocolState {
-    fn to_string(&self) -> String {
-        match self {
+impl Display for ProtocolState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
             ProtocolState::Handshaking => "Hanshake",
             ProtocolState::Status => "Status",
             ProtocolState::Login => "Login",
@@ -394,65 +988,378 @@ impl ToString for ProtocolState {
             ProtocolState::Play => "Play",
             ProtocolState::ShutDown => "Shutdown",
             ProtocolState::Transfer => "Transfer",
-        }
-        .to_string()
+        };
+        write!(f, "{name}")
     }
 }
 
-pub enum HandshakingPackets {
-    Handshake,
+/// Moves bytes both ways until either side closes.
+#[cfg(target_os = "linux")]
+async fn spliice(
+    client_stream: &mut TcpStream,
+    server_stream: &mut TcpStream,
+    buf_size: usize,
+    metrics: &Metrics,
+) {
+    let (up, down) = metrics.spliced_bytes();
+    let res = tokio::select! {
+        res = splice_one_way(client_stream, server_stream, buf_size, &up) => ("Client", res),
+        res = splice_one_way(server_stream, client_stream, buf_size, &down) => ("Server", res),
+    };
+    match res {
+        (from, Ok(bytes)) => println!(
+            "{from} PLAY: {:#x} -> Shutdown res == 0 ({bytes} bytes)",
+            -1
+        ),
+        (from, Err(err)) => println!("{from} PLAY: {:#x} -> Shutdown; splice error: {err}", -1),
+    }
 }
 
-pub enum StatusPackets {
-    StatusRequest,
-    PingRequest,
+/// Zero-copy through a pipe: socket -> pipe -> socket, the data never enters userspace.
+/// Returns the amount of bytes moved once `from` hits EOF, `moved` is kept up to date while splicing.
+#[cfg(target_os = "linux")]
+async fn splice_one_way(
+    from: &TcpStream,
+    to: &TcpStream,
+    buf_size: usize,
+    moved: &std::sync::atomic::AtomicU64,
+) -> std::io::Result<u64> {
+    use nix::{
+        fcntl::{splice, OFlag, SpliceFFlags},
+        unistd::pipe2,
+    };
+    use std::{os::fd::AsFd, sync::atomic::Ordering};
+    use tokio::io::Interest;
+
+    let (rd, wr) = pipe2(OFlag::O_CLOEXEC)?;
+    let flags = SpliceFFlags::SPLICE_F_NONBLOCK | SpliceFFlags::SPLICE_F_MOVE;
+    let mut total = 0;
+    loop {
+        from.readable().await?;
+        let res = from.try_io(Interest::READABLE, || {
+            Ok(splice(from.as_fd(), None, &wr, None, buf_size, flags)?)
+        });
+        let mut in_pipe = match res {
+            Ok(0) => return Ok(total),
+            Ok(x) => x,
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        };
+        total += in_pipe as u64;
+        moved.fetch_add(in_pipe as u64, Ordering::Relaxed);
+        while in_pipe > 0 {
+            to.writable().await?;
+            let res = to.try_io(Interest::WRITABLE, || {
+                Ok(splice(&rd, None, to.as_fd(), None, in_pipe, flags)?)
+            });
+            match res {
+                Ok(x) => in_pipe -= x,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
 }
 
-fn spliice(
-    mut server_stream: TcpStream,
-    server_state: Arc<Mutex<ClientConnectionState>>,
-    mut client_stream: TcpStream,
-    client_server_string: String,
+/// Moves bytes both ways until either side closes.
+#[cfg(not(target_os = "linux"))]
+async fn spliice(
+    client_stream: &mut TcpStream,
+    server_stream: &mut TcpStream,
+    buf_size: usize,
+    metrics: &Metrics,
 ) {
-    let (rd, wr) = pipe().unwrap();
-    loop {
-        let res = splice(
-            server_stream.as_fd(),
-            None,
-            wr.try_clone().unwrap(),
-            None,
-            BUF_SIZE,
-            SpliceFFlags::empty(),
-        )
-        .unwrap();
-        if res == 0 {
-            server_state.lock().unwrap().state = ProtocolState::ShutDown;
-            server_stream.shutdown(std::net::Shutdown::Both).ok();
-            client_stream.shutdown(std::net::Shutdown::Both).ok();
+    match tokio::io::copy_bidirectional_with_sizes(client_stream, server_stream, buf_size, buf_size)
+        .await
+    {
+        Ok((to_server, to_client)) => {
+            let (up, down) = metrics.spliced_bytes();
+            up.fetch_add(to_server, std::sync::atomic::Ordering::Relaxed);
+            down.fetch_add(to_client, std::sync::atomic::Ordering::Relaxed);
             println!(
-                "{client_server_string} PLAY: {:#x} -> Shutdown res == 0",
+                "Server PLAY: {:#x} -> Shutdown res == 0 ({to_server} bytes up, {to_client} bytes down)",
                 -1
-            );
-            return;
+            )
         }
-        let _res = splice(
-            rd.try_clone().unwrap(),
-            None,
-            client_stream.as_fd(),
-            None,
-            BUF_SIZE,
-            SpliceFFlags::empty(),
+        Err(err) => println!("Server PLAY: {:#x} -> Shutdown; copy error: {err}", -1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packets::ReadLimits;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A connected client and the proxy's end of it.
+    async fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        (client, accepted)
+    }
+
+    #[tokio::test]
+    async fn spliice_moves_both_ways_until_one_side_closes() {
+        let (mut client, mut proxy_client) = socket_pair().await;
+        let (mut proxy_server, mut server) = socket_pair().await;
+        let metrics = Arc::new(Metrics::create());
+        let proxy = tokio::spawn({
+            let metrics = metrics.clone();
+            async move {
+                spliice(&mut proxy_client, &mut proxy_server, 16, &metrics).await;
+            }
+        });
+
+        let up = vec![7; 1000];
+        client.write_all(&up).await.unwrap();
+        let mut buf = vec![0; up.len()];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, up);
+
+        server.write_all(b"pong").await.unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), proxy)
+            .await
+            .unwrap()
+            .unwrap();
+        let (up, down) = metrics.spliced_bytes();
+        assert_eq!(up.load(std::sync::atomic::Ordering::Relaxed), 1000);
+        assert_eq!(down.load(std::sync::atomic::Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test]
+    async fn cached_status_gets_the_suffix() {
+        let (mut client, mut proxy_client) = socket_pair().await;
+        let config =
+            Config::parse("[messages]\nonline_motd_suffix = \" ({players}/{max} on {hostname})\"")
+                .unwrap();
+        let mut json = StatusStructNew::create();
+        json.description = TextComponent::text("A Minecraft Server");
+        json.players.online = 3;
+        json.players.max = 20;
+        let status = StatusResponse::set_json(Box::new(json));
+        let mut vars = Vars::create();
+        vars.set("hostname", "mc.example.com");
+        let proxy = tokio::spawn(async move {
+            cached_status(&mut proxy_client, status, 767, &config, &vars).await
+        });
+
+        Packet::from_bytes(0, Vec::new())
+            .send_packet(&mut client)
+            .await
+            .unwrap();
+        let limits = ReadLimits {
+            timeout: Duration::from_secs(5),
+            max_size: 1024,
+        };
+        let response = Packet::read(&mut client, limits)
+            .await
+            .unwrap()
+            .expect::<StatusResponse>(767)
+            .unwrap();
+        let mut json = response.get_json().unwrap();
+        assert_eq!(
+            json.get_description().to_plain(),
+            "A Minecraft Server (3/20 on mc.example.com)"
+        );
+
+        let ping = Packet::from_bytes(1, 42i64.to_be_bytes().to_vec());
+        ping.send_packet(&mut client).await.unwrap();
+        let pong = Packet::read(&mut client, limits).await.unwrap();
+        assert_eq!(pong.all, ping.all);
+        proxy.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn unsupported_versions_are_told_the_supported_ones() {
+        let (mut client, mut proxy_client) = socket_pair().await;
+        let config = Config::parse("[versions]\nmin = \"1.20.5\"").unwrap();
+        let server_state = ClientConnectionState {
+            state: ProtocolState::Login,
+            protocol_version: 760,
+            username: None,
+            uuid: None,
+            server_address: "mc.example.com".to_owned(),
+            server_port: 25565,
+        };
+        let messages = config.messages.clone();
+        answer_without_backend(
+            &mut proxy_client,
+            &server_state,
+            &messages.unsupported_motd,
+            &messages.disconnect_unsupported_version,
+            &config,
         )
+        .await
         .unwrap();
-        if _res == 0 {
-            server_state.lock().unwrap().state = ProtocolState::ShutDown;
-            server_stream.shutdown(std::net::Shutdown::Both).ok();
-            client_stream.shutdown(std::net::Shutdown::Both).ok();
-            println!(
-                "{client_server_string} PLAY: {:#x} -> Shutdown res == 0",
-                -1
+        let limits = ReadLimits {
+            timeout: Duration::from_secs(5),
+            max_size: 1024,
+        };
+        let disconnect = Packet::read(&mut client, limits)
+            .await
+            .unwrap()
+            .expect::<Disconnect>(760)
+            .unwrap();
+        assert_eq!(
+            disconnect.reason.0.to_plain(),
+            "Please use Minecraft 1.20.5+, not 1.19.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn over_the_limit_logins_are_disconnected() {
+        let config =
+            Config::parse("[rate_limit]\nconnections_per_ip = { burst = 1, per_minute = 1 }")
+                .unwrap();
+        let proxy = Arc::new(Proxy {
+            router: Router::create(&config),
+            rate_limiter: RateLimiter::create(&config.rate_limit),
+            metrics: Metrics::create(),
+            sessions: SessionTable::create(),
+            config,
+        });
+        let addr: SocketAddr = "10.0.0.1:50000".parse().unwrap();
+        let _first = proxy.rate_limiter.admit(addr.ip()).unwrap();
+        assert!(proxy.rate_limiter.admit(addr.ip()).is_err());
+
+        let limits = ReadLimits {
+            timeout: Duration::from_secs(5),
+            max_size: 1024,
+        };
+        for next_state in [2, 3] {
+            let (mut client, proxy_client) = tokio::io::duplex(1024);
+            handle_rejected(
+                proxy.clone(),
+                proxy_client,
+                addr,
+                proxy.rate_limiter.rejecter().unwrap(),
+            );
+            let handshake = Handshake::create(
+                types::VarInt::from(767),
+                types::VarString::from("mc.example.com".to_owned()),
+                25565,
+                types::VarInt::from(next_state),
+            );
+            handshake.send(&mut client, 767).await.unwrap();
+            let disconnect = Packet::read(&mut client, limits)
+                .await
+                .unwrap()
+                .expect::<Disconnect>(767)
+                .unwrap();
+            assert_eq!(
+                disconnect.reason.0.to_plain(),
+                "Too many connections, try again in a minute"
             );
-            return;
         }
+
+        // Status pings get the canned status
+        let (mut client, proxy_client) = tokio::io::duplex(1024);
+        handle_rejected(
+            proxy.clone(),
+            proxy_client,
+            addr,
+            proxy.rate_limiter.rejecter().unwrap(),
+        );
+        let handshake = Handshake::create(
+            types::VarInt::from(767),
+            types::VarString::from("mc.example.com".to_owned()),
+            25565,
+            types::VarInt::from(1),
+        );
+        handshake.send(&mut client, 767).await.unwrap();
+        StatusRequest {}.send(&mut client, 767).await.unwrap();
+        let response = Packet::read(&mut client, limits)
+            .await
+            .unwrap()
+            .expect::<StatusResponse>(767)
+            .unwrap();
+        assert_eq!(
+            response.get_json().unwrap().get_description().to_plain(),
+            "Too many requests, slow down"
+        );
+    }
+
+    #[tokio::test]
+    async fn ready_but_refusing_servers_disconnect_instead_of_the_limbo() {
+        let script =
+            std::env::temp_dir().join(format!("mc-proxy-test-{}-refusing.sh", std::process::id()));
+        std::fs::write(
+            &script,
+            "echo '[12:00:00] [Server thread/INFO]: Done (1.0s)! For help, type \"help\"' >&2\n\
+             read line",
+        )
+        .unwrap();
+        let mut config = Config::parse(
+            "[console]\nmirror = false\n[messages]\ndisconnect_already_starting = \"{state}\"",
+        )
+        .unwrap();
+        config.backends[0].address = "127.0.0.1:1".to_owned();
+        config.backends[0].start_command = script.to_string_lossy().into_owned();
+        let proxy = Proxy {
+            router: Router::create(&config),
+            rate_limiter: RateLimiter::create(&config.rate_limit),
+            metrics: Metrics::create(),
+            sessions: SessionTable::create(),
+            config,
+        };
+        let backend = proxy.router.backends()[0].clone();
+        backend.handler.lock().unwrap().wake().unwrap();
+        for _ in 0..500 {
+            if backend.handler.lock().unwrap().state() == ServerState::Ready {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(backend.handler.lock().unwrap().state(), ServerState::Ready);
+
+        let (mut client, mut proxy_client) = socket_pair().await;
+        let server_state = ClientConnectionState {
+            state: ProtocolState::Login,
+            protocol_version: 767,
+            username: Some("Notch".to_owned()),
+            uuid: None,
+            server_address: "mc.example.com".to_owned(),
+            server_port: 25565,
+        };
+        let mut session = proxy.sessions.register(
+            proxy_client.peer_addr().unwrap(),
+            CancellationToken::new(),
+            proxy.metrics.connected(),
+        );
+        handle_offline(
+            &mut proxy_client,
+            &server_state,
+            &backend,
+            &proxy,
+            &mut session,
+        )
+        .await
+        .unwrap();
+        let limits = ReadLimits {
+            timeout: Duration::from_secs(5),
+            max_size: 1024,
+        };
+        let disconnect = Packet::read(&mut client, limits)
+            .await
+            .unwrap()
+            .expect::<Disconnect>(767)
+            .unwrap();
+        assert_eq!(disconnect.reason.0.to_plain(), "ready");
+        backend
+            .handler
+            .lock()
+            .unwrap()
+            .kill_minecraft_server()
+            .unwrap();
+        std::fs::remove_file(script).ok();
     }
 }
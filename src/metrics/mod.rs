@@ -0,0 +1,401 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    http::{self, Request, Response},
+    mincraft_server::ServerState,
+    ratelimit::Rejection,
+    ProtocolState, Proxy,
+};
+
+/// The states a session is counted in, spliced logins count as `Play`
+/// since the proxy can't see their state anymore.
+const SESSION_STATES: [ProtocolState; 6] = [
+    ProtocolState::Handshaking,
+    ProtocolState::Status,
+    ProtocolState::Login,
+    ProtocolState::Transfer,
+    ProtocolState::Configuration,
+    ProtocolState::Play,
+];
+
+/// Counters the connection tasks update, the rest is read from the proxy when scraped.
+pub struct Metrics {
+    sessions: Arc<[AtomicI64; SESSION_STATES.len()]>,
+    connections: AtomicU64,
+    rejected_unknown_host: AtomicU64,
+    rejected_not_allowed: AtomicU64,
+    rejected_unsupported_version: AtomicU64,
+    /// Bytes spliced from the client to the server
+    bytes_up: Arc<AtomicU64>,
+    /// Bytes spliced from the server to the client
+    bytes_down: Arc<AtomicU64>,
+}
+
+/// Counts its session in one state until it's moved or dropped.
+pub struct SessionMetrics {
+    sessions: Arc<[AtomicI64; SESSION_STATES.len()]>,
+    index: usize,
+}
+
+impl SessionMetrics {
+    pub fn set_state(&mut self, state: ProtocolState) {
+        let index = state_index(state);
+        self.sessions[self.index].fetch_sub(1, Ordering::Relaxed);
+        self.sessions[index].fetch_add(1, Ordering::Relaxed);
+        self.index = index;
+    }
+}
+
+impl Drop for SessionMetrics {
+    fn drop(&mut self) {
+        self.sessions[self.index].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn state_index(state: ProtocolState) -> usize {
+    SESSION_STATES
+        .iter()
+        .position(|x| *x == state)
+        .unwrap_or(SESSION_STATES.len() - 1)
+}
+
+pub fn state_label(state: ProtocolState) -> &'static str {
+    match state {
+        ProtocolState::Handshaking => "handshaking",
+        ProtocolState::Status => "status",
+        ProtocolState::Login => "login",
+        ProtocolState::Transfer => "transfer",
+        ProtocolState::Configuration => "configuration",
+        ProtocolState::Play | ProtocolState::ShutDown => "play",
+    }
+}
+
+impl Metrics {
+    pub fn create() -> Metrics {
+        Metrics {
+            sessions: Arc::new(Default::default()),
+            connections: AtomicU64::new(0),
+            rejected_unknown_host: AtomicU64::new(0),
+            rejected_not_allowed: AtomicU64::new(0),
+            rejected_unsupported_version: AtomicU64::new(0),
+            bytes_up: Arc::new(AtomicU64::new(0)),
+            bytes_down: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Counts every accepted connection, before the rate limits are checked.
+    pub fn accepted(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a new session, which starts out handshaking.
+    pub fn connected(&self) -> SessionMetrics {
+        let index = state_index(ProtocolState::Handshaking);
+        self.sessions[index].fetch_add(1, Ordering::Relaxed);
+        SessionMetrics {
+            sessions: self.sessions.clone(),
+            index,
+        }
+    }
+
+    pub fn rejected_unknown_host(&self) {
+        self.rejected_unknown_host.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected_not_allowed(&self) {
+        self.rejected_not_allowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected_unsupported_version(&self) {
+        self.rejected_unsupported_version
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The (client -> server, server -> client) counters `splice` adds to.
+    pub fn spliced_bytes(&self) -> (Arc<AtomicU64>, Arc<AtomicU64>) {
+        (self.bytes_up.clone(), self.bytes_down.clone())
+    }
+}
+
+/// Serves `/metrics` in the prometheus text format.
+pub async fn serve(listener: TcpListener, proxy: Arc<Proxy>, shutdown: CancellationToken) {
+    http::serve(listener, shutdown, "METRICS", move |request: Request| {
+        let proxy = proxy.clone();
+        async move {
+            match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/metrics") => Response::create(
+                    200,
+                    "text/plain; version=0.0.4; charset=utf-8",
+                    render(&proxy),
+                ),
+                (_, "/metrics") => Response::text(405, "only GET\n"),
+                _ => Response::text(404, "not found, try /metrics\n"),
+            }
+        }
+    })
+    .await
+}
+
+fn render(proxy: &Proxy) -> String {
+    let metrics = &proxy.metrics;
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "mc_proxy_sessions",
+        "gauge",
+        "Open client connections by protocol state",
+    );
+    for (state, count) in SESSION_STATES.iter().zip(metrics.sessions.iter()) {
+        let count = count.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "mc_proxy_sessions{{state=\"{}\"}} {count}",
+            state_label(*state)
+        )
+        .ok();
+    }
+
+    header(
+        &mut out,
+        "mc_proxy_connections_total",
+        "counter",
+        "Accepted client connections, the rate limited ones included",
+    );
+    writeln!(
+        out,
+        "mc_proxy_connections_total {}",
+        metrics.connections.load(Ordering::Relaxed)
+    )
+    .ok();
+
+    header(
+        &mut out,
+        "mc_proxy_rejections_total",
+        "counter",
+        "Client connections turned away, by reason",
+    );
+    let rate_limiter = &proxy.rate_limiter;
+    for (reason, count) in [
+        (
+            "rate_limit_connections",
+            rate_limiter.rejected(Rejection::Connections),
+        ),
+        (
+            "rate_limit_status",
+            rate_limiter.rejected(Rejection::Status),
+        ),
+        (
+            "rate_limit_sessions",
+            rate_limiter.rejected(Rejection::Sessions),
+        ),
+        (
+            "unknown_host",
+            metrics.rejected_unknown_host.load(Ordering::Relaxed),
+        ),
+        (
+            "not_allowed",
+            metrics.rejected_not_allowed.load(Ordering::Relaxed),
+        ),
+        (
+            "unsupported_version",
+            metrics.rejected_unsupported_version.load(Ordering::Relaxed),
+        ),
+    ] {
+        writeln!(
+            out,
+            "mc_proxy_rejections_total{{reason=\"{reason}\"}} {count}"
+        )
+        .ok();
+    }
+
+    header(
+        &mut out,
+        "mc_proxy_spliced_bytes_total",
+        "counter",
+        "Bytes moved between clients and servers after login",
+    );
+    for (direction, count) in [
+        ("client_to_server", &metrics.bytes_up),
+        ("server_to_client", &metrics.bytes_down),
+    ] {
+        let count = count.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "mc_proxy_spliced_bytes_total{{direction=\"{direction}\"}} {count}"
+        )
+        .ok();
+    }
+
+    let backends: Vec<_> = proxy
+        .router
+        .backends()
+        .iter()
+        .map(|backend| {
+            let stats = backend.handler.lock().unwrap().stats();
+            (escape(&backend.config.name), stats)
+        })
+        .collect();
+
+    header(
+        &mut out,
+        "mc_proxy_server_state",
+        "gauge",
+        "1 for the state the minecraft server is in",
+    );
+    for (name, stats) in &backends {
+        for state in ServerState::ALL {
+            let value = u8::from(stats.state == state);
+            writeln!(
+                out,
+                "mc_proxy_server_state{{backend=\"{name}\",state=\"{state}\"}} {value}"
+            )
+            .ok();
+        }
+    }
+
+    header(
+        &mut out,
+        "mc_proxy_server_starts_total",
+        "counter",
+        "Times the proxy started the minecraft server",
+    );
+    for (name, stats) in &backends {
+        writeln!(
+            out,
+            "mc_proxy_server_starts_total{{backend=\"{name}\"}} {}",
+            stats.starts
+        )
+        .ok();
+    }
+
+    header(
+        &mut out,
+        "mc_proxy_server_time_to_ready_seconds",
+        "gauge",
+        "Seconds from the last start until the server was ready, by its console or a status poll",
+    );
+    for (name, stats) in &backends {
+        if let Some(ready_after) = stats.ready_after {
+            writeln!(
+                out,
+                "mc_proxy_server_time_to_ready_seconds{{backend=\"{name}\"}} {}",
+                ready_after.as_secs_f64()
+            )
+            .ok();
+        }
+    }
+
+    header(
+        &mut out,
+        "mc_proxy_server_players_online",
+        "gauge",
+        "Player count from the last status poll",
+    );
+    for (name, stats) in &backends {
+        if let Some(players_online) = stats.players_online {
+            writeln!(
+                out,
+                "mc_proxy_server_players_online{{backend=\"{name}\"}} {players_online}"
+            )
+            .ok();
+        }
+    }
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").ok();
+    writeln!(out, "# TYPE {name} {kind}").ok();
+}
+
+/// Label values are quoted, so `\`, `"` and newlines need escaping.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, ratelimit::RateLimiter, router::Router, sessions::SessionTable};
+
+    fn proxy(config: &str) -> Proxy {
+        let config = Config::parse(config).unwrap();
+        Proxy {
+            router: Router::create(&config),
+            rate_limiter: RateLimiter::create(&config.rate_limit),
+            metrics: Metrics::create(),
+            sessions: SessionTable::create(),
+            config,
+        }
+    }
+
+    #[test]
+    fn sessions_follow_their_state() {
+        let proxy = proxy("");
+        let mut session = proxy.metrics.connected();
+        let _other = proxy.metrics.connected();
+        session.set_state(ProtocolState::Login);
+        let out = render(&proxy);
+        assert!(out.contains("mc_proxy_sessions{state=\"handshaking\"} 1\n"));
+        assert!(out.contains("mc_proxy_sessions{state=\"login\"} 1\n"));
+        assert!(out.contains("mc_proxy_connections_total 0\n"));
+        drop(session);
+        assert!(render(&proxy).contains("mc_proxy_sessions{state=\"login\"} 0\n"));
+    }
+
+    #[test]
+    fn rate_limited_connections_count_too() {
+        let proxy = proxy("[rate_limit]\nconnections_per_ip = { burst = 1, per_minute = 1 }");
+        let ip = "10.0.0.1".parse().unwrap();
+        for _ in 0..3 {
+            proxy.metrics.accepted();
+            proxy.rate_limiter.admit(ip).ok();
+        }
+        let out = render(&proxy);
+        assert!(out.contains("mc_proxy_connections_total 3\n"));
+        assert!(out.contains("mc_proxy_rejections_total{reason=\"rate_limit_connections\"} 2\n"));
+    }
+
+    #[test]
+    fn rejections_and_bytes() {
+        let proxy = proxy("");
+        proxy.metrics.rejected_unknown_host();
+        proxy.metrics.rejected_not_allowed();
+        proxy.metrics.rejected_not_allowed();
+        let (up, _) = proxy.metrics.spliced_bytes();
+        up.fetch_add(1234, Ordering::Relaxed);
+        let out = render(&proxy);
+        assert!(out.contains("mc_proxy_rejections_total{reason=\"unknown_host\"} 1\n"));
+        assert!(out.contains("mc_proxy_rejections_total{reason=\"not_allowed\"} 2\n"));
+        assert!(out.contains("mc_proxy_spliced_bytes_total{direction=\"client_to_server\"} 1234\n"));
+        assert!(out.contains("mc_proxy_spliced_bytes_total{direction=\"server_to_client\"} 0\n"));
+    }
+
+    #[test]
+    fn one_series_per_backend() {
+        let proxy = proxy(
+            "[[backends]]\nname = \"a\\\"b\"\nhostnames = [\"a\"]\n[[backends]]\nname = \"c\"\nhostnames = [\"*\"]",
+        );
+        let out = render(&proxy);
+        assert!(out.contains("mc_proxy_server_state{backend=\"a\\\"b\",state=\"stopped\"} 1\n"));
+        assert!(out.contains("mc_proxy_server_state{backend=\"c\",state=\"ready\"} 0\n"));
+        assert!(out.contains("mc_proxy_server_starts_total{backend=\"c\"} 0\n"));
+        // Nothing polled yet
+        assert!(!out.contains("mc_proxy_server_players_online{"));
+        // Every metric is announced once
+        assert_eq!(out.matches("# TYPE mc_proxy_server_state gauge").count(), 1);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read},
+    sync::{Arc, Mutex},
+};
+
+use regex::Regex;
+
+use crate::config::ConsoleConfig;
+
+use super::MinecraftServer;
+
+/// Reads what the server prints: mirrors it, keeps the last lines and watches for readiness and progress.
+#[derive(Clone)]
+pub struct ConsoleWatch {
+    /// Prefixes the mirrored lines
+    name: String,
+    /// `None` only if the pattern didn't compile, which `Config::validate` rules out
+    ready: Option<Regex>,
+    progress: Option<Regex>,
+    history_lines: usize,
+    mirror: bool,
+}
+
+/// The last `history_lines` lines of output.
+pub struct ConsoleHistory {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl ConsoleHistory {
+    pub fn create(capacity: usize) -> ConsoleHistory {
+        ConsoleHistory {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.iter().cloned().collect()
+    }
+}
+
+impl ConsoleWatch {
+    pub fn create(name: String, config: &ConsoleConfig) -> ConsoleWatch {
+        ConsoleWatch {
+            name,
+            ready: Regex::new(&config.ready_pattern).ok(),
+            progress: Regex::new(&config.progress_pattern).ok(),
+            history_lines: config.history_lines,
+            mirror: config.mirror,
+        }
+    }
+
+    pub fn history(&self) -> ConsoleHistory {
+        ConsoleHistory::create(self.history_lines)
+    }
+
+    /// Reads `output` line by line on its own thread until the process closes it.
+    pub fn watch(
+        &self,
+        output: impl Read + Send + 'static,
+        server: Arc<Mutex<MinecraftServer>>,
+    ) -> std::io::Result<()> {
+        let watch = self.clone();
+        std::thread::Builder::new()
+            .name("Minecraft server console thread".to_string())
+            .spawn(move || {
+                let mut reader = BufReader::new(output);
+                let mut buf = Vec::new();
+                loop {
+                    buf.clear();
+                    match reader.read_until(b'\n', &mut buf) {
+                        Ok(0) => return,
+                        Ok(_) => (),
+                        Err(err) => {
+                            println!("PROXY: can't read the console of {}: {err}", watch.name);
+                            return;
+                        }
+                    }
+                    // Not every server (or plugin) prints valid utf-8
+                    let line = String::from_utf8_lossy(&buf).trim_end().to_owned();
+                    watch.line(line, &server);
+                }
+            })
+            .map(|_| ())
+    }
+
+    fn line(&self, line: String, server: &Mutex<MinecraftServer>) {
+        if self.mirror {
+            println!("{} | {line}", self.name);
+        }
+        let ready = self.ready.as_ref().is_some_and(|x| x.is_match(&line));
+        let progress = self.progress.as_ref().and_then(|x| {
+            let captures = x.captures(&line)?;
+            match captures.name("percent") {
+                Some(percent) => Some(format!("{}%", percent.as_str())),
+                None => Some(captures.get(0)?.as_str().to_owned()),
+            }
+        });
+        let mut server = server.lock().unwrap();
+        if ready {
+            server.mark_ready("console");
+        } else if let Some(progress) = progress {
+            server.progress = Some(progress);
+        }
+        server.history.push(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_the_last_lines() {
+        let mut history = ConsoleHistory::create(2);
+        for line in ["one", "two", "three"] {
+            history.push(line.to_owned());
+        }
+        assert_eq!(history.lines(), ["two", "three"]);
+
+        let mut history = ConsoleHistory::create(0);
+        history.push("one".to_owned());
+        assert!(history.lines().is_empty());
+    }
+}
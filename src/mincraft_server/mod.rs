@@ -1,188 +1,914 @@
 use std::{
+    collections::VecDeque,
+    fmt::Display,
     io::Write,
-    net::{TcpListener, TcpStream},
+    os::unix::process::CommandExt,
+    path::PathBuf,
     process::{ChildStdin, Command, Stdio},
-    sync::{Arc, Mutex},
-    thread::{self},
-    time,
+    sync::{Arc, Mutex, Weak},
+    time::{self, Duration, Instant},
 };
 
+use tokio::net::TcpStream;
+
 use crate::{
-    packets::{self, clientbound::status::StatusTrait, SendPacket},
+    config::{BackendConfig, Config, LifecycleConfig},
+    error::{ProxyError, ProxyResult},
+    packets::{
+        self,
+        clientbound::status::{StatusResponse, StatusStructNew, StatusTrait},
+        registry::Registered,
+        serverbound::{handshake::Handshake, status::StatusRequest},
+        ReadLimits,
+    },
     types::*,
+    ProtocolState,
 };
 
+mod console;
+
+use console::{ConsoleHistory, ConsoleWatch};
+
+/// Where the server process is in its life, driven by process events and status probes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerState {
+    /// Never started, or exited after a stop
+    Stopped,
+    /// Spawned, but hasn't answered a status probe yet
+    Starting,
+    /// Answered a status probe
+    Ready,
+    /// `stop` was sent (or the process was killed), waiting for it to exit
+    Stopping,
+    /// Exited with an error without being asked to
+    Crashed,
+}
+
+impl ServerState {
+    pub const ALL: [ServerState; 5] = [
+        ServerState::Stopped,
+        ServerState::Starting,
+        ServerState::Ready,
+        ServerState::Stopping,
+        ServerState::Crashed,
+    ];
+
+    /// Whether there is a process
+    pub fn is_alive(self) -> bool {
+        matches!(
+            self,
+            ServerState::Starting | ServerState::Ready | ServerState::Stopping
+        )
+    }
+}
+
+impl Display for ServerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ServerState::Stopped => "stopped",
+            ServerState::Starting => "starting",
+            ServerState::Ready => "ready",
+            ServerState::Stopping => "stopping",
+            ServerState::Crashed => "crashed",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How long the last few starts took until ready, shared by every run of a server.
+#[derive(Clone, Default)]
+pub struct StartTimes(Arc<Mutex<VecDeque<Duration>>>);
+
+impl StartTimes {
+    const KEPT: usize = 10;
+
+    fn record(&self, took: Duration) {
+        let mut times = self.0.lock().unwrap();
+        if times.len() == StartTimes::KEPT {
+            times.pop_front();
+        }
+        times.push_back(took);
+    }
+
+    /// The average of the recorded starts, `None` before the first one finished.
+    pub fn expected(&self) -> Option<Duration> {
+        let times = self.0.lock().unwrap();
+        let total: Duration = times.iter().sum();
+        total.checked_div(times.len() as u32)
+    }
+}
+
+/// Where a starting server is at, for the status reply.
+pub struct StartProgress {
+    /// What the console last reported, e.g. `47%`
+    pub progress: Option<String>,
+    pub elapsed: Duration,
+    /// `None` without earlier starts to go by
+    pub eta: Option<Duration>,
+}
+
+impl StartProgress {
+    /// `~1m 05s`, or `any moment now` once the usual start time passed.
+    pub fn eta_text(&self) -> Option<String> {
+        match self.eta? {
+            eta if eta < Duration::from_secs(1) => Some("any moment now".to_owned()),
+            eta => Some(format!("~{}", format_duration(eta))),
+        }
+    }
+}
+
+/// `1m 05s` or `42s`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
 pub struct MinecraftServer {
     mc_server_stdin: ChildStdin,
+    /// Also the process group of everything the start command spawned
+    pid: u32,
     /// The amount of seconds since the server has no players online.
     shutdown_timer: u64,
-    running: bool,
+    state: ServerState,
     addr: String,
+    started: Instant,
+    /// How long after `started` the server became ready
+    ready_after: Option<Duration>,
+    /// From the last successful poll
+    players_online: Option<i32>,
+    /// The last start progress the console reported
+    progress: Option<String>,
+    /// The last status the polling got and when
+    status: Option<(StatusResponse, Instant)>,
+    history: ConsoleHistory,
+    start_times: StartTimes,
 }
 
 impl MinecraftServer {
-    pub fn spawn(start_command: String, addr: String) -> Option<Arc<Mutex<MinecraftServer>>> {
-        let mut cmd = match Command::new("bash")
+    /// `on_exit` runs on the wait thread once the process exited and the state is updated.
+    pub fn spawn(
+        start_command: String,
+        addr: String,
+        console: &ConsoleWatch,
+        start_times: StartTimes,
+        on_exit: impl FnOnce() + Send + 'static,
+    ) -> ProxyResult<Arc<Mutex<MinecraftServer>>> {
+        let mut cmd = Command::new("bash")
             .arg(start_command)
             // .arg("ssh://root@elaina.tami.moe")
             .stdin(Stdio::piped())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // So a kill reaches the java process behind the start script too
+            .process_group(0)
             .spawn()
-        {
-            Ok(it) => it,
-            Err(err) => return None,
-        };
+            .map_err(|err| ProxyError::ServerProcess(format!("can't run start command: {err}")))?;
+        let (mc_server_stdin, stdout, stderr) =
+            match (cmd.stdin.take(), cmd.stdout.take(), cmd.stderr.take()) {
+                (Some(stdin), Some(stdout), Some(stderr)) => (stdin, stdout, stderr),
+                _ => {
+                    cmd.kill().ok();
+                    return Err(ProxyError::ServerProcess("missing stdio pipe".to_owned()));
+                }
+            };
 
         let selfo = Arc::new(Mutex::new(MinecraftServer {
-            mc_server_stdin: cmd.stdin.take().unwrap(),
+            mc_server_stdin,
+            pid: cmd.id(),
             shutdown_timer: 0,
-            running: true,
+            state: ServerState::Starting,
             addr,
+            started: Instant::now(),
+            ready_after: None,
+            players_online: None,
+            progress: None,
+            status: None,
+            history: console.history(),
+            start_times,
         }));
 
+        // A full pipe would block the server, so the output is read even without a mirror
+        for output in [
+            Box::new(stdout) as Box<dyn std::io::Read + Send>,
+            Box::new(stderr),
+        ] {
+            if let Err(err) = console.watch(output, selfo.clone()) {
+                cmd.kill().ok();
+                return Err(ProxyError::ServerProcess(format!(
+                    "can't spawn console thread: {err}"
+                )));
+            }
+        }
+
         // Register callback for when the server stops
         let callback_clone = selfo.clone();
         std::thread::Builder::new()
             .name("Minecraft server callback thread".to_string())
             .spawn(move || {
-                cmd.wait().unwrap();
-                callback_clone.lock().unwrap().running = false;
-            })
-            .unwrap();
-        return Some(selfo);
-    }
-    pub fn query_server(&self) -> Option<Box<dyn StatusTrait>> {
-        match TcpStream::connect(self.addr.clone()) {
-            //TODO: fixx this ok part
-            Ok(mut stream_server) => {
-                let handshake = packets::serverbound::handshake::Handshake::create(
-                    VarInt::from(746)?,
-                    VarString::from(self.addr.clone()),
-                    UShort::from(1234),
-                    VarInt::from(1)?,
-                )?;
-                handshake.send_packet(&mut stream_server).ok()?;
-                let status_rq = packets::Packet::from_bytes(0, Vec::new())?;
-                status_rq.send_packet(&mut stream_server).ok()?;
-                let return_packet = packets::Packet::parse(&mut stream_server)?;
-                let status_response =
-                    packets::clientbound::status::StatusResponse::parse(return_packet).unwrap();
-
-                return status_response.get_json();
-            }
-            Err(_) => None,
-        }
-    }
-    pub fn stop(&mut self) -> Option<()> {
-        self.send_command("stop".to_owned())
-    }
-    pub fn send_command(&mut self, mut command: String) -> Option<()> {
-        command.push_str("\n");
-        self.mc_server_stdin.write_all(command.as_bytes()).ok()?;
-        Some(())
-    }
-
-    fn shutdown_if_offline(&mut self, frequency: u64, timeout: u64, grace_period: u64) -> bool {
-        if self.running {
-            match self.query_server() {
-                Some(pl_online) => {
-                    if pl_online.get_players_online() == 0 {
-                        if self.shutdown_timer >= (timeout + grace_period) {
-                            self.stop();
-                            println!("PROXY: polling: server is empty; Shutting down");
-                            self.shutdown_timer = 0;
-                            return true;
-                        } else {
-                            self.shutdown_timer += frequency;
-                            return false;
-                        }
+                let success = match cmd.wait() {
+                    Ok(status) => {
+                        println!("PROXY: minecraft server exited: {status}");
+                        status.success()
+                    }
+                    Err(err) => {
+                        println!("PROXY: can't wait for the minecraft server: {err}");
+                        false
+                    }
+                };
+                {
+                    let mut server = callback_clone.lock().unwrap();
+                    if server.state == ServerState::Stopping || success {
+                        server.set_state(ServerState::Stopped);
                     } else {
-                        self.shutdown_timer = grace_period;
-                        return false;
+                        server.set_state(ServerState::Crashed);
                     }
                 }
-                None => {
-                    println!("PROXY: polling: unable to connect to server. Maybe it starting?");
-                    return false;
-                }
-            };
-        } else {
+                on_exit();
+            })
+            .map_err(|err| ProxyError::ServerProcess(format!("can't spawn wait thread: {err}")))?;
+        Ok(selfo)
+    }
+    /// Not a method, so the server doesn't have to stay locked while waiting on the network.
+    pub async fn query_server(addr: String, limits: ReadLimits) -> ProxyResult<StatusResponse> {
+        let mut stream_server = TcpStream::connect(addr.clone())
+            .await
+            .map_err(|err| ProxyError::BackendUnavailable(format!("{addr}: {err}")))?;
+        // What a client connecting to `addr` would send
+        let (host, port) = match addr.rsplit_once(':') {
+            Some((host, port)) => (host.to_owned(), port.parse().unwrap_or_default()),
+            None => (addr.clone(), 25565),
+        };
+        // Any version answers a status request
+        let protocol_version = 746;
+        Handshake::create(
+            VarInt::from(protocol_version),
+            VarString::from(host),
+            port,
+            VarInt::from(1),
+        )
+        .send(&mut stream_server, protocol_version)
+        .await?;
+        StatusRequest::default()
+            .send(&mut stream_server, protocol_version)
+            .await?;
+        packets::Packet::read(&mut stream_server, limits)
+            .await?
+            .expect::<StatusResponse>(protocol_version)
+    }
+    pub fn stop(&mut self) -> ProxyResult<()> {
+        self.send_command("stop".to_owned())?;
+        self.set_state(ServerState::Stopping);
+        Ok(())
+    }
+    /// SIGKILLs the whole process group, for when `stop` doesn't work.
+    pub fn kill(&mut self) -> ProxyResult<()> {
+        use nix::{
+            sys::signal::{killpg, Signal},
+            unistd::Pid,
+        };
+        killpg(Pid::from_raw(self.pid as i32), Signal::SIGKILL)
+            .map_err(|err| ProxyError::ServerProcess(format!("can't kill the server: {err}")))?;
+        self.set_state(ServerState::Stopping);
+        Ok(())
+    }
+    /// The first of the console and the status probe to notice decides when the server was ready.
+    fn mark_ready(&mut self, by: &str) {
+        if self.state != ServerState::Starting {
+            return;
+        }
+        let ready_after = self.started.elapsed();
+        println!("PROXY: {by}: server is ready after {ready_after:?}");
+        self.ready_after = Some(ready_after);
+        self.start_times.record(ready_after);
+        self.progress = None;
+        self.set_state(ServerState::Ready);
+    }
+    fn set_state(&mut self, state: ServerState) {
+        if self.state != state {
+            println!("PROXY: server {}: {} -> {state}", self.addr, self.state);
+            self.state = state;
+        }
+    }
+    pub fn send_command(&mut self, mut command: String) -> ProxyResult<()> {
+        command.push('\n');
+        self.mc_server_stdin
+            .write_all(command.as_bytes())
+            .map_err(|err| ProxyError::ServerProcess(format!("can't write to stdin: {err}")))
+    }
+
+    /// `status` is the result of `query_server` from this polling round.
+    fn shutdown_if_offline(
+        &mut self,
+        status: ProxyResult<Box<dyn StatusTrait>>,
+        frequency: u64,
+        timeout: u64,
+        grace_period: u64,
+    ) -> bool {
+        if !self.state.is_alive() {
             println!("PROXY: polling: server is offline; stopping polling");
             return true;
         }
+        match status {
+            // Already on its way out, the exit is what ends the polling
+            Ok(_) if self.state == ServerState::Stopping => false,
+            Ok(pl_online) => {
+                self.mark_ready("polling");
+                self.players_online = Some(pl_online.get_players_online());
+                if pl_online.get_players_online() == 0 {
+                    if self.shutdown_timer >= (timeout + grace_period) {
+                        if let Err(err) = self.stop() {
+                            println!("PROXY: polling: can't stop the server: {err}");
+                        }
+                        println!("PROXY: polling: server is empty; Shutting down");
+                        self.shutdown_timer = 0;
+                        true
+                    } else {
+                        self.shutdown_timer += frequency;
+                        false
+                    }
+                } else {
+                    self.shutdown_timer = grace_period;
+                    false
+                }
+            }
+            Err(err) => {
+                println!("PROXY: polling: unable to query server ({err}). Maybe it starting?");
+                false
+            }
+        }
     }
 }
 
 pub struct MinecraftServerHandler {
+    console: ConsoleWatch,
+    start_times: StartTimes,
     start_command: String,
     pub addr: String,
+    lifecycle: LifecycleConfig,
+    /// Used when polling the server for its status
+    read_limits: ReadLimits,
+    /// Where the polling saves the last status
+    status_file: Option<PathBuf>,
+    /// The status saved by an earlier run, until this one polled its own
+    saved_status: Option<String>,
     server: Option<Arc<Mutex<MinecraftServer>>>,
+    /// How many times the proxy started the server
+    starts: u64,
+    /// Start again once the stopping server exited
+    restart_queued: bool,
+    /// For the exit callback of the server process
+    this: Weak<Mutex<MinecraftServerHandler>>,
 }
+
+/// A snapshot of the server for metrics and the admin interfaces.
+pub struct ServerStats {
+    pub state: ServerState,
+    pub starts: u64,
+    /// `None` while there is no process
+    pub uptime: Option<Duration>,
+    pub ready_after: Option<Duration>,
+    pub players_online: Option<i32>,
+    pub progress: Option<String>,
+    /// Until the empty server gets stopped, `None` while somebody is online
+    pub idle_remaining: Option<Duration>,
+}
+
 impl MinecraftServerHandler {
-    /// `addr` is the address the minecraft server is running on
-    pub fn create(start_command: String, addr: String) -> MinecraftServerHandler {
-        MinecraftServerHandler {
-            start_command,
-            addr,
-            server: None,
-        }
+    pub fn create(backend: &BackendConfig, config: &Config) -> Arc<Mutex<MinecraftServerHandler>> {
+        let status_file = backend.status_file(&config.status);
+        let saved_status = status_file
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        Arc::new_cyclic(|this| {
+            Mutex::new(MinecraftServerHandler {
+                console: ConsoleWatch::create(backend.name.clone(), &config.console),
+                start_times: StartTimes::default(),
+                start_command: backend.start_command.clone(),
+                addr: backend.address.clone(),
+                lifecycle: config.lifecycle.clone(),
+                read_limits: config.read_limits(ProtocolState::Status),
+                status_file,
+                saved_status,
+                server: None,
+                starts: 0,
+                restart_queued: false,
+                this: this.clone(),
+            })
+        })
     }
-    /// This is an async function which polls the minecraft server every `frequency` seconds
+    /// Spawns a task which polls the minecraft server every `frequency` seconds
     /// and if no player has been online for `timeout` seconds, then it stops the server
     /// `grace_period` how much time it should wait before starting polling
-    fn start_polling(&self, frequency: u64, timeout: u64, grace_period: u64) -> Option<()> {
-        let mc_server = self.server.clone();
-        let mc_server = match mc_server {
+    fn start_polling(&self, frequency: u64, timeout: u64, grace_period: u64) -> ProxyResult<()> {
+        let mc_server = match self.server.clone() {
             Some(x) => x,
-            None => {
-                println!("PROXY: whyyyy must it not work?");
-                return None;
-            }
+            None => return Err(ProxyError::ServerProcess("no server to poll".to_owned())),
         };
-        thread::Builder::new()
-            .name("Server Polling Thread".to_string())
-            .spawn(move || loop {
-                thread::sleep(time::Duration::from_secs(frequency));
-                let mut server = mc_server.lock().unwrap();
-                if server.shutdown_if_offline(frequency, timeout, grace_period) {
-                    return;
+        let read_limits = self.read_limits;
+        let status_file = self.status_file.clone();
+        tokio::spawn(async move {
+            let mut saved = None;
+            loop {
+                tokio::time::sleep(time::Duration::from_secs(frequency)).await;
+                let addr = mc_server.lock().unwrap().addr.clone();
+                let response = MinecraftServer::query_server(addr, read_limits).await;
+                let to_save = {
+                    let mut server = mc_server.lock().unwrap();
+                    let mut to_save = None;
+                    let status = response.and_then(|response| {
+                        let status = response.get_json()?;
+                        to_save = Some(response.get_string());
+                        server.status = Some((response, Instant::now()));
+                        Ok(status)
+                    });
+                    if server.shutdown_if_offline(status, frequency, timeout, grace_period) {
+                        return;
+                    }
+                    to_save
+                };
+                // Small, and only written when it changed (mostly the player count)
+                if let (Some(path), Some(json)) = (&status_file, to_save) {
+                    if saved.as_ref() != Some(&json) {
+                        if let Err(err) = std::fs::write(path, &json) {
+                            println!(
+                                "PROXY: polling: can't save the status to {}: {err}",
+                                path.display()
+                            );
+                        }
+                        saved = Some(json);
+                    }
                 }
-            })
-            .unwrap();
-        return Some(());
+            }
+        });
+        Ok(())
     }
-    pub fn running(&self) -> bool {
-        return match self.server.clone() {
-            Some(ser) => ser.lock().unwrap().running,
-            None => false,
-        };
+    pub fn state(&self) -> ServerState {
+        match &self.server {
+            Some(server) => server.lock().unwrap().state,
+            None => ServerState::Stopped,
+        }
+    }
+    pub fn stats(&self) -> ServerStats {
+        let server = self.server.as_ref().map(|x| x.lock().unwrap());
+        let state = server.as_ref().map_or(ServerState::Stopped, |x| x.state);
+        ServerStats {
+            state,
+            starts: self.starts,
+            uptime: server
+                .as_ref()
+                .filter(|x| x.state.is_alive())
+                .map(|x| x.started.elapsed()),
+            ready_after: server.as_ref().and_then(|x| x.ready_after),
+            players_online: server.as_ref().and_then(|x| x.players_online),
+            progress: server.as_ref().and_then(|x| x.progress.clone()),
+            idle_remaining: server
+                .as_ref()
+                .filter(|x| x.state == ServerState::Ready && x.players_online == Some(0))
+                .map(|x| {
+                    let limit = self.lifecycle.idle_timeout + self.lifecycle.grace_period;
+                    Duration::from_secs(limit.saturating_sub(x.shutdown_timer))
+                }),
+        }
+    }
+    /// `Some` while the server is starting.
+    pub fn start_progress(&self) -> Option<StartProgress> {
+        let server = self.server.as_ref()?.lock().unwrap();
+        if server.state != ServerState::Starting {
+            return None;
+        }
+        let elapsed = server.started.elapsed();
+        Some(StartProgress {
+            progress: server.progress.clone(),
+            elapsed,
+            eta: self
+                .start_times
+                .expected()
+                .map(|x| x.saturating_sub(elapsed)),
+        })
+    }
+    /// The last polled status, while the server is ready and the status younger than `ttl`.
+    pub fn cached_status(&self, ttl: Duration) -> Option<StatusResponse> {
+        let server = self.server.as_ref()?.lock().unwrap();
+        if server.state != ServerState::Ready {
+            return None;
+        }
+        match &server.status {
+            Some((response, polled)) if polled.elapsed() < ttl => Some(response.clone()),
+            _ => None,
+        }
+    }
+    /// The last status the server answered, polled in this run or saved by an earlier one.
+    pub fn last_status(&self) -> Option<StatusStructNew> {
+        let polled = self.server.as_ref().and_then(|server| {
+            let server = server.lock().unwrap();
+            server
+                .status
+                .as_ref()
+                .map(|(response, _)| response.get_string())
+        });
+        let json = polled.or_else(|| self.saved_status.clone())?;
+        serde_json::from_str(&json).ok()
+    }
+    /// The last console lines of the current (or last) run.
+    pub fn console_history(&self) -> Vec<String> {
+        match &self.server {
+            Some(server) => server.lock().unwrap().history.lines(),
+            None => Vec::new(),
+        }
     }
-    pub fn start_minecraft_server(&mut self) -> Option<()> {
-        let server = self.server.clone();
-        match server {
-            Some(ser) => {
-                let server = ser.lock().unwrap();
-                if server.running {
-                    println!("PROXY: Starting server failed! -> Server is already running!");
-                    return None;
+    /// Runs `action` on the server, if it's running.
+    fn with_running_server(
+        &self,
+        action: impl FnOnce(&mut MinecraftServer) -> ProxyResult<()>,
+    ) -> ProxyResult<()> {
+        match &self.server {
+            Some(server) => {
+                let mut server = server.lock().unwrap();
+                if !server.state.is_alive() {
+                    return Err(ProxyError::ServerProcess(
+                        "server is not running".to_owned(),
+                    ));
+                }
+                action(&mut server)
+            }
+            None => Err(ProxyError::ServerProcess(
+                "server is not running".to_owned(),
+            )),
+        }
+    }
+    pub fn stop_minecraft_server(&mut self) -> ProxyResult<()> {
+        self.restart_queued = false;
+        self.with_running_server(MinecraftServer::stop)
+    }
+    pub fn kill_minecraft_server(&mut self) -> ProxyResult<()> {
+        self.restart_queued = false;
+        self.with_running_server(MinecraftServer::kill)
+    }
+    pub fn send_command(&self, command: String) -> ProxyResult<()> {
+        self.with_running_server(|server| server.send_command(command))
+    }
+    /// A stopping server is on its way up again once it's down.
+    pub fn restart_queued(&self) -> bool {
+        self.restart_queued
+    }
+    /// What a join from an allowed player does: starts a stopped (or crashed) server,
+    /// queues a restart for a stopping one and leaves a running one alone.
+    pub fn wake(&mut self) -> ProxyResult<()> {
+        match self.state() {
+            ServerState::Stopped | ServerState::Crashed => self.start_minecraft_server(),
+            ServerState::Stopping => {
+                if !self.restart_queued {
+                    println!("PROXY: server {}: restart queued", self.addr);
+                }
+                self.restart_queued = true;
+                Ok(())
+            }
+            ServerState::Starting | ServerState::Ready => Ok(()),
+        }
+    }
+    pub fn start_minecraft_server(&mut self) -> ProxyResult<()> {
+        let state = self.state();
+        if state.is_alive() {
+            return Err(ProxyError::ServerProcess(format!("server is {state}")));
+        }
+        self.restart_queued = false;
+        // The process exits on a plain thread, the restart has to get back onto the runtime
+        let runtime = tokio::runtime::Handle::current();
+        let this = self.this.clone();
+        let on_exit = move || {
+            let handler = match this.upgrade() {
+                Some(x) => x,
+                None => return,
+            };
+            let _runtime = runtime.enter();
+            let mut handler = handler.lock().unwrap();
+            if handler.restart_queued {
+                println!("PROXY: server {}: restarting", handler.addr);
+                if let Err(err) = handler.start_minecraft_server() {
+                    println!("PROXY: server {}: can't restart: {err}", handler.addr);
                 }
             }
-            None => (),
-        };
-        let server = MinecraftServer::spawn(self.start_command.clone(), self.addr.clone());
-        let server = match server {
-            Some(x) => x,
-            None => return None,
         };
+        let server = MinecraftServer::spawn(
+            self.start_command.clone(),
+            self.addr.clone(),
+            &self.console,
+            self.start_times.clone(),
+            on_exit,
+        )?;
         self.server = Some(server);
-        match self.start_polling(10, 600, 600) {
-            Some(_) => println!("PROXY: polling started!"),
-            None => {
-                println!("PROXY: polling failed to start!");
-                return None;
+        self.starts += 1;
+        self.start_polling(
+            self.lifecycle.poll_interval,
+            self.lifecycle.idle_timeout,
+            self.lifecycle.grace_period,
+        )?;
+        println!("PROXY: polling started!");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::{
+        clientbound::status::StatusStructNew, serverbound::status::StatusRequest,
+    };
+
+    /// A start command running `body` as its script.
+    fn handler(name: &str, body: &str) -> Arc<Mutex<MinecraftServerHandler>> {
+        let mut config = Config::parse("[console]\nhistory_lines = 2\nmirror = false").unwrap();
+        config.backends[0].address = "127.0.0.1:1".to_owned();
+        handler_with(name, body, config)
+    }
+
+    fn handler_with(
+        name: &str,
+        body: &str,
+        mut config: Config,
+    ) -> Arc<Mutex<MinecraftServerHandler>> {
+        let path =
+            std::env::temp_dir().join(format!("mc-proxy-test-{}-{name}.sh", std::process::id()));
+        std::fs::write(&path, body).unwrap();
+        config.backends[0].start_command = path.to_string_lossy().into_owned();
+        MinecraftServerHandler::create(&config.backends[0], &config)
+    }
+
+    async fn wait_for(handler: &Mutex<MinecraftServerHandler>, state: ServerState, starts: u64) {
+        for _ in 0..500 {
+            let stats = handler.lock().unwrap().stats();
+            if stats.state == state && stats.starts == starts {
+                return;
             }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("server never got {state} after {starts} starts");
+    }
+
+    fn online(players: i32) -> ProxyResult<Box<dyn StatusTrait>> {
+        let mut status = StatusStructNew::create();
+        status.players.online = players;
+        Ok(Box::new(status))
+    }
+
+    #[tokio::test]
+    async fn ready_idle_stop_and_restart() {
+        let handler = handler(
+            "stop",
+            "while read line; do if [ \"$line\" = stop ]; then exit 0; fi; done; exit 1",
+        );
+        handler.lock().unwrap().start_minecraft_server().unwrap();
+        assert_eq!(handler.lock().unwrap().state(), ServerState::Starting);
+        assert!(handler.lock().unwrap().start_minecraft_server().is_err());
+
+        let server = handler.lock().unwrap().server.clone().unwrap();
+        {
+            let mut server = server.lock().unwrap();
+            let failed = Err(ProxyError::BackendUnavailable("refused".to_owned()));
+            assert!(!server.shutdown_if_offline(failed, 10, 20, 0));
+            assert_eq!(server.state, ServerState::Starting);
+            assert!(!server.shutdown_if_offline(online(1), 10, 20, 0));
+            assert_eq!(server.state, ServerState::Ready);
+            assert!(server.ready_after.is_some());
+            assert!(!server.shutdown_if_offline(online(0), 10, 20, 0));
+            assert!(!server.shutdown_if_offline(online(0), 10, 20, 0));
+            assert!(server.shutdown_if_offline(online(0), 10, 20, 0));
+            assert_eq!(server.state, ServerState::Stopping);
+        }
+        let stats = handler.lock().unwrap().stats();
+        assert_eq!(stats.players_online, Some(0));
+        assert!(stats.uptime.is_some());
+
+        // Joining while it stops starts it again once it exited
+        handler.lock().unwrap().wake().unwrap();
+        wait_for(&handler, ServerState::Starting, 2).await;
+
+        handler.lock().unwrap().kill_minecraft_server().unwrap();
+        wait_for(&handler, ServerState::Stopped, 2).await;
+        assert!(handler.lock().unwrap().stats().uptime.is_none());
+        assert!(handler.lock().unwrap().stop_minecraft_server().is_err());
+        assert!(handler
+            .lock()
+            .unwrap()
+            .send_command("list".to_owned())
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn console_readiness_progress_and_history() {
+        let handler = handler(
+            "console",
+            "echo 'Preparing spawn area: 42%'\n\
+             read line\n\
+             echo '[12:00:00] [Server thread/INFO]: Done (3.2s)! For help, type \"help\"' >&2\n\
+             read line\n\
+             echo one; echo two; read line",
+        );
+        handler.lock().unwrap().start_minecraft_server().unwrap();
+        for _ in 0..500 {
+            if handler.lock().unwrap().stats().progress.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            handler.lock().unwrap().stats().progress.as_deref(),
+            Some("42%")
+        );
+        let start_progress = handler.lock().unwrap().start_progress().unwrap();
+        assert_eq!(start_progress.progress.as_deref(), Some("42%"));
+        assert_eq!(start_progress.eta_text(), None);
+
+        handler
+            .lock()
+            .unwrap()
+            .send_command("go on".to_owned())
+            .unwrap();
+        wait_for(&handler, ServerState::Ready, 1).await;
+        let stats = handler.lock().unwrap().stats();
+        assert!(stats.ready_after.is_some());
+        assert_eq!(stats.progress, None);
+        assert!(handler.lock().unwrap().start_progress().is_none());
+        assert!(handler.lock().unwrap().start_times.expected().is_some());
+        // stdout and stderr are read on separate threads, so this waits for the Done line first
+        handler
+            .lock()
+            .unwrap()
+            .send_command("more".to_owned())
+            .unwrap();
+        for _ in 0..500 {
+            if handler
+                .lock()
+                .unwrap()
+                .console_history()
+                .contains(&"two".to_owned())
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let history = handler.lock().unwrap().console_history();
+        assert_eq!(history, ["one", "two"]);
+
+        handler.lock().unwrap().kill_minecraft_server().unwrap();
+        wait_for(&handler, ServerState::Stopped, 1).await;
+        assert_eq!(handler.lock().unwrap().console_history().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn status_is_cached_while_ready() {
+        let handler = handler("cache", "read line");
+        handler.lock().unwrap().start_minecraft_server().unwrap();
+        let ttl = Duration::from_secs(60);
+        let server = handler.lock().unwrap().server.clone().unwrap();
+        let status = StatusResponse::set_json(online(0).unwrap());
+        server.lock().unwrap().status = Some((status, Instant::now()));
+        // Still starting, the status could be from the last run
+        assert!(handler.lock().unwrap().cached_status(ttl).is_none());
+
+        server.lock().unwrap().mark_ready("test");
+        let cached = handler.lock().unwrap().cached_status(ttl).unwrap();
+        assert_eq!(cached.get_json().unwrap().get_players_online(), 0);
+        assert!(handler
+            .lock()
+            .unwrap()
+            .cached_status(Duration::ZERO)
+            .is_none());
+
+        handler.lock().unwrap().kill_minecraft_server().unwrap();
+        assert!(handler.lock().unwrap().cached_status(ttl).is_none());
+        wait_for(&handler, ServerState::Stopped, 1).await;
+    }
+
+    #[tokio::test]
+    async fn query_server_asks_for_the_status() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let limits = ReadLimits {
+            timeout: Duration::from_secs(5),
+            max_size: 1024,
+        };
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let handshake = packets::Packet::read(&mut stream, limits).await.unwrap();
+            let handshake = packets::serverbound::handshake::Handshake::parse(&handshake).unwrap();
+            assert_eq!(handshake.get_next_state(), 1);
+            packets::Packet::read(&mut stream, limits)
+                .await
+                .unwrap()
+                .expect::<StatusRequest>(746)
+                .unwrap();
+            StatusResponse::set_json(online(3).unwrap())
+                .send(&mut stream, 746)
+                .await
+                .unwrap();
+        });
+        let status = MinecraftServer::query_server(addr.clone(), limits)
+            .await
+            .unwrap();
+        assert_eq!(status.get_json().unwrap().get_players_online(), 3);
+        server.await.unwrap();
+
+        assert!(matches!(
+            MinecraftServer::query_server(addr, limits).await,
+            Err(ProxyError::BackendUnavailable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn status_is_saved_for_the_next_run() {
+        let dir = std::env::temp_dir().join(format!("mc-proxy-test-{}-saved", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = || {
+            let mut config = Config::parse("[lifecycle]\npoll_interval = 1").unwrap();
+            config.console.mirror = false;
+            config.status.save_dir = Some(dir.clone());
+            config.backends[0].name = "a/b".to_owned();
+            config.backends[0].address = listener.local_addr().unwrap().to_string();
+            config
+        };
+        let path = config().backends[0].status_file(&config().status).unwrap();
+        assert_eq!(path, dir.join("a_b.json"));
+        std::fs::remove_file(&path).ok();
+
+        let handler = handler_with("saved", "read line", config());
+        assert!(handler.lock().unwrap().last_status().is_none());
+        handler.lock().unwrap().start_minecraft_server().unwrap();
+        // One poll from the server
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let limits = ReadLimits {
+            timeout: Duration::from_secs(5),
+            max_size: 1024,
         };
-        return Some(());
+        packets::Packet::read(&mut stream, limits).await.unwrap();
+        packets::Packet::read(&mut stream, limits).await.unwrap();
+        let mut status = StatusStructNew::create();
+        status.players.max = 20;
+        StatusResponse::set_json(Box::new(status))
+            .send(&mut stream, 746)
+            .await
+            .unwrap();
+        for _ in 0..500 {
+            if path.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            handler.lock().unwrap().last_status().unwrap().players.max,
+            20
+        );
+        handler.lock().unwrap().kill_minecraft_server().unwrap();
+        wait_for(&handler, ServerState::Stopped, 1).await;
+
+        // A new proxy run shows it before the server was polled
+        let handler = handler_with("saved", "read line", config());
+        assert_eq!(
+            handler.lock().unwrap().last_status().unwrap().players.max,
+            20
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn start_eta() {
+        let times = StartTimes::default();
+        assert_eq!(times.expected(), None);
+        for secs in 0..StartTimes::KEPT as u64 + 2 {
+            times.record(Duration::from_secs(secs * 10));
+        }
+        // 0 and 10 fell out, the average of 20..=110
+        assert_eq!(times.expected(), Some(Duration::from_secs(65)));
+
+        let mut start_progress = StartProgress {
+            progress: None,
+            elapsed: Duration::from_secs(65),
+            eta: None,
+        };
+        assert_eq!(start_progress.eta_text(), None);
+        start_progress.eta = Some(Duration::from_millis(400));
+        assert_eq!(start_progress.eta_text().unwrap(), "any moment now");
+        start_progress.eta = Some(Duration::from_secs(42));
+        assert_eq!(start_progress.eta_text().unwrap(), "~42s");
+        assert_eq!(format_duration(start_progress.elapsed), "1m 05s");
+    }
+
+    #[tokio::test]
+    async fn crash_and_wake() {
+        let handler = handler("crash", "exit 3");
+        handler.lock().unwrap().wake().unwrap();
+        wait_for(&handler, ServerState::Crashed, 1).await;
+        assert!(handler
+            .lock()
+            .unwrap()
+            .send_command("list".to_owned())
+            .is_err());
+        handler.lock().unwrap().wake().unwrap();
+        wait_for(&handler, ServerState::Crashed, 2).await;
     }
 }
@@ -0,0 +1,158 @@
+use crate::{
+    error::ProxyResult,
+    packets::registry::declare_packets,
+    text::{JsonText, NbtText},
+    types::{nbt::Nbt, Codec, Identifier, VarInt, VarString},
+};
+
+declare_packets! {
+    Configuration, Clientbound;
+    Disconnect [764..=765 => 0x01, 766.. => 0x02] {
+        /// 1.20.2 (764) still sent JSON
+        json_reason: JsonText [764],
+        reason: NbtText [765..],
+    }
+    /// The client answers with Acknowledge Finish Configuration and goes on to Play.
+    FinishConfiguration [764..=765 => 0x02, 766.. => 0x03] {}
+    KeepAlive [764..=765 => 0x03, 766.. => 0x04] {
+        id: i64,
+    }
+    /// 1.20.5+: the entries of one synchronized registry, like `minecraft:dimension_type`.
+    RegistryData [766.. => 0x07] {
+        registry: Identifier,
+        entries: Vec<RegistryEntry>,
+    }
+    /// 1.20.5+: the client disconnects and joins `host:port` with next state 3.
+    Transfer [766.. => 0x0b] {
+        host: VarString,
+        port: VarInt,
+    }
+}
+
+impl RegistryData {
+    pub fn create(registry: &str, entries: Vec<RegistryEntry>) -> RegistryData {
+        RegistryData {
+            registry: Identifier::from(registry).unwrap_or_default(),
+            entries,
+        }
+    }
+}
+
+/// One entry of a registry, without data it comes from a pack the client knows.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegistryEntry {
+    pub id: Identifier,
+    pub data: Option<Nbt>,
+}
+
+impl RegistryEntry {
+    pub fn create(id: &str, data: Nbt) -> RegistryEntry {
+        RegistryEntry {
+            id: Identifier::from(id).unwrap_or_default(),
+            data: Some(data),
+        }
+    }
+}
+
+impl Codec for RegistryEntry {
+    fn parse<I>(data: &mut I) -> ProxyResult<RegistryEntry>
+    where
+        I: Iterator<Item = u8>,
+    {
+        Ok(RegistryEntry {
+            id: Identifier::parse(data)?,
+            data: Option::parse(data)?,
+        })
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        self.id.write(data)?;
+        self.data.write(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packets::registry::Registered,
+        text::TextComponent,
+        types::{nbt::Nbt, Codec},
+    };
+
+    fn disconnect(reason: TextComponent) -> Disconnect {
+        Disconnect {
+            json_reason: JsonText(reason.clone()),
+            reason: NbtText(reason),
+        }
+    }
+
+    #[test]
+    fn layouts() {
+        let disconnect = disconnect(TextComponent::text("bye"));
+        let packet = disconnect.encode(767).unwrap();
+        assert_eq!(packet.all, [7, 2, 0x08, 0, 3, b'b', b'y', b'e']);
+
+        let keep_alive = KeepAlive { id: 258 };
+        let packet = keep_alive.encode(767).unwrap();
+        assert_eq!(packet.all, [9, 4, 0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(KeepAlive::decode(&packet, 767).unwrap(), keep_alive);
+
+        let transfer = Transfer {
+            host: VarString::from("mc".to_owned()),
+            port: VarInt::from(25565),
+        };
+        let packet = transfer.encode(767).unwrap();
+        assert_eq!(packet.all, [7, 0x0b, 2, b'm', b'c', 0xdd, 0xc7, 0x01]);
+        assert_eq!(Transfer::decode(&packet, 767).unwrap(), transfer);
+        assert_eq!(Transfer::id(765), None);
+
+        let packet = FinishConfiguration {}.encode(767).unwrap();
+        assert_eq!(packet.all, [1, 3]);
+    }
+
+    #[test]
+    fn registry_data() {
+        let registry = RegistryData::create(
+            "dimension_type",
+            vec![RegistryEntry::create("overworld", Nbt::Byte(1))],
+        );
+        let packet = registry.encode(767).unwrap();
+        let mut expected = vec![0x07];
+        expected.extend(b"\x18minecraft:dimension_type\x01");
+        expected.extend(b"\x13minecraft:overworld\x01\x01\x01");
+        assert_eq!(packet.all[1..], expected);
+        assert_eq!(RegistryData::decode(&packet, 767).unwrap(), registry);
+        assert_eq!(RegistryData::id(765), None);
+    }
+
+    #[test]
+    fn ids_by_version() {
+        assert_eq!(Disconnect::id(763), None);
+        assert_eq!(Disconnect::id(764), Some(0x01));
+        assert_eq!(Disconnect::id(765), Some(0x01));
+        assert_eq!(Disconnect::id(766), Some(0x02));
+        assert_eq!(Disconnect::id(772), Some(0x02));
+        assert_eq!(KeepAlive::id(765), Some(0x03));
+        assert_eq!(KeepAlive::id(766), Some(0x04));
+    }
+
+    #[test]
+    fn reasons_are_json_in_764_and_nbt_since_765() {
+        let reason = TextComponent::from_legacy("§cno");
+        let disconnect = disconnect(reason.clone());
+
+        let data = disconnect.encode(764).unwrap().data;
+        let mut reader = data.iter().copied();
+        let text = VarString::parse(&mut reader).unwrap().get_value();
+        assert_eq!(text, serde_json::to_string(&reason).unwrap());
+        assert_eq!(reader.next(), None);
+
+        for protocol_version in [765, 767] {
+            let data = disconnect.encode(protocol_version).unwrap().data;
+            let mut reader = data.iter().copied();
+            assert_eq!(Nbt::parse(&mut reader).unwrap(), reason.to_nbt());
+            assert_eq!(reader.next(), None);
+        }
+    }
+}
@@ -1,41 +1,113 @@
-use std::io::Write;
-
 use crate::{
-    packets::{Packet, SendPacket},
-    types::VarString,
+    error::ProxyResult,
+    packets::registry::declare_packets,
+    text::{JsonText, TextComponent},
+    types::{Codec, Uuid, VarString},
 };
 
-/// id: 0x00
-#[derive(Debug)]
-pub struct Disconnect {
-    reason: VarString,
-    all: Vec<u8>,
+declare_packets! {
+    Login, Clientbound;
+    Disconnect [_ => 0x00] {
+        reason: JsonText,
+    }
+    /// The limbo is the only one sending it, the login is offline so there are no properties.
+    LoginSuccess [_ => 0x02] {
+        uuid: Uuid [707..],
+        /// Before 1.16 (707) the uuid was a string with dashes
+        uuid_string: VarString [0..=706],
+        name: VarString,
+        properties: Vec<Property> [759..],
+        /// Only 1.20.5 - 1.21.1 (766 - 767)
+        strict_error_handling: bool [766..=767],
+    }
 }
 
 impl Disconnect {
-    pub fn parse(packet: Packet) -> Option<Disconnect> {
-        let mut reader = packet.data.into_iter();
-        Some(Disconnect {
-            all: packet.all,
-            reason: VarString::parse(&mut reader)?,
-        })
+    pub fn set_reason(reason: TextComponent) -> Disconnect {
+        Disconnect {
+            reason: JsonText(reason),
+        }
     }
-    pub fn get_string(&self) -> String {
-        self.reason.get_value()
+}
+
+impl LoginSuccess {
+    pub fn create(uuid: Uuid, name: String) -> LoginSuccess {
+        LoginSuccess {
+            uuid,
+            uuid_string: VarString::from(uuid.to_string()),
+            name: VarString::from(name),
+            ..Default::default()
+        }
     }
-    pub fn set_reason(reason: String) -> Option<Disconnect> {
-        let vec = VarString::from(reason).move_data()?;
-        Disconnect::parse(Packet::from_bytes(0, vec)?)
+}
+
+/// A profile property, like the skin `textures`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Property {
+    pub name: VarString,
+    pub value: VarString,
+    pub signature: Option<VarString>,
+}
+
+impl Codec for Property {
+    fn parse<I>(data: &mut I) -> ProxyResult<Property>
+    where
+        I: Iterator<Item = u8>,
+    {
+        Ok(Property {
+            name: VarString::parse(data)?,
+            value: VarString::parse(data)?,
+            signature: Option::parse(data)?,
+        })
     }
-    pub fn get_all(&self) -> Vec<u8> {
-        self.all.clone()
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        self.name.write(data)?;
+        self.value.write(data)?;
+        self.signature.write(data)
     }
 }
 
-impl SendPacket for Disconnect {
-    fn send_packet(&self, stream: &mut std::net::TcpStream) -> std::io::Result<()> {
-        stream.write_all(&self.all)?;
-        stream.flush()?;
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::registry::Registered;
+
+    #[test]
+    fn login_success_strict_error_handling_until_767() {
+        let uuid = Uuid::parse(&mut [0x11; 16].into_iter()).unwrap();
+        let success = LoginSuccess::create(uuid, "Notch".to_owned());
+        let mut expected = vec![0x11; 16];
+        expected.extend([5, b'N', b'o', b't', b'c', b'h', 0]);
+
+        let mut with_flag = expected.clone();
+        with_flag.push(0);
+        assert_eq!(success.encode(767).unwrap().data, with_flag);
+        assert_eq!(success.encode(768).unwrap().data, expected);
+
+        let decoded = LoginSuccess::decode(&success.encode(767).unwrap(), 767).unwrap();
+        assert_eq!(
+            decoded,
+            LoginSuccess {
+                uuid_string: VarString::default(),
+                ..success
+            }
+        );
+    }
+
+    #[test]
+    fn uuid_string_before_707() {
+        let uuid = Uuid::parse(&mut [0x11; 16].into_iter()).unwrap();
+        let data = LoginSuccess::create(uuid, "Notch".to_owned())
+            .encode(340)
+            .unwrap()
+            .data;
+        let mut reader = data.iter().copied();
+        assert_eq!(
+            VarString::parse(&mut reader).unwrap().get_value(),
+            "11111111-1111-1111-1111-111111111111"
+        );
+        assert_eq!(VarString::parse(&mut reader).unwrap().get_value(), "Notch");
+        assert_eq!(reader.next(), None);
     }
 }
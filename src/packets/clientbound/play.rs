@@ -0,0 +1,217 @@
+use crate::{
+    packets::registry::declare_packets,
+    text::{JsonText, NbtText, TextComponent},
+    types::{Identifier, Position, VarInt, VarString},
+};
+
+declare_packets! {
+    Play, Clientbound;
+    /// Only for connections the proxy itself speaks Play on: the spliced ones can be encrypted
+    /// or compressed, on those the server has to do the kicking.
+    Disconnect [
+        // 1.19.4 - 1.20.1
+        762..=763 => 0x1a,
+        // 1.20.2 - 1.20.4
+        764..=765 => 0x1b,
+        // 1.20.5 - 1.21.4
+        766..=769 => 0x1d,
+        // 1.21.5 - 1.21.8
+        770..=772 => 0x1c,
+    ] {
+        json_reason: JsonText [0..=764],
+        reason: NbtText [765..],
+    }
+    GameEvent [766..=767 => 0x22, 768..=769 => 0x23, 770..=772 => 0x22] {
+        event: u8,
+        value: f32,
+    }
+    KeepAlive [766..=767 => 0x26, 768..=769 => 0x27, 770..=772 => 0x26] {
+        id: i64,
+    }
+    /// Joins the world, the ones from 1.20.5 on.
+    Login [766..=767 => 0x2b, 768..=769 => 0x2c, 770..=772 => 0x2b] {
+        entity_id: i32,
+        hardcore: bool,
+        dimension_names: Vec<Identifier>,
+        max_players: VarInt,
+        view_distance: VarInt,
+        simulation_distance: VarInt,
+        reduced_debug_info: bool,
+        respawn_screen: bool,
+        limited_crafting: bool,
+        /// The index into the `dimension_type` registry
+        dimension_type: VarInt,
+        dimension_name: Identifier,
+        hashed_seed: i64,
+        game_mode: u8,
+        /// -1 for none
+        previous_game_mode: i8,
+        debug: bool,
+        flat: bool,
+        /// The death location follows when true
+        has_death_location: bool,
+        portal_cooldown: VarInt,
+        sea_level: VarInt [768..],
+        enforces_secure_chat: bool,
+    }
+    /// Before 1.21.2 without the velocity, with the teleport id at the end and the flags in a byte.
+    PlayerPosition [766..=767 => 0x40, 768..=769 => 0x42, 770..=772 => 0x41] {
+        teleport_id: VarInt [768..],
+        x: f64,
+        y: f64,
+        z: f64,
+        velocity_x: f64 [768..],
+        velocity_y: f64 [768..],
+        velocity_z: f64 [768..],
+        yaw: f32,
+        pitch: f32,
+        old_flags: i8 [0..=767],
+        flags: i32 [768..],
+        old_teleport_id: VarInt [0..=767],
+    }
+    SetDefaultSpawnPosition [766..=767 => 0x56, 768..=769 => 0x5b, 770..=772 => 0x5a] {
+        location: Position,
+        angle: f32,
+    }
+    /// Like the Configuration one, for clients that already joined a world.
+    Transfer [766..=767 => 0x73, 768..=772 => 0x7a] {
+        host: VarString,
+        port: VarInt,
+    }
+}
+
+impl Disconnect {
+    pub fn set_reason(reason: TextComponent) -> Disconnect {
+        Disconnect {
+            json_reason: JsonText(reason.clone()),
+            reason: NbtText(reason),
+        }
+    }
+}
+
+impl GameEvent {
+    /// Makes the client leave the loading screen once the chunk it's in arrived,
+    /// or right away when it's outside the world height.
+    pub fn start_waiting_for_chunks() -> GameEvent {
+        GameEvent {
+            event: 13,
+            value: 0.0,
+        }
+    }
+}
+
+impl KeepAlive {
+    pub fn create(id: i64) -> KeepAlive {
+        KeepAlive { id }
+    }
+}
+
+impl PlayerPosition {
+    /// Absolute, without any of the relative flags.
+    pub fn create(x: f64, y: f64, z: f64) -> PlayerPosition {
+        PlayerPosition {
+            x,
+            y,
+            z,
+            ..PlayerPosition::default()
+        }
+    }
+}
+
+impl Transfer {
+    pub fn create(host: String, port: u16) -> Transfer {
+        Transfer {
+            host: VarString::from(host),
+            port: VarInt::from(port as i32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packets::registry::Registered,
+        types::{nbt::Nbt, Codec, VarString},
+    };
+
+    #[test]
+    fn ids_by_version() {
+        for (protocol_version, ids) in [
+            (766, [0x22, 0x26, 0x2b, 0x40, 0x56, 0x73]),
+            (768, [0x23, 0x27, 0x2c, 0x42, 0x5b, 0x7a]),
+            (772, [0x22, 0x26, 0x2b, 0x41, 0x5a, 0x7a]),
+        ] {
+            let found = [
+                GameEvent::id(protocol_version),
+                KeepAlive::id(protocol_version),
+                Login::id(protocol_version),
+                PlayerPosition::id(protocol_version),
+                SetDefaultSpawnPosition::id(protocol_version),
+                Transfer::id(protocol_version),
+            ];
+            assert_eq!(found, ids.map(Some), "{protocol_version}");
+        }
+        assert_eq!(Login::id(765), None);
+        assert_eq!(Login::id(773), None);
+    }
+
+    #[test]
+    fn player_position_layouts() {
+        let position = PlayerPosition::create(0.0, 1.0, 0.0);
+        let one = 1f64.to_be_bytes();
+
+        // x, y, z, yaw, pitch, flags, teleport id
+        let mut old = [0; 8].to_vec();
+        old.extend(one);
+        old.extend([0; 8 + 4 + 4 + 1 + 1]);
+        assert_eq!(position.encode(767).unwrap().data, old);
+        assert_eq!(
+            PlayerPosition::decode(&position.encode(767).unwrap(), 767).unwrap(),
+            position
+        );
+
+        // teleport id, x, y, z, velocity, yaw, pitch, flags
+        let mut new = [0; 1 + 8].to_vec();
+        new.extend(one);
+        new.extend([0; 8 + 3 * 8 + 4 + 4 + 4]);
+        assert_eq!(position.encode(768).unwrap().data, new);
+    }
+
+    #[test]
+    fn sea_level_since_768() {
+        let login = Login {
+            dimension_names: vec![Identifier::from("overworld").unwrap()],
+            dimension_name: Identifier::from("overworld").unwrap(),
+            ..Login::default()
+        };
+        let old = login.encode(767).unwrap().data;
+        let new = login.encode(768).unwrap().data;
+        assert_eq!(new.len(), old.len() + 1);
+        assert_eq!(
+            Login::decode(&login.encode(768).unwrap(), 768).unwrap(),
+            login
+        );
+    }
+
+    #[test]
+    fn json_until_764_nbt_since_765() {
+        let reason = TextComponent::from_legacy("§cbye");
+        let disconnect = Disconnect::set_reason(reason.clone());
+
+        let json = disconnect.encode(764).unwrap();
+        assert_eq!(json.id.get_int(), 0x1b);
+        let mut data = json.data.iter().copied();
+        let text = VarString::parse(&mut data).unwrap().get_value();
+        assert_eq!(text, serde_json::to_string(&reason).unwrap());
+
+        for (protocol_version, id) in [(765, 0x1b), (767, 0x1d), (772, 0x1c)] {
+            let nbt = disconnect.encode(protocol_version).unwrap();
+            assert_eq!(nbt.id.get_int(), id);
+            let mut data = nbt.data.iter().copied();
+            assert_eq!(Nbt::parse(&mut data).unwrap(), reason.to_nbt());
+        }
+        assert!(disconnect.encode(761).is_err());
+        assert!(disconnect.encode(773).is_err());
+    }
+}
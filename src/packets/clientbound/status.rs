@@ -1,17 +1,21 @@
-use std::{collections::HashMap, io::Write};
+use std::collections::HashMap;
 
 use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
-    packets::{Packet, SendPacket},
+    error::{ProxyError, ProxyResult},
+    packets::registry::declare_packets,
+    text::TextComponent,
     types::VarString,
 };
 
-pub trait StatusTrait {
+pub trait StatusTrait: Send {
     fn get_players_online(&self) -> i32;
-    fn set_description(&mut self, str: String);
-    fn get_description(&mut self) -> &mut String;
+    fn get_players_max(&self) -> i32;
+    fn get_version_name(&self) -> String;
+    fn set_description(&mut self, description: TextComponent);
+    fn get_description(&mut self) -> &mut TextComponent;
     fn get_string(&self) -> String;
 }
 impl StatusTrait for StatusStructNew {
@@ -21,61 +25,44 @@ impl StatusTrait for StatusStructNew {
     {
         self.players.online
     }
+    fn get_players_max(&self) -> i32 {
+        self.players.max
+    }
+    fn get_version_name(&self) -> String {
+        self.version.name.clone()
+    }
 
-    fn set_description(&mut self, str: String)
+    fn set_description(&mut self, description: TextComponent)
     where
         Self: Sized,
     {
-        self.description.text = str;
+        self.description = description;
     }
-    fn get_description(&mut self) -> &mut String
+    fn get_description(&mut self) -> &mut TextComponent
     where
         Self: Sized,
     {
-        &mut self.description.text
-    }
-
-    fn get_string(&self) -> String {
-        serde_json::to_string(&self).unwrap()
-    }
-}
-
-impl StatusTrait for StatusStructOld {
-    fn get_players_online(&self) -> i32 {
-        self.players.online
-    }
-
-    fn set_description(&mut self, str: String) {
-        self.description = str;
-    }
-
-    fn get_description(&mut self) -> &mut String {
         &mut self.description
     }
 
     fn get_string(&self) -> String {
-        serde_json::to_string(&self).unwrap()
+        // Plain structs and json values, serializing them can't fail
+        serde_json::to_string(&self).unwrap_or_default()
     }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct StatusStructNew {
     pub version: StatusVersion,
-    pub enforcesSecureChat: Option<bool>,
-    pub description: StatusDescription,
+    #[serde(rename = "enforcesSecureChat")]
+    pub enforces_secure_chat: Option<bool>,
+    /// Older servers send a plain string, that parses as a text component too
+    pub description: TextComponent,
     pub players: StatusPlayers,
     #[serde(flatten)]
     extra: HashMap<String, Value>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct StatusStructOld {
-    pub version: StatusVersion,
-    pub description: String,
-    pub players: StatusPlayers,
-    #[serde(flatten)]
-    extra: HashMap<String, Value>,
-}
 impl StatusStructNew {
     pub fn create() -> StatusStructNew {
         StatusStructNew {
@@ -83,19 +70,17 @@ impl StatusStructNew {
                 name: "???".to_owned(),
                 protocol: -1,
             },
-            enforcesSecureChat: Some(false),
-            description: StatusDescription {
-                text: "Proxy default config".to_owned(),
-            },
+            enforces_secure_chat: Some(false),
+            description: TextComponent::text("Proxy default config"),
             players: StatusPlayers { max: 0, online: 0 },
             extra: HashMap::new(),
         }
     }
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct StatusDescription {
-    pub text: String,
+    /// `favicon` is a `data:image/png;base64,...` uri.
+    pub fn set_favicon(&mut self, favicon: String) {
+        self.extra
+            .insert("favicon".to_owned(), Value::String(favicon));
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -109,47 +94,60 @@ pub struct StatusPlayers {
     pub online: i32,
 }
 
-/// id: 0x00
-#[derive(Debug)]
-pub struct StatusResponse {
-    json: VarString,
-    all: Vec<u8>,
+declare_packets! {
+    Status, Clientbound;
+    StatusResponse [_ => 0x00] {
+        json: VarString,
+    }
+    PongResponse [_ => 0x01] {
+        payload: i64,
+    }
 }
 
 impl StatusResponse {
-    pub fn parse(packet: Packet) -> Option<StatusResponse> {
-        let mut reader = packet.data.into_iter();
-        Some(StatusResponse {
-            all: packet.all,
-            json: VarString::parse(&mut reader)?,
-        })
-    }
     pub fn get_string(&self) -> String {
         self.json.get_value()
     }
-    pub fn get_json(&self) -> Option<Box<dyn StatusTrait>> {
-        if let Some(json) = serde_json::from_str::<StatusStructNew>(&self.json.get_value()).ok() {
-            return Some(Box::new(json));
-        } else if let Some(json) =
-            serde_json::from_str::<StatusStructOld>(&self.json.get_value()).ok()
-        {
-            return Some(Box::new(json));
+    pub fn get_json(&self) -> ProxyResult<Box<dyn StatusTrait>> {
+        match serde_json::from_str::<StatusStructNew>(&self.json.get_value()) {
+            Ok(json) => Ok(Box::new(json)),
+            Err(err) => Err(ProxyError::Malformed(format!(
+                "status json matches no known layout: {err}"
+            ))),
         }
-        None
     }
     pub fn set_json(json: Box<dyn StatusTrait>) -> StatusResponse {
-        let vec = VarString::from(json.get_string()).move_data().unwrap();
-        StatusResponse::parse(Packet::from_bytes(0, vec).unwrap()).unwrap()
-    }
-    pub fn get_all(&self) -> Vec<u8> {
-        self.all.clone()
+        StatusResponse {
+            json: VarString::from(json.get_string()),
+        }
     }
 }
 
-impl SendPacket for StatusResponse {
-    fn send_packet(&self, stream: &mut std::net::TcpStream) -> std::io::Result<()> {
-        stream.write_all(&self.all)?;
-        stream.flush()?;
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packets::Packet, types::Codec};
+
+    fn status(json: &str) -> ProxyResult<Box<dyn StatusTrait>> {
+        let data = VarString::from(json.to_owned()).get_data().unwrap();
+        Packet::from_bytes(0, data)
+            .expect::<StatusResponse>(767)?
+            .get_json()
+    }
+
+    #[test]
+    fn old_and_new_descriptions() {
+        let players = r#""version":{"name":"1.8.9","protocol":47},"players":{"max":20,"online":1}"#;
+        let mut old = status(&format!(r#"{{{players},"description":"§aHi"}}"#)).unwrap();
+        assert_eq!(old.get_description().to_legacy(), "§aHi");
+        let mut new = status(&format!(
+            r#"{{{players},"description":{{"text":"Hi","color":"green"}},"favicon":"data:"}}"#
+        ))
+        .unwrap();
+        assert_eq!(new.get_description().to_legacy(), "§aHi");
+        assert_eq!(new.get_players_max(), 20);
+        // Fields the proxy doesn't know are passed on
+        assert!(new.get_string().contains(r#""favicon":"data:""#));
+        assert!(status("{}").is_err());
     }
 }
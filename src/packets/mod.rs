@@ -1,120 +1,238 @@
-use crate::{types::*, ProtocolState};
-use std::{
-    io::{self, Read, Write},
-    net::TcpStream,
+use crate::{
+    error::{ProxyError, ProxyResult},
+    types::*,
+    ProtocolState,
 };
+use std::{io, time::Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 pub mod clientbound;
+pub mod registry;
 pub mod serverbound;
 
+use registry::{Direction, Registered};
+
 #[derive(Debug)]
 pub struct Packet {
     pub id: VarInt,
-    length: VarInt,
     pub data: Vec<u8>,
     pub all: Vec<u8>,
 }
+/// How long and how much `Packet::read` reads before giving up.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadLimits {
+    pub timeout: Duration,
+    pub max_size: usize,
+}
+
 pub trait SendPacket {
-    fn send_packet(&self, stream: &mut TcpStream) -> io::Result<()>;
+    /// The whole packet as it goes on the wire, length prefix included.
+    fn get_all(&self) -> &[u8];
+
+    async fn send_packet<W>(&self, stream: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        stream.write_all(self.get_all()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
 }
 
 impl SendPacket for Packet {
-    fn send_packet(&self, stream: &mut TcpStream) -> io::Result<()> {
-        stream.write_all(&self.all)?;
-        Ok(())
+    fn get_all(&self) -> &[u8] {
+        &self.all
     }
 }
 
 impl Packet {
-    pub fn from_bytes(id: i32, data: Vec<u8>) -> Option<Packet> {
-        let id = VarInt::from(id)?;
-        let length = VarInt::from((data.len() + id.get_data().len()) as i32)?;
-        let mut all = length.get_data();
-        all.append(&mut id.get_data());
-        all.append(&mut data.clone());
-        Some(Packet {
-            id,
-            length,
-            data,
-            all,
-        })
-    }
-    pub fn new(id: i32, data: Vec<u8>) -> Option<Packet> {
-        let mut vec = VarInt::from(id)?.get_data();
-        vec.append(&mut data.clone());
-
-        let mut all = VarInt::from(vec.len() as i32)?.get_data();
-        all.append(&mut vec.clone());
+    pub fn from_bytes(id: i32, data: Vec<u8>) -> Packet {
+        let id = VarInt::from(id);
+        let length = VarInt::from((data.len() + id.to_bytes().len()) as i32);
+        let mut all = length.to_bytes();
+        all.append(&mut id.to_bytes());
         all.append(&mut data.clone());
-        Some(Packet {
-            id: VarInt::from(id)?,
-            length: VarInt::from(vec.len() as i32)?,
-            data,
-            all,
-        })
-    }
-    pub fn parse(buf: &mut TcpStream) -> Option<Packet> {
-        let bytes_iter = &mut buf.bytes().into_iter().map(|x| x.unwrap());
-        let length = VarInt::parse(bytes_iter)?;
+        Packet { id, data, all }
+    }
+    /// Reads exactly one packet and nothing more, so the stream can be handed to `splice` after it.
+    /// Gives up once `limits.timeout` passed or when the length is over `limits.max_size`.
+    pub async fn read<R>(buf: &mut R, limits: ReadLimits) -> ProxyResult<Packet>
+    where
+        R: AsyncRead + Unpin,
+    {
+        tokio::time::timeout(limits.timeout, Packet::parse(buf, limits.max_size))
+            .await
+            .map_err(|_| ProxyError::Timeout(limits.timeout))?
+    }
+    async fn parse<R>(buf: &mut R, max_size: usize) -> ProxyResult<Packet>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let length = Packet::read_varint(buf).await?;
         // println!("---length: {length}");
-        let id = match VarInt::parse(bytes_iter) {
-            Some(x) => x,
-            None => {
-                println!("Packet id problem(it was None)! REEEEEEEEEEEEEEEEEEEE");
-                panic!();
-                // return None;
-            }
-        };
+        if length.get_int() < 1 || length.get_int() as usize > max_size {
+            return Err(ProxyError::Malformed(format!(
+                "packet length {} is not in 1..={max_size}",
+                length.get_int()
+            )));
+        }
+
+        let mut body: Vec<u8> = vec![0; length.get_int() as usize];
+        buf.read_exact(&mut body).await?;
+        let mut reader = body.iter().copied();
+        let id = VarInt::parse(&mut reader)?;
+        // What the id took on the wire, a padded VarInt encodes shorter again
+        let id_length = body.len() - reader.len();
         // println!("---id: {id}");
         if id.get_int() == 122 {
-            return None;
+            return Err(ProxyError::malformed("packet id 0x7a"));
         }
-
-        let mut data: Vec<u8> = vec![0; length.get_int() as usize - id.get_data().len()];
-        match buf.read_exact(&mut data) {
-            Ok(_) => {
-                // data_id.append(&mut data.clone());
-                // data_length.append(&mut data_id);
-                let mut vec = id.get_data();
-                vec.append(&mut data.clone());
-                let mut all = length.get_data();
-                all.append(&mut vec);
-                Some(Packet {
-                    id,
-                    length,
-                    data,
-                    all,
-                })
-            }
-            Err(x) => {
-                if id.get_int() == 122 {
-                    return None;
-                } else {
-                    println!("len = {}: {:?}", length.get_int(), length.get_data());
-                    println!("Buffer read error: {x}");
-                    return None;
-                }
+        let data = body.split_off(id_length);
+        let mut all = length.to_bytes();
+        all.append(&mut body);
+        all.append(&mut data.clone());
+        Ok(Packet { id, data, all })
+    }
+    /// The stream isn't buffered, so the `VarInt` has to be read byte by byte.
+    async fn read_varint<R>(buf: &mut R) -> ProxyResult<VarInt>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = buf.read_u8().await?;
+            bytes.push(byte);
+            if byte & 0x80 == 0 || bytes.len() >= 5 {
+                break;
             }
         }
+        VarInt::parse(&mut bytes.into_iter())
+    }
+    /// Decodes the packet as `T`, fails if it has another id.
+    pub fn expect<T: Registered>(&self, protocol_version: i32) -> ProxyResult<T> {
+        T::decode(self, protocol_version)
     }
-    pub fn all(&self) -> Option<Vec<u8>> {
-        let mut vec = self.id.get_data();
-        vec.append(&mut self.data.clone());
-        let mut all = VarInt::from(vec.len() as i32)?.get_data();
-        all.append(&mut vec);
-        return Some(all);
-    }
-    pub fn proto_name(&self, state: &ProtocolState) -> String {
-        match state {
-            ProtocolState::Handshaking => match self.id.get_int() {
-                0 => "Handshake".to_owned(),
-                _ => "error".to_owned(),
-            },
-            ProtocolState::Status => match self.id.get_int() {
-                0 => "StatusRequest".to_owned(),
-                1 => "PingRequest".to_owned(),
-                _ => "error".to_owned(),
-            },
-            _ => "Dont care state".to_owned(),
+    /// The packet name for the logs, or its id if the proxy doesn't know it.
+    pub fn name(
+        &self,
+        state: ProtocolState,
+        direction: Direction,
+        protocol_version: i32,
+    ) -> String {
+        let id = self.id.get_int();
+        match registry::name(state, direction, protocol_version, id) {
+            Some(name) => name.to_owned(),
+            None => format!("{id:#x}"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    const LIMITS: ReadLimits = ReadLimits {
+        timeout: Duration::from_secs(5),
+        max_size: 64,
+    };
+
+    /// Reads a packet from `bytes`, then the stream stays open.
+    async fn read(bytes: &[u8]) -> ProxyResult<Packet> {
+        let (mut client, mut proxy) = duplex(1024);
+        client.write_all(bytes).await.unwrap();
+        Packet::read(&mut proxy, LIMITS).await
+    }
+
+    #[tokio::test]
+    async fn read_takes_exactly_one_packet() {
+        let first = Packet::from_bytes(0x00, vec![1, 2, 3]);
+        let second = Packet::from_bytes(0x01, vec![4]);
+        let mut stream = [first.get_all(), second.get_all()].concat();
+        stream.extend_from_slice(b"rest");
+        let mut reader = &stream[..];
+
+        let packet = Packet::read(&mut reader, LIMITS).await.unwrap();
+        assert_eq!(packet.id.get_int(), 0x00);
+        assert_eq!(packet.data, [1, 2, 3]);
+        assert_eq!(packet.get_all(), first.get_all());
+        let packet = Packet::read(&mut reader, LIMITS).await.unwrap();
+        assert_eq!(packet.id.get_int(), 0x01);
+        assert_eq!(packet.data, [4]);
+        // What comes after is left for `splice`
+        assert_eq!(reader, b"rest");
+    }
+
+    #[tokio::test]
+    async fn length_over_max_size() {
+        // Only the length is sent, the body would never come
+        assert!(matches!(read(&[65]).await, Err(ProxyError::Malformed(_))));
+        assert!(matches!(
+            read(&[0xff, 0xff, 0xff, 0xff, 0x07]).await,
+            Err(ProxyError::Malformed(_))
+        ));
+        let packet = Packet::from_bytes(0x00, vec![0; 63]);
+        assert!(read(packet.get_all()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn zero_and_negative_length() {
+        assert!(matches!(read(&[0]).await, Err(ProxyError::Malformed(_))));
+        assert!(matches!(
+            read(&[0xff, 0xff, 0xff, 0xff, 0x0f]).await,
+            Err(ProxyError::Malformed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn overlong_length() {
+        assert!(matches!(
+            read(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x01]).await,
+            Err(ProxyError::Malformed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn length_shorter_than_the_id() {
+        // The id 0x80 takes two bytes
+        assert!(matches!(
+            read(&[1, 0x80, 0x01]).await,
+            Err(ProxyError::Malformed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn truncated_body() {
+        let (mut client, mut proxy) = duplex(1024);
+        client.write_all(&[10, 0x00, 1, 2]).await.unwrap();
+        drop(client);
+        assert!(matches!(
+            Packet::read(&mut proxy, LIMITS).await,
+            Err(ProxyError::Closed)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_client_times_out() {
+        let (mut client, mut proxy) = duplex(1024);
+        // Half a packet, then nothing
+        client.write_all(&[10, 0x00]).await.unwrap();
+        let started = tokio::time::Instant::now();
+        assert!(matches!(
+            Packet::read(&mut proxy, LIMITS).await,
+            Err(ProxyError::Timeout(after)) if after == LIMITS.timeout
+        ));
+        assert_eq!(started.elapsed(), LIMITS.timeout);
+    }
+
+    #[tokio::test]
+    async fn unexpected_id() {
+        let packet = read(&[2, 0x01, 0]).await.unwrap();
+        assert!(matches!(
+            packet.expect::<serverbound::status::StatusRequest>(767),
+            Err(ProxyError::UnexpectedPacket {
+                state: ProtocolState::Status,
+                id: 0x01
+            })
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+use tokio::io::AsyncWrite;
+
+use crate::{
+    error::{ProxyError, ProxyResult},
+    packets::{clientbound, serverbound, Packet, SendPacket},
+    ProtocolState,
+};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Direction {
+    Serverbound,
+    Clientbound,
+}
+
+/// A packet declared with `declare_packets!`.
+pub trait Registered: Sized {
+    const NAME: &'static str;
+    const STATE: ProtocolState;
+
+    /// The id in `protocol_version`, None if the packet doesn't exist there.
+    fn id(protocol_version: i32) -> Option<i32>;
+    /// The fields from the packet data, the ones `protocol_version` doesn't have stay default.
+    fn decode_data(data: &[u8], protocol_version: i32) -> ProxyResult<Self>;
+    fn encode_data(&self, protocol_version: i32, data: &mut Vec<u8>) -> ProxyResult<()>;
+
+    /// Fails if `packet` has another id, bytes after the last field are ignored.
+    fn decode(packet: &Packet, protocol_version: i32) -> ProxyResult<Self> {
+        let id = packet.id.get_int();
+        if Self::id(protocol_version) != Some(id) {
+            return Err(ProxyError::UnexpectedPacket {
+                state: Self::STATE,
+                id,
+            });
+        }
+        Self::decode_data(&packet.data, protocol_version)
+    }
+
+    fn encode(&self, protocol_version: i32) -> ProxyResult<Packet> {
+        let id = Self::id(protocol_version).ok_or(ProxyError::UnsupportedVersion {
+            what: Self::NAME,
+            protocol_version,
+        })?;
+        let mut data = Vec::new();
+        self.encode_data(protocol_version, &mut data)?;
+        Ok(Packet::from_bytes(id, data))
+    }
+
+    async fn send<W>(&self, stream: &mut W, protocol_version: i32) -> ProxyResult<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        self.encode(protocol_version)?.send_packet(stream).await?;
+        Ok(())
+    }
+}
+
+/// What `declare_packets!` lists for every packet, so ids can be named in the logs.
+pub struct Entry {
+    pub name: &'static str,
+    pub state: ProtocolState,
+    pub direction: Direction,
+    pub id: fn(i32) -> Option<i32>,
+}
+
+/// Every module with packets in it.
+const REGISTRIES: [&[Entry]; 8] = [
+    serverbound::handshake::REGISTRY,
+    serverbound::status::REGISTRY,
+    serverbound::login::REGISTRY,
+    serverbound::configuration::REGISTRY,
+    clientbound::status::REGISTRY,
+    clientbound::login::REGISTRY,
+    clientbound::configuration::REGISTRY,
+    clientbound::play::REGISTRY,
+];
+
+/// The name of the packet with `id`, None if the proxy doesn't know it.
+pub fn name(
+    state: ProtocolState,
+    direction: Direction,
+    protocol_version: i32,
+    id: i32,
+) -> Option<&'static str> {
+    // A transfer logs in like any other client
+    let state = match state {
+        ProtocolState::Transfer => ProtocolState::Login,
+        x => x,
+    };
+    REGISTRIES
+        .iter()
+        .flat_map(|x| x.iter())
+        .find(|x| {
+            x.state == state && x.direction == direction && (x.id)(protocol_version) == Some(id)
+        })
+        .map(|x| x.name)
+}
+
+/// Declares the packets of one state and direction: the struct with its fields,
+/// the ids by protocol version and `REGISTRY` for the module.
+///
+/// ```ignore
+/// declare_packets! {
+///     Login, Serverbound;
+///     LoginStart [_ => 0x00] {
+///         name: VarString,
+///         uuid: Uuid [764..],
+///     }
+/// }
+/// ```
+///
+/// A field with a version pattern is only on the wire for those versions,
+/// every field type has to implement `Codec` and `Default`.
+macro_rules! declare_packets {
+    (
+        $state:ident, $direction:ident;
+        $(
+            $(#[$meta:meta])*
+            $name:ident [$($versions:pat => $id:expr),* $(,)?] {
+                $(
+                    $(#[$field_meta:meta])*
+                    $field:ident: $type:ty $([$field_versions:pat])?
+                ),* $(,)?
+            }
+        )*
+    ) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Default, PartialEq)]
+            pub struct $name {
+                $(
+                    $(#[$field_meta])*
+                    pub $field: $type,
+                )*
+            }
+
+            impl $crate::packets::registry::Registered for $name {
+                const NAME: &'static str = stringify!($name);
+                const STATE: $crate::ProtocolState = $crate::ProtocolState::$state;
+
+                fn id(protocol_version: i32) -> Option<i32> {
+                    match protocol_version {
+                        $($versions => Some($id),)*
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    }
+                }
+
+                #[allow(unused_variables, unused_mut)]
+                fn decode_data(
+                    data: &[u8],
+                    protocol_version: i32,
+                ) -> $crate::error::ProxyResult<$name> {
+                    let mut reader = data.iter().copied();
+                    let mut packet = $name::default();
+                    $(
+                        if true $(&& matches!(protocol_version, $field_versions))? {
+                            packet.$field =
+                                <$type as $crate::types::Codec>::parse(&mut reader)?;
+                        }
+                    )*
+                    Ok(packet)
+                }
+
+                #[allow(unused_variables)]
+                fn encode_data(
+                    &self,
+                    protocol_version: i32,
+                    data: &mut Vec<u8>,
+                ) -> $crate::error::ProxyResult<()> {
+                    $(
+                        if true $(&& matches!(protocol_version, $field_versions))? {
+                            $crate::types::Codec::write(&self.$field, data)?;
+                        }
+                    )*
+                    Ok(())
+                }
+            }
+        )*
+
+        /// Every packet declared in this module.
+        pub const REGISTRY: &[$crate::packets::registry::Entry] = &[
+            $(
+                $crate::packets::registry::Entry {
+                    name: stringify!($name),
+                    state: $crate::ProtocolState::$state,
+                    direction: $crate::packets::registry::Direction::$direction,
+                    id: <$name as $crate::packets::registry::Registered>::id,
+                },
+            )*
+        ];
+    };
+}
+
+pub(crate) use declare_packets;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_by_state_direction_and_version() {
+        use Direction::*;
+        use ProtocolState::*;
+        assert_eq!(name(Handshaking, Serverbound, 767, 0x00), Some("Handshake"));
+        assert_eq!(name(Status, Serverbound, 767, 0x01), Some("PingRequest"));
+        assert_eq!(name(Status, Clientbound, 767, 0x01), Some("PongResponse"));
+        assert_eq!(name(Login, Serverbound, 767, 0x00), Some("LoginStart"));
+        // A transfer speaks the login packets
+        assert_eq!(
+            name(Transfer, Serverbound, 767, 0x03),
+            Some("LoginAcknowledged")
+        );
+        assert_eq!(name(Login, Serverbound, 763, 0x03), None);
+
+        assert_eq!(
+            name(Configuration, Clientbound, 765, 0x01),
+            Some("Disconnect")
+        );
+        assert_eq!(name(Configuration, Clientbound, 766, 0x01), None);
+        assert_eq!(
+            name(Configuration, Clientbound, 766, 0x02),
+            Some("Disconnect")
+        );
+        assert_eq!(
+            name(Configuration, Clientbound, 766, 0x0b),
+            Some("Transfer")
+        );
+        assert_eq!(name(Play, Clientbound, 763, 0x1a), Some("Disconnect"));
+        assert_eq!(name(Play, Clientbound, 772, 0x1c), Some("Disconnect"));
+        // Outside of every range
+        assert_eq!(name(Play, Clientbound, 761, 0x1a), None);
+        assert_eq!(name(Play, Clientbound, 773, 0x1c), None);
+        // The same id in the other direction
+        assert_eq!(
+            name(Configuration, Serverbound, 767, 0x02),
+            Some("PluginMessage")
+        );
+    }
+
+    #[test]
+    fn packet_names_fall_back_to_the_id() {
+        let packet = Packet::from_bytes(0x2a, Vec::new());
+        assert_eq!(
+            packet.name(ProtocolState::Status, Direction::Clientbound, 767),
+            "0x2a"
+        );
+        let packet = Packet::from_bytes(0x00, Vec::new());
+        assert_eq!(
+            packet.name(ProtocolState::Status, Direction::Clientbound, 767),
+            "StatusResponse"
+        );
+    }
+}
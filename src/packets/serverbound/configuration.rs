@@ -0,0 +1,51 @@
+use crate::{
+    packets::registry::declare_packets,
+    types::{ByteArray, Identifier},
+};
+
+declare_packets! {
+    Configuration, Serverbound;
+    /// `minecraft:brand` carries the client brand (`vanilla`, `fabric`, ...) as a string.
+    PluginMessage [764..=765 => 0x01, 766.. => 0x02] {
+        channel: Identifier,
+        data: ByteArray,
+    }
+    AcknowledgeFinishConfiguration [764..=765 => 0x02, 766.. => 0x03] {}
+}
+
+impl PluginMessage {
+    pub fn is_brand(&self) -> bool {
+        self.channel.get_namespace() == "minecraft" && self.channel.get_path() == "brand"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packets::{registry::Registered, Packet},
+        types::{Codec, VarString},
+    };
+
+    #[test]
+    fn brand_plugin_message() {
+        let mut data = VarString::from("minecraft:brand".to_owned())
+            .get_data()
+            .unwrap();
+        data.extend(VarString::from("fabric".to_owned()).get_data().unwrap());
+        let message = PluginMessage::decode(&Packet::from_bytes(0x02, data.clone()), 767).unwrap();
+        assert!(message.is_brand());
+        let brand = VarString::parse(&mut message.data.get_value().iter().copied()).unwrap();
+        assert_eq!(brand.get_value(), "fabric");
+        assert_eq!(message.encode(767).unwrap().data, data);
+        assert_eq!(PluginMessage::id(765), Some(0x01));
+
+        let mut data = VarString::from("fabric:registry/sync".to_owned())
+            .get_data()
+            .unwrap();
+        data.push(0);
+        let message = PluginMessage::decode(&Packet::from_bytes(0x01, data), 765).unwrap();
+        assert!(!message.is_brand());
+        assert_eq!(AcknowledgeFinishConfiguration::id(767), Some(0x03));
+    }
+}
@@ -1,70 +1,84 @@
-use std::io::Write;
-
-use nix::NixPath;
-
 use crate::{
-    packets::{Packet, SendPacket},
-    types::{UShort, VarInt, VarString},
+    error::ProxyResult,
+    packets::{registry::declare_packets, Packet},
+    types::{VarInt, VarString},
 };
 
-/// id: 0x00
-pub struct Handshake {
-    pub protocol_version: VarInt,
-    pub server_address: VarString,
-    pub server_port: UShort,
-    pub next_state: VarInt,
-    all: Vec<u8>,
+declare_packets! {
+    Handshaking, Serverbound;
+    /// `next_state` is 1 for status, 2 for login and 3 for a transfer (1.20.5+).
+    Handshake [_ => 0x00] {
+        protocol_version: VarInt,
+        server_address: VarString,
+        server_port: u16,
+        next_state: VarInt,
+    }
 }
 
 impl Handshake {
-    pub fn parse(packet: Packet) -> Option<Handshake> {
-        let mut reader = packet.data.clone().into_iter();
-        let protocol_version = VarInt::parse(&mut reader)?;
-        let server_address = VarString::parse(&mut reader)?;
-        let server_port = UShort::parse(&mut reader)?;
-        let next_state = VarInt::parse(&mut reader)?;
-        Some(Handshake {
-            protocol_version,
-            server_address,
-            server_port,
-            next_state,
-            all: packet.all,
-        })
+    /// The handshake is what tells the protocol version, its layout is the same in all of them.
+    pub fn parse(packet: &Packet) -> ProxyResult<Handshake> {
+        packet.expect(0)
     }
     pub fn get_server_address(&self) -> String {
         self.server_address.get_value()
     }
+    pub fn get_server_port(&self) -> u16 {
+        self.server_port
+    }
     pub fn get_next_state(&self) -> i32 {
         self.next_state.get_int()
     }
-
+    pub fn get_protocol_version(&self) -> i32 {
+        self.protocol_version.get_int()
+    }
+    /// The same handshake with another next state.
+    pub fn with_next_state(&self, next_state: i32) -> Handshake {
+        Handshake {
+            next_state: VarInt::from(next_state),
+            ..self.clone()
+        }
+    }
     pub fn create(
         protocol_version: VarInt,
         server_address: VarString,
-        server_port: UShort,
+        server_port: u16,
         next_state: VarInt,
-    ) -> Option<Handshake> {
-        let mut vec = VarInt::from(0)?.get_data();
-        vec.append(&mut protocol_version.get_data());
-        vec.append(&mut server_address.get_data()?);
-        vec.append(&mut server_port.get_data());
-        vec.append(&mut next_state.get_data());
-        let mut all = VarInt::from(vec.len() as i32)?.get_data();
-        all.append(&mut vec);
-        Some(Handshake {
+    ) -> Handshake {
+        Handshake {
             protocol_version,
             server_address,
             server_port,
             next_state,
-            all,
-        })
+        }
     }
 }
 
-impl SendPacket for Handshake {
-    fn send_packet(&self, stream: &mut std::net::TcpStream) -> std::io::Result<()> {
-        stream.write_all(&self.all)?;
-        stream.flush()?;
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packets::registry::Registered, types::Codec};
+
+    #[test]
+    fn with_next_state_keeps_the_rest() {
+        let mut data = VarInt::from(767).get_data().unwrap();
+        data.append(
+            &mut VarString::from("mc.example.com".to_owned())
+                .get_data()
+                .unwrap(),
+        );
+        data.extend([0x63, 0xdd, 3]);
+        let transfer = Handshake::parse(&Packet::from_bytes(0, data.clone())).unwrap();
+        assert_eq!(transfer.get_server_port(), 25565);
+        assert_eq!(transfer.get_next_state(), 3);
+
+        let login = transfer.with_next_state(2);
+        assert_eq!(login.get_protocol_version(), 767);
+        assert_eq!(login.get_server_address(), "mc.example.com");
+        assert_eq!(login.get_next_state(), 2);
+        let encoded = login.encode(767).unwrap();
+        let (last, rest) = encoded.data.split_last().unwrap();
+        assert_eq!(*last, 2);
+        assert_eq!(rest, &data[..rest.len()]);
     }
 }
@@ -0,0 +1,170 @@
+use crate::{
+    error::ProxyResult,
+    packets::registry::declare_packets,
+    types::{Codec, Uuid, VarString},
+};
+
+declare_packets! {
+    Login, Serverbound;
+    /// The layout changed a lot between versions:
+    /// - before 1.19 (759): only the name
+    /// - 1.19 (759): name, optional signature data
+    /// - 1.19.1 (760): name, optional signature data, optional uuid
+    /// - 1.19.3 - 1.20.1 (761 - 763): name, optional uuid
+    /// - 1.20.2+ (764): name, uuid
+    LoginStart [_ => 0x00] {
+        name: VarString,
+        signature_data: Option<SignatureData> [759..=760],
+        optional_uuid: Option<Uuid> [760..=763],
+        uuid: Uuid [764..],
+    }
+    /// 1.20.2+, the client got the Login Success and goes on to Configuration.
+    LoginAcknowledged [764.. => 0x03] {}
+}
+
+impl LoginStart {
+    pub fn get_name(&self) -> String {
+        self.name.get_value()
+    }
+    pub fn get_uuid(&self, protocol_version: i32) -> Option<Uuid> {
+        if protocol_version >= 764 {
+            Some(self.uuid)
+        } else {
+            self.optional_uuid
+        }
+    }
+}
+
+/// 1.19 - 1.19.1: the key the client signs its chat with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SignatureData {
+    pub timestamp: i64,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl Codec for SignatureData {
+    fn parse<I>(data: &mut I) -> ProxyResult<SignatureData>
+    where
+        I: Iterator<Item = u8>,
+    {
+        Ok(SignatureData {
+            timestamp: i64::parse(data)?,
+            public_key: Vec::parse(data)?,
+            signature: Vec::parse(data)?,
+        })
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        self.timestamp.write(data)?;
+        self.public_key.write(data)?;
+        self.signature.write(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::ProxyError,
+        packets::{registry::Registered, Packet},
+    };
+
+    const UUID: [u8; 16] = [
+        0x06, 0x9a, 0x79, 0xf4, 0x44, 0xe9, 0x47, 0x26, 0xa5, 0xbe, 0xfc, 0xa9, 0x0e, 0x38, 0xaa,
+        0xf5,
+    ];
+
+    /// The name, then `rest` as the version specific part.
+    fn login_start(protocol_version: i32, rest: &[u8]) -> ProxyResult<LoginStart> {
+        let mut data = VarString::from("Notch".to_owned()).get_data().unwrap();
+        data.extend_from_slice(rest);
+        let packet = LoginStart::decode(&Packet::from_bytes(0x00, data.clone()), protocol_version)?;
+        // Every field is read, so encoding it again gives the same bytes
+        assert_eq!(packet.encode(protocol_version).unwrap().data, data);
+        Ok(packet)
+    }
+
+    fn signature_data() -> Vec<u8> {
+        let mut data = vec![1];
+        data.extend_from_slice(&1_700_000_000_000i64.to_be_bytes());
+        data.extend_from_slice(&[3, 0xaa, 0xbb, 0xcc]);
+        data.extend_from_slice(&[2, 0xdd, 0xee]);
+        data
+    }
+
+    #[test]
+    fn name_only_before_1_19() {
+        let packet = login_start(758, &[]).unwrap();
+        assert_eq!(packet.get_name(), "Notch");
+        assert_eq!(packet.get_uuid(758), None);
+    }
+
+    #[test]
+    fn signature_data_in_1_19() {
+        let packet = login_start(759, &signature_data()).unwrap();
+        assert_eq!(packet.get_name(), "Notch");
+        assert_eq!(packet.get_uuid(759), None);
+        let key = packet.signature_data.unwrap();
+        assert_eq!(key.timestamp, 1_700_000_000_000);
+        assert_eq!(key.public_key, [0xaa, 0xbb, 0xcc]);
+        assert_eq!(key.signature, [0xdd, 0xee]);
+        assert!(login_start(759, &[0]).is_ok());
+        // Cut off in the middle of the key
+        assert!(login_start(759, &signature_data()[..11]).is_err());
+    }
+
+    #[test]
+    fn signature_data_and_optional_uuid_in_1_19_1() {
+        let mut rest = signature_data();
+        rest.push(1);
+        rest.extend_from_slice(&UUID);
+        let packet = login_start(760, &rest).unwrap();
+        assert!(packet.signature_data.is_some());
+        assert_eq!(
+            packet.get_uuid(760).unwrap().to_string(),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+        );
+        assert_eq!(login_start(760, &[0, 0]).unwrap().get_uuid(760), None);
+    }
+
+    #[test]
+    fn optional_uuid_until_1_20_1() {
+        let mut rest = vec![1];
+        rest.extend_from_slice(&UUID);
+        assert!(login_start(763, &rest).unwrap().get_uuid(763).is_some());
+        assert_eq!(login_start(763, &[0]).unwrap().get_uuid(763), None);
+        assert!(login_start(763, &[]).is_err());
+    }
+
+    #[test]
+    fn uuid_from_1_20_2() {
+        for protocol_version in [764, 767, 772] {
+            let packet = login_start(protocol_version, &UUID).unwrap();
+            assert_eq!(
+                packet.get_uuid(protocol_version).unwrap().to_string(),
+                "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+            );
+            assert!(login_start(protocol_version, &UUID[..15]).is_err());
+        }
+    }
+
+    #[test]
+    fn login_acknowledged_from_1_20_2() {
+        assert_eq!(LoginAcknowledged::id(764), Some(0x03));
+        assert_eq!(LoginAcknowledged::id(763), None);
+        assert!(matches!(
+            LoginAcknowledged {}.encode(763),
+            Err(ProxyError::UnsupportedVersion {
+                what: "LoginAcknowledged",
+                protocol_version: 763
+            })
+        ));
+        // Another id in the same state
+        let packet = Packet::from_bytes(0x00, Vec::new());
+        assert!(matches!(
+            LoginAcknowledged::decode(&packet, 764),
+            Err(ProxyError::UnexpectedPacket { id: 0x00, .. })
+        ));
+    }
+}
@@ -1,2 +1,4 @@
+pub mod configuration;
 pub mod handshake;
+pub mod login;
 pub mod status;
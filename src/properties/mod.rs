@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::config::BackendConfig;
+
+/// What the server directory tells about a server that never ran under the proxy,
+/// read once when the proxy starts.
+#[derive(Default, Clone)]
+pub struct ServerProperties {
+    /// `motd` from `server.properties`
+    pub motd: Option<String>,
+    /// `max-players` from `server.properties`
+    pub max_players: Option<i32>,
+    /// `server-port` from `server.properties`
+    pub port: Option<u16>,
+    /// `server-icon.png` as a data uri, ready for the status `favicon`
+    pub favicon: Option<String>,
+}
+
+impl ServerProperties {
+    /// Empty without `server_dir`, missing files leave their values out.
+    pub fn create(backend: &BackendConfig) -> ServerProperties {
+        let dir = match &backend.server_dir {
+            Some(x) => x,
+            None => return ServerProperties::default(),
+        };
+        let mut properties = ServerProperties::default();
+        match std::fs::read_to_string(dir.join("server.properties")) {
+            Ok(text) => {
+                for (key, value) in parse(&text) {
+                    match key.as_str() {
+                        "motd" => properties.motd = Some(value),
+                        "max-players" => properties.max_players = value.parse().ok(),
+                        "server-port" => properties.port = value.parse().ok(),
+                        _ => (),
+                    }
+                }
+            }
+            Err(err) => println!(
+                "PROXY: {}: can't read server.properties in {}: {err}",
+                backend.name,
+                dir.display()
+            ),
+        }
+        properties.favicon = read_icon(&dir.join("server-icon.png"));
+        if let (Some(port), Some((_, address_port))) =
+            (properties.port, backend.address.rsplit_once(':'))
+        {
+            if address_port.parse() != Ok(port) {
+                println!(
+                    "PROXY: {}: server.properties has server-port={port}, but the backend address is {}",
+                    backend.name, backend.address
+                );
+            }
+        }
+        properties
+    }
+}
+
+fn read_icon(path: &Path) -> Option<String> {
+    let icon = std::fs::read(path).ok()?;
+    Some(format!("data:image/png;base64,{}", STANDARD.encode(icon)))
+}
+
+/// The java properties format, as far as `server.properties` uses it:
+/// `key=value` lines, `#` comments and backslash escapes (`\u00A7` for `§`).
+fn parse(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(str::trim_start)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once(['=', ':'])?;
+            Some((unescape(key.trim_end()), unescape(value.trim_start())))
+        })
+        .collect()
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            out.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(x) => out.push(x),
+                    None => out.push_str(&hex),
+                }
+            }
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(x) => out.push(x),
+            None => (),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_properties() {
+        let text = "#Minecraft server properties\n\
+                    ! also a comment\n\
+                    motd=\\u00A7aA Minecraft Server\\nline two\n\
+                    max-players = 20\n\
+                    level-name:world\n\
+                    \n\
+                    broken line\n\
+                    bad=\\u00zz\n";
+        assert_eq!(
+            parse(text),
+            [
+                ("motd", "§aA Minecraft Server\nline two"),
+                ("max-players", "20"),
+                ("level-name", "world"),
+                ("bad", "00zz"),
+            ]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        );
+    }
+
+    #[test]
+    fn from_the_server_dir() {
+        let dir = std::env::temp_dir().join(format!("mc-proxy-test-{}-props", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("server.properties"),
+            "motd=Hi\nmax-players=not a number\nserver-port=25566\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("server-icon.png"), b"png").unwrap();
+        let backend = BackendConfig {
+            server_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let properties = ServerProperties::create(&backend);
+        assert_eq!(properties.motd.as_deref(), Some("Hi"));
+        assert_eq!(properties.max_players, None);
+        assert_eq!(properties.port, Some(25566));
+        assert_eq!(
+            properties.favicon.as_deref(),
+            Some("data:image/png;base64,cG5n")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let properties = ServerProperties::create(&backend);
+        assert_eq!(properties.motd, None);
+        assert_eq!(properties.favicon, None);
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use crate::config::{BucketConfig, RateLimitConfig};
+
+/// At most this many connections over the limits are told why at once, the rest are just closed.
+const MAX_REJECTERS: usize = 32;
+
+/// Above this many tracked ips the full (idle) buckets get dropped,
+/// and if that isn't enough the ones touched longest ago.
+const MAX_TRACKED_IPS: usize = 4096;
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn take(&mut self, config: &BucketConfig) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * config.per_minute as f64 / 60.0).min(config.burst as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+    fn is_full(&self, config: &BucketConfig) -> bool {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.tokens + elapsed * config.per_minute as f64 / 60.0 >= config.burst as f64
+    }
+}
+
+/// One token bucket per ip.
+struct IpBuckets {
+    config: Option<BucketConfig>,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl IpBuckets {
+    fn create(config: Option<BucketConfig>) -> IpBuckets {
+        IpBuckets {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+    fn take(&self, ip: IpAddr) -> bool {
+        let config = match &self.config {
+            Some(x) => x,
+            None => return true,
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_IPS {
+            buckets.retain(|_, bucket| !bucket.is_full(config));
+        }
+        if buckets.len() >= MAX_TRACKED_IPS {
+            // A spray of new ips leaves every bucket drained, keep the 3/4 used most recently
+            let mut refills: Vec<Instant> = buckets.values().map(|x| x.last_refill).collect();
+            let cutoff_index = refills.len() - MAX_TRACKED_IPS * 3 / 4;
+            let (_, cutoff, _) = refills.select_nth_unstable(cutoff_index);
+            let cutoff = *cutoff;
+            buckets.retain(|_, bucket| bucket.last_refill > cutoff);
+        }
+        buckets
+            .entry(bucket_key(ip))
+            .or_insert(TokenBucket {
+                tokens: config.burst as f64,
+                last_refill: Instant::now(),
+            })
+            .take(config)
+    }
+}
+
+/// Everybody gets a whole /64 of ipv6 addresses, so those share one bucket.
+fn bucket_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6((v6.to_bits() & !0u128 << 64).into()),
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Rejection {
+    /// Too many connections from one ip
+    Connections,
+    /// Too many status pings from one ip
+    Status,
+    /// `max_sessions` reached
+    Sessions,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Rejection::Connections => "connections per ip",
+            Rejection::Status => "status pings per ip",
+            Rejection::Sessions => "max sessions",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Decides which clients get served; shared by every listener.
+pub struct RateLimiter {
+    connections: IpBuckets,
+    status: IpBuckets,
+    max_sessions: Option<usize>,
+    sessions: Arc<AtomicUsize>,
+    rejecters: Arc<AtomicUsize>,
+    rejected_connections: AtomicU64,
+    rejected_status: AtomicU64,
+    rejected_sessions: AtomicU64,
+}
+
+/// Holds one of the `max_sessions` (or rejecter) slots until dropped.
+pub struct Slot {
+    taken: Arc<AtomicUsize>,
+}
+
+impl Slot {
+    /// Takes a slot of `taken`, even when there are already `max` of them.
+    fn take(taken: &Arc<AtomicUsize>, max: Option<usize>) -> (Slot, bool) {
+        let before = taken.fetch_add(1, Ordering::SeqCst);
+        let slot = Slot {
+            taken: taken.clone(),
+        };
+        (slot, max.is_none_or(|max| before < max))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.taken.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl RateLimiter {
+    pub fn create(config: &RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            connections: IpBuckets::create(config.connections_per_ip.clone()),
+            status: IpBuckets::create(config.status_per_ip.clone()),
+            max_sessions: config.max_sessions,
+            sessions: Arc::new(AtomicUsize::new(0)),
+            rejecters: Arc::new(AtomicUsize::new(0)),
+            rejected_connections: AtomicU64::new(0),
+            rejected_status: AtomicU64::new(0),
+            rejected_sessions: AtomicU64::new(0),
+        }
+    }
+
+    /// Called for every accepted connection before anything is read from it,
+    /// the slot has to live as long as the session.
+    pub fn admit(&self, ip: IpAddr) -> Result<Slot, Rejection> {
+        if !self.connections.take(ip) {
+            self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+            return Err(Rejection::Connections);
+        }
+        let (slot, free) = Slot::take(&self.sessions, self.max_sessions);
+        if !free {
+            self.rejected_sessions.fetch_add(1, Ordering::Relaxed);
+            return Err(Rejection::Sessions);
+        }
+        Ok(slot)
+    }
+
+    /// A slot to tell a connection `admit` turned away why, None while `MAX_REJECTERS` are busy.
+    pub fn rejecter(&self) -> Option<Slot> {
+        let (slot, free) = Slot::take(&self.rejecters, Some(MAX_REJECTERS));
+        free.then_some(slot)
+    }
+
+    /// Called once the handshake says the connection is a status ping.
+    pub fn check_status(&self, ip: IpAddr) -> Result<(), Rejection> {
+        if !self.status.take(ip) {
+            self.rejected_status.fetch_add(1, Ordering::Relaxed);
+            return Err(Rejection::Status);
+        }
+        Ok(())
+    }
+
+    pub fn active_sessions(&self) -> usize {
+        self.sessions.load(Ordering::SeqCst)
+    }
+
+    pub fn rejected(&self, rejection: Rejection) -> u64 {
+        match rejection {
+            Rejection::Connections => &self.rejected_connections,
+            Rejection::Status => &self.rejected_status,
+            Rejection::Sessions => &self.rejected_sessions,
+        }
+        .load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    fn buckets(burst: u32) -> IpBuckets {
+        IpBuckets::create(Some(BucketConfig {
+            burst,
+            per_minute: 1,
+        }))
+    }
+
+    fn limiter(text: &str) -> RateLimiter {
+        let config = crate::config::Config::parse(text).unwrap();
+        RateLimiter::create(&config.rate_limit)
+    }
+
+    #[test]
+    fn bucket_runs_out_per_ip() {
+        let buckets = buckets(2);
+        assert!(buckets.take(ip(1)));
+        assert!(buckets.take(ip(1)));
+        assert!(!buckets.take(ip(1)));
+        assert!(buckets.take(ip(2)));
+    }
+
+    #[test]
+    fn bucket_refills() {
+        let mut bucket = TokenBucket {
+            tokens: 0.0,
+            last_refill: Instant::now() - std::time::Duration::from_secs(30),
+        };
+        let config = BucketConfig {
+            burst: 5,
+            per_minute: 4,
+        };
+        // Half a minute of 4 per minute
+        assert!(bucket.take(&config));
+        assert!(bucket.take(&config));
+        assert!(!bucket.take(&config));
+    }
+
+    #[test]
+    fn no_limits_by_default() {
+        let limiter = limiter("");
+        for _ in 0..100 {
+            assert!(limiter.admit(ip(1)).is_ok());
+            assert!(limiter.check_status(ip(1)).is_ok());
+        }
+    }
+
+    #[test]
+    fn status_pings_count_on_top_of_connections() {
+        let limiter = limiter(
+            "[rate_limit]\nstatus_per_ip = { burst = 1, per_minute = 1 }\nconnections_per_ip = { burst = 3, per_minute = 1 }",
+        );
+        assert!(limiter.admit(ip(1)).is_ok());
+        assert!(limiter.check_status(ip(1)).is_ok());
+        assert!(limiter.admit(ip(1)).is_ok());
+        assert!(matches!(
+            limiter.check_status(ip(1)),
+            Err(Rejection::Status)
+        ));
+        assert!(limiter.admit(ip(1)).is_ok());
+        assert!(matches!(limiter.admit(ip(1)), Err(Rejection::Connections)));
+        assert_eq!(limiter.rejected(Rejection::Status), 1);
+        assert_eq!(limiter.rejected(Rejection::Connections), 1);
+    }
+
+    #[test]
+    fn sessions_are_freed_when_the_guard_drops() {
+        let limiter = limiter("[rate_limit]\nmax_sessions = 2");
+        let first = limiter.admit(ip(1)).unwrap();
+        let _second = limiter.admit(ip(2)).unwrap();
+        assert!(matches!(limiter.admit(ip(3)), Err(Rejection::Sessions)));
+        assert_eq!(limiter.active_sessions(), 2);
+        drop(first);
+        assert!(limiter.admit(ip(3)).is_ok());
+        assert_eq!(limiter.rejected(Rejection::Sessions), 1);
+    }
+
+    #[test]
+    fn rejecters_are_capped() {
+        let limiter = limiter("");
+        let busy: Vec<Slot> = (0..MAX_REJECTERS)
+            .map(|_| limiter.rejecter().unwrap())
+            .collect();
+        assert!(limiter.rejecter().is_none());
+        drop(busy);
+        assert!(limiter.rejecter().is_some());
+    }
+
+    #[test]
+    fn ipv6_shares_a_bucket_per_64() {
+        let buckets = buckets(1);
+        assert!(buckets.take("2001:db8::1".parse().unwrap()));
+        assert!(!buckets.take("2001:db8::ffff:1234".parse().unwrap()));
+        assert!(buckets.take("2001:db8:0:1::1".parse().unwrap()));
+        let mapped = IpAddr::V6(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped());
+        assert_eq!(bucket_key(mapped), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn drained_buckets_are_capped() {
+        let buckets = buckets(1);
+        for i in 0..MAX_TRACKED_IPS as u32 * 3 {
+            buckets.take(IpAddr::V4(Ipv4Addr::from(i)));
+        }
+        assert!(buckets.buckets.lock().unwrap().len() <= MAX_TRACKED_IPS);
+        // The most recent ones are still limited
+        let last = IpAddr::V4(Ipv4Addr::from(MAX_TRACKED_IPS as u32 * 3 - 1));
+        assert!(!buckets.take(last));
+    }
+}
@@ -0,0 +1,286 @@
+use std::sync::{Arc, Mutex};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    allowlist::AllowList,
+    config::{BackendConfig, Config},
+    error::ProxyResult,
+    mincraft_server::{format_duration, MinecraftServerHandler},
+    properties::ServerProperties,
+    template::Vars,
+};
+
+/// A single minecraft server the proxy can route to.
+pub struct Backend {
+    pub config: BackendConfig,
+    pub handler: Arc<Mutex<MinecraftServerHandler>>,
+    /// Who can start the server by joining it
+    pub allow_list: AllowList,
+    /// From `server_dir`, for the status before the server answered one
+    pub properties: ServerProperties,
+}
+
+/// What the admin interfaces show about a backend.
+#[derive(Serialize, Deserialize)]
+pub struct BackendStatus {
+    pub name: String,
+    pub address: String,
+    pub state: String,
+    pub running: bool,
+    pub players_online: Option<i32>,
+    pub uptime_seconds: Option<u64>,
+    pub starts: u64,
+    pub time_to_ready_seconds: Option<f64>,
+    /// While starting, what the console last reported
+    pub progress: Option<String>,
+}
+
+/// What the admin interfaces can do with a backend.
+pub enum BackendAction {
+    Start,
+    /// Sends `stop` to the console
+    Stop,
+    /// SIGKILL, for when `stop` doesn't work
+    Kill,
+    Command(String),
+}
+
+impl Backend {
+    pub fn status(&self) -> BackendStatus {
+        let stats = self.handler.lock().unwrap().stats();
+        BackendStatus {
+            name: self.config.name.clone(),
+            address: self.config.address.clone(),
+            state: stats.state.to_string(),
+            running: stats.state.is_alive(),
+            players_online: stats.players_online,
+            uptime_seconds: stats.uptime.map(|x| x.as_secs()),
+            starts: stats.starts,
+            time_to_ready_seconds: stats.ready_after.map(|x| x.as_secs_f64()),
+            progress: stats.progress,
+        }
+    }
+
+    /// The placeholders every message about this backend can use, see `template::Vars`.
+    pub fn template_vars(&self) -> Vars {
+        let (stats, start_progress, last_status) = {
+            let handler = self.handler.lock().unwrap();
+            (
+                handler.stats(),
+                handler.start_progress(),
+                handler.last_status(),
+            )
+        };
+        let mut vars = Vars::create();
+        vars.set("state", stats.state)
+            .set_opt("players", stats.players_online)
+            .set_opt(
+                "max",
+                last_status
+                    .as_ref()
+                    .map(|x| x.players.max)
+                    .or(self.properties.max_players),
+            )
+            .set_opt("version", last_status.map(|x| x.version.name))
+            .set_opt("uptime", stats.uptime.map(format_duration))
+            .set_opt("idle_remaining", stats.idle_remaining.map(format_duration))
+            .set_opt("motd", self.properties.motd.as_ref());
+        if let Some(start_progress) = start_progress {
+            vars.set_opt("progress", start_progress.progress.as_ref())
+                .set("elapsed", format_duration(start_progress.elapsed))
+                .set_opt("eta", start_progress.eta_text());
+        }
+        vars
+    }
+
+    /// `from` names the interface in the logs.
+    pub fn apply(&self, action: BackendAction, from: &str) -> ProxyResult<()> {
+        let name = &self.config.name;
+        let mut handler = self.handler.lock().unwrap();
+        match action {
+            BackendAction::Start => {
+                println!("{from}: starting {name}");
+                handler.start_minecraft_server()
+            }
+            BackendAction::Stop => {
+                println!("{from}: stopping {name}");
+                handler.stop_minecraft_server()
+            }
+            BackendAction::Kill => {
+                println!("{from}: killing {name}");
+                handler.kill_minecraft_server()
+            }
+            BackendAction::Command(command) => {
+                // A newline would smuggle in a second command
+                let command = command.lines().next().unwrap_or_default().to_owned();
+                println!("{from}: {name} <- {command}");
+                handler.send_command(command)
+            }
+        }
+    }
+}
+
+/// Picks the backend for a client based on the hostname from its handshake.
+pub struct Router {
+    backends: Vec<Arc<Backend>>,
+}
+
+impl Router {
+    pub fn create(config: &Config) -> Router {
+        let backends = config
+            .backends
+            .iter()
+            .map(|backend| {
+                Arc::new(Backend {
+                    config: backend.clone(),
+                    handler: MinecraftServerHandler::create(backend, config),
+                    allow_list: AllowList::create(backend),
+                    properties: ServerProperties::create(backend),
+                })
+            })
+            .collect();
+        Router { backends }
+    }
+
+    pub fn backends(&self) -> &[Arc<Backend>] {
+        &self.backends
+    }
+
+    /// By the name from the config, for the admin interfaces.
+    pub fn backend(&self, name: &str) -> Option<Arc<Backend>> {
+        self.backends
+            .iter()
+            .find(|x| x.config.name == name)
+            .cloned()
+    }
+
+    /// Exact hostnames win over `*.domain` wildcards (longest first), `*` is the fallback.
+    /// Only backends whose `joins` take the join are considered.
+    pub fn route(&self, server_address: &str, transferred: bool) -> Option<Arc<Backend>> {
+        let hostname = Router::normalize(server_address);
+        let mut best: Option<(usize, &Arc<Backend>)> = None;
+        for backend in &self.backends {
+            if !backend.config.joins.takes(transferred) {
+                continue;
+            }
+            for pattern in &backend.config.hostnames {
+                let pattern = pattern.to_lowercase();
+                let score = if pattern == hostname {
+                    usize::MAX
+                } else if pattern == "*" {
+                    0
+                } else if let Some(suffix) = pattern.strip_prefix('*') {
+                    if hostname.ends_with(suffix) {
+                        suffix.len()
+                    } else {
+                        continue;
+                    }
+                } else {
+                    continue;
+                };
+                if best.is_none_or(|(best_score, _)| score > best_score) {
+                    best = Some((score, backend));
+                }
+            }
+        }
+        best.map(|(_, backend)| backend.clone())
+    }
+
+    /// Forge appends `\0FML\0` and some clients send a trailing dot, neither is part of the hostname.
+    pub fn normalize(server_address: &str) -> String {
+        let hostname = server_address.split('\0').next().unwrap_or_default();
+        hostname.trim_end_matches('.').to_lowercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(backends: &[(&str, &[&str])]) -> Router {
+        let config = Config {
+            backends: backends
+                .iter()
+                .map(|(name, hostnames)| BackendConfig {
+                    name: name.to_string(),
+                    hostnames: hostnames.iter().map(|x| x.to_string()).collect(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        Router::create(&config)
+    }
+
+    fn route(router: &Router, server_address: &str) -> Option<String> {
+        router
+            .route(server_address, false)
+            .map(|backend| backend.config.name.clone())
+    }
+
+    #[test]
+    fn exact_then_longest_wildcard_then_default() {
+        let router = router(&[
+            ("default", &["*"]),
+            ("example", &["*.example.com"]),
+            ("modded", &["*.modded.example.com", "play.example.com"]),
+            ("exact", &["mc.modded.example.com"]),
+        ]);
+        assert_eq!(route(&router, "mc.modded.example.com").unwrap(), "exact");
+        assert_eq!(route(&router, "a.modded.example.com").unwrap(), "modded");
+        assert_eq!(route(&router, "play.example.com").unwrap(), "modded");
+        assert_eq!(route(&router, "www.example.com").unwrap(), "example");
+        // The wildcard needs a subdomain
+        assert_eq!(route(&router, "example.com").unwrap(), "default");
+        assert_eq!(route(&router, "localhost").unwrap(), "default");
+    }
+
+    #[test]
+    fn unknown_without_default() {
+        let router = router(&[("example", &["*.example.com"])]);
+        assert_eq!(route(&router, "example.org"), None);
+        assert_eq!(route(&router, ""), None);
+    }
+
+    #[test]
+    fn hostname_is_normalized() {
+        let router = router(&[("default", &["*"]), ("exact", &["MC.example.com"])]);
+        assert_eq!(route(&router, "mc.Example.COM.").unwrap(), "exact");
+        assert_eq!(route(&router, "mc.example.com\0FML\0").unwrap(), "exact");
+    }
+
+    #[test]
+    fn fresh_and_transferred_joins() {
+        let config = Config::parse(
+            r#"
+            [[backends]]
+            name = "lobby"
+            joins = "fresh"
+            [[backends]]
+            name = "survival"
+            joins = "transferred"
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let router = Router::create(&config);
+        let name = |transferred| router.route("mc.example.com", transferred).unwrap();
+        assert_eq!(name(false).config.name, "lobby");
+        assert_eq!(name(true).config.name, "survival");
+
+        let config = Config::parse("[[backends]]\njoins = \"fresh\"").unwrap();
+        let router = Router::create(&config);
+        assert!(router.route("mc.example.com", true).is_none());
+    }
+
+    #[test]
+    fn template_vars_of_a_stopped_backend() {
+        let router = router(&[("default", &["*"])]);
+        let vars = router.backends()[0].template_vars();
+        assert_eq!(
+            vars.render("{state}{?players} {players}{/players}{!eta}, no eta{/eta}"),
+            "stopped, no eta"
+        );
+    }
+}
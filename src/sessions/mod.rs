@@ -0,0 +1,199 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFuture};
+
+use crate::{
+    metrics::{self, SessionMetrics},
+    ProtocolState,
+};
+
+/// Every open client connection, so they can be listed and kicked.
+pub struct SessionTable {
+    next_id: AtomicU64,
+    sessions: Arc<Mutex<BTreeMap<u64, Session>>>,
+}
+
+struct Session {
+    addr: SocketAddr,
+    state: ProtocolState,
+    player: Option<String>,
+    backend: Option<String>,
+    since: Instant,
+    cancel: CancellationToken,
+}
+
+/// What the control socket lists for a session.
+#[derive(Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub state: String,
+    pub player: Option<String>,
+    pub backend: Option<String>,
+    pub connected_seconds: u64,
+}
+
+/// Keeps its session in the table (and in the metrics) until dropped.
+pub struct SessionHandle {
+    id: u64,
+    sessions: Arc<Mutex<BTreeMap<u64, Session>>>,
+    metrics: SessionMetrics,
+    cancel: CancellationToken,
+}
+
+impl SessionHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Done once the session got kicked (or the proxy is shutting down),
+    /// the connection task is dropped a moment later.
+    pub fn cancelled(&self) -> WaitForCancellationFuture<'_> {
+        self.cancel.cancelled()
+    }
+
+    pub fn set_state(&mut self, state: ProtocolState) {
+        self.metrics.set_state(state);
+        self.update(|session| session.state = state);
+    }
+
+    pub fn set_player(&self, player: String) {
+        self.update(|session| session.player = Some(player));
+    }
+
+    pub fn set_backend(&self, backend: String) {
+        self.update(|session| session.backend = Some(backend));
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Session)) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(&self.id) {
+            change(session);
+        }
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl SessionTable {
+    pub fn create() -> SessionTable {
+        SessionTable {
+            next_id: AtomicU64::new(1),
+            sessions: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// `cancel` is what `kick` cancels, it has to end the connection task.
+    pub fn register(
+        &self,
+        addr: SocketAddr,
+        cancel: CancellationToken,
+        metrics: SessionMetrics,
+    ) -> SessionHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.sessions.lock().unwrap().insert(
+            id,
+            Session {
+                addr,
+                state: ProtocolState::Handshaking,
+                player: None,
+                backend: None,
+                since: Instant::now(),
+                cancel: cancel.clone(),
+            },
+        );
+        SessionHandle {
+            id,
+            sessions: self.sessions.clone(),
+            metrics,
+            cancel,
+        }
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, session)| SessionInfo {
+                id: *id,
+                addr: session.addr,
+                state: metrics::state_label(session.state).to_owned(),
+                player: session.player.clone(),
+                backend: session.backend.clone(),
+                connected_seconds: session.since.elapsed().as_secs(),
+            })
+            .collect()
+    }
+
+    /// How many sessions are spliced to a server.
+    pub fn playing(&self) -> usize {
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|session| session.state == ProtocolState::Play)
+            .count()
+    }
+
+    /// Returns false if there is no session with `id`.
+    pub fn kick(&self, id: u64) -> bool {
+        match self.sessions.lock().unwrap().get(&id) {
+            Some(session) => {
+                session.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn list_and_kick() {
+        let metrics = Metrics::create();
+        let table = SessionTable::create();
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let cancel = CancellationToken::new();
+        let mut first = table.register(addr, cancel.clone(), metrics.connected());
+        let second = table.register(addr, CancellationToken::new(), metrics.connected());
+        assert_ne!(first.id(), second.id());
+
+        first.set_state(ProtocolState::Login);
+        first.set_player("Notch".to_owned());
+        first.set_backend("survival".to_owned());
+        let list = table.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id, first.id());
+        assert_eq!(list[0].state, "login");
+        assert_eq!(list[0].player.as_deref(), Some("Notch"));
+        assert_eq!(list[0].backend.as_deref(), Some("survival"));
+        assert_eq!(list[1].state, "handshaking");
+        assert_eq!(list[1].player, None);
+
+        assert!(table.kick(first.id()));
+        assert!(cancel.is_cancelled());
+        assert!(!table.kick(1000));
+
+        drop(second);
+        assert_eq!(table.list().len(), 1);
+        drop(first);
+        assert!(table.list().is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+use std::{collections::HashMap, fmt::Display};
+
+/// The placeholders messages can use, anything else in braces is left as it is.
+pub const PLACEHOLDERS: [&str; 15] = [
+    "state",
+    "players",
+    "max",
+    "uptime",
+    "idle_remaining",
+    "eta",
+    "elapsed",
+    "progress",
+    "hostname",
+    "player",
+    "motd",
+    "version",
+    "rev",
+    "client_version",
+    "supported",
+];
+
+/// The values for the placeholders of one message.
+///
+/// - `{name}` is replaced by the value, or nothing if it isn't known
+/// - `{?name}...{/name}` only shows when `name` is known
+/// - `{!name}...{/name}` only shows when it isn't
+/// - `{{` is a plain `{`
+#[derive(Clone)]
+pub struct Vars {
+    values: HashMap<&'static str, String>,
+}
+
+enum Node<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    Section {
+        name: &'a str,
+        when_set: bool,
+        body: Vec<Node<'a>>,
+    },
+}
+
+impl Vars {
+    /// `{rev}` is always known.
+    pub fn create() -> Vars {
+        let commit_hash: &'static str = env!(
+            "COMMIT_HASH",
+            "No COMMIT_HASH env var during build, but build.rs should always set it?"
+        );
+        let mut vars = Vars {
+            values: HashMap::new(),
+        };
+        vars.set("rev", commit_hash);
+        vars
+    }
+
+    /// `name` has to be one of `PLACEHOLDERS`, an empty value counts as unknown.
+    pub fn set(&mut self, name: &'static str, value: impl Display) -> &mut Vars {
+        let value = value.to_string();
+        if value.is_empty() {
+            self.values.remove(name);
+        } else {
+            self.values.insert(name, value);
+        }
+        self
+    }
+
+    pub fn set_opt(&mut self, name: &'static str, value: Option<impl Display>) -> &mut Vars {
+        match value {
+            Some(value) => self.set(name, value),
+            None => {
+                self.values.remove(name);
+                self
+            }
+        }
+    }
+
+    /// A template that doesn't parse (`check` rules that out for the config) comes back as it is.
+    pub fn render(&self, template: &str) -> String {
+        match parse(template) {
+            Ok(nodes) => {
+                let mut out = String::with_capacity(template.len());
+                self.write(&nodes, &mut out);
+                out
+            }
+            Err(_) => template.to_owned(),
+        }
+    }
+
+    fn write(&self, nodes: &[Node], out: &mut String) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Var(name) => {
+                    if let Some(value) = self.values.get(name) {
+                        out.push_str(value);
+                    }
+                }
+                Node::Section {
+                    name,
+                    when_set,
+                    body,
+                } => {
+                    if self.values.contains_key(name) == *when_set {
+                        self.write(body, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether `template` parses, for the config validation.
+pub fn check(template: &str) -> Result<(), String> {
+    parse(template).map(|_| ())
+}
+
+fn parse(template: &str) -> Result<Vec<Node<'_>>, String> {
+    // The sections still open, with what was parsed before each of them
+    let mut open: Vec<(&str, bool, Vec<Node>)> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            nodes.push(Node::Text(&rest[..start]));
+        }
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{{") {
+            nodes.push(Node::Text("{"));
+            rest = after;
+            continue;
+        }
+        let tag = match rest.find('}') {
+            Some(end) => &rest[1..end],
+            None => break,
+        };
+        let (kind, name) = match tag.strip_prefix(['?', '!', '/']) {
+            Some(name) => (&tag[..1], name),
+            None => ("", tag),
+        };
+        if !PLACEHOLDERS.contains(&name) {
+            nodes.push(Node::Text("{"));
+            rest = &rest[1..];
+            continue;
+        }
+        rest = &rest[tag.len() + 2..];
+        match kind {
+            "" => nodes.push(Node::Var(name)),
+            "/" => match open.pop() {
+                Some((open_name, when_set, outer)) if open_name == name => {
+                    let body = std::mem::replace(&mut nodes, outer);
+                    nodes.push(Node::Section {
+                        name,
+                        when_set,
+                        body,
+                    });
+                }
+                Some((open_name, ..)) => {
+                    return Err(format!("`{{/{name}}}` closes `{open_name}`"));
+                }
+                None => return Err(format!("`{{/{name}}}` closes nothing")),
+            },
+            _ => open.push((name, kind == "?", std::mem::take(&mut nodes))),
+        }
+    }
+    if !rest.is_empty() {
+        nodes.push(Node::Text(rest));
+    }
+    match open.last() {
+        Some((name, ..)) => Err(format!("`{name}` section is never closed")),
+        None => Ok(nodes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars {
+        let mut vars = Vars::create();
+        vars.set("player", "Alex").set("players", 3).set("eta", "");
+        vars
+    }
+
+    #[test]
+    fn placeholders() {
+        let vars = vars();
+        assert_eq!(vars.render("no placeholders"), "no placeholders");
+        assert_eq!(vars.render("hi {player}!"), "hi Alex!");
+        assert_eq!(vars.render("{players}/{max}"), "3/");
+        assert!(!vars.render("{rev}").is_empty());
+        // Not a placeholder
+        assert_eq!(vars.render("{foo} {?foo}"), "{foo} {?foo}");
+        assert_eq!(vars.render("{{player}"), "{player}");
+        assert_eq!(vars.render("{{{player}"), "{Alex");
+        assert_eq!(vars.render("open { and {player"), "open { and {player");
+    }
+
+    #[test]
+    fn sections() {
+        let vars = vars();
+        assert_eq!(vars.render("a{?player} {player}{/player}"), "a Alex");
+        assert_eq!(vars.render("a{!player} nobody{/player}"), "a");
+        // Empty values count as unknown
+        assert_eq!(
+            vars.render("{?eta}eta {eta}{/eta}{!eta}no eta{/eta}"),
+            "no eta"
+        );
+        assert_eq!(
+            vars.render(
+                "{?player}{?players}{players} with {player}{/players}{!max}, no max{/max}{/player}"
+            ),
+            "3 with Alex, no max"
+        );
+        assert_eq!(vars.render("{?max}{?player}{player}{/player}{/max}"), "");
+    }
+
+    #[test]
+    fn set_and_unset() {
+        let mut vars = vars();
+        vars.set_opt("player", None::<String>);
+        assert_eq!(vars.render("{!player}gone{/player}"), "gone");
+        vars.set_opt("player", Some("Steve"));
+        assert_eq!(vars.render("{player}"), "Steve");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(check("{?eta}{/eta} {{/eta} {foo}").is_ok());
+        assert_eq!(check("{/eta}").unwrap_err(), "`{/eta}` closes nothing");
+        assert_eq!(
+            check("{?eta}{?player}{/eta}{/player}").unwrap_err(),
+            "`{/eta}` closes `player`"
+        );
+        assert_eq!(
+            check("{?eta}{!player}{/player}").unwrap_err(),
+            "`eta` section is never closed"
+        );
+        // A template that doesn't parse is shown as it is
+        assert_eq!(vars().render("{?player}{player}"), "{?player}{player}");
+    }
+}
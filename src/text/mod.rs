@@ -0,0 +1,857 @@
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    error::{ProxyError, ProxyResult},
+    types::{nbt::Nbt, Codec, VarString},
+};
+
+/// The legacy `§` colors, by code, with their component name and rgb.
+const COLORS: [(char, &str, u32); 16] = [
+    ('0', "black", 0x000000),
+    ('1', "dark_blue", 0x0000AA),
+    ('2', "dark_green", 0x00AA00),
+    ('3', "dark_aqua", 0x00AAAA),
+    ('4', "dark_red", 0xAA0000),
+    ('5', "dark_purple", 0xAA00AA),
+    ('6', "gold", 0xFFAA00),
+    ('7', "gray", 0xAAAAAA),
+    ('8', "dark_gray", 0x555555),
+    ('9', "blue", 0x5555FF),
+    ('a', "green", 0x55FF55),
+    ('b', "aqua", 0x55FFFF),
+    ('c', "red", 0xFF5555),
+    ('d', "light_purple", 0xFF55FF),
+    ('e', "yellow", 0xFFFF55),
+    ('f', "white", 0xFFFFFF),
+];
+
+/// A JSON text component, what the status description and the disconnect reasons are.
+///
+/// Children inherit the style of their parent. Anything the proxy doesn't model
+/// (click and hover events, `score`, `selector`, ...) is kept in `other` and written back as it was.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(try_from = "Value", into = "Value")]
+pub struct TextComponent {
+    pub content: Content,
+    pub style: Style,
+    pub extra: Vec<TextComponent>,
+    pub other: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Content {
+    Text(String),
+    /// Looked up in the client's language, `%s` and `%1$s` are filled from `with`.
+    Translate {
+        key: String,
+        fallback: Option<String>,
+        with: Vec<TextComponent>,
+    },
+    /// The key bound to the action, like `key.jump`.
+    Keybind(String),
+    /// `score`, `selector` or `nbt`, the fields stay in `other`.
+    Other,
+}
+
+impl Default for Content {
+    fn default() -> Content {
+        Content::Text(String::new())
+    }
+}
+
+/// `None` inherits from the parent, `Some(false)` turns it off again.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Style {
+    /// A name like `gold`, or `#rrggbb`
+    pub color: Option<String>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underlined: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub obfuscated: Option<bool>,
+    pub font: Option<String>,
+    pub insertion: Option<String>,
+}
+
+impl Style {
+    pub fn is_empty(&self) -> bool {
+        *self == Style::default()
+    }
+
+    /// What a child with this style ends up with under `parent`.
+    fn inherit(&self, parent: &Style) -> Style {
+        Style {
+            color: self.color.clone().or_else(|| parent.color.clone()),
+            bold: self.bold.or(parent.bold),
+            italic: self.italic.or(parent.italic),
+            underlined: self.underlined.or(parent.underlined),
+            strikethrough: self.strikethrough.or(parent.strikethrough),
+            obfuscated: self.obfuscated.or(parent.obfuscated),
+            font: self.font.clone().or_else(|| parent.font.clone()),
+            insertion: self.insertion.clone().or_else(|| parent.insertion.clone()),
+        }
+    }
+
+    /// The codes that switch from a reset to this style.
+    fn legacy_codes(&self) -> String {
+        let mut codes = String::new();
+        if let Some(code) = self.color.as_deref().and_then(legacy_color) {
+            codes.push('§');
+            codes.push(code);
+        }
+        for (flag, code) in [
+            (self.obfuscated, 'k'),
+            (self.bold, 'l'),
+            (self.strikethrough, 'm'),
+            (self.underlined, 'n'),
+            (self.italic, 'o'),
+        ] {
+            if flag == Some(true) {
+                codes.push('§');
+                codes.push(code);
+            }
+        }
+        codes
+    }
+}
+
+impl TextComponent {
+    pub fn text(text: impl Into<String>) -> TextComponent {
+        TextComponent {
+            content: Content::Text(text.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Splits `§` formatted text into children, one per style.
+    /// A color resets the formatting like it does in the client, `§x§r§r§g§g§b§b` is a hex color.
+    pub fn from_legacy(legacy: &str) -> TextComponent {
+        let mut parts = Vec::new();
+        let mut style = Style::default();
+        let mut text = String::new();
+        let mut chars = legacy.chars();
+        while let Some(char) = chars.next() {
+            if char != '§' {
+                text.push(char);
+                continue;
+            }
+            let code = match chars.next() {
+                Some(x) => x.to_ascii_lowercase(),
+                None => break,
+            };
+            let mut next = style.clone();
+            match code {
+                'r' => next = Style::default(),
+                'x' => match read_hex_color(&mut chars) {
+                    Some(color) => {
+                        next = Style {
+                            color: Some(color),
+                            ..Default::default()
+                        }
+                    }
+                    None => continue,
+                },
+                'k' => next.obfuscated = Some(true),
+                'l' => next.bold = Some(true),
+                'm' => next.strikethrough = Some(true),
+                'n' => next.underlined = Some(true),
+                'o' => next.italic = Some(true),
+                _ => match COLORS.iter().find(|(x, ..)| *x == code) {
+                    Some((_, name, _)) => {
+                        next = Style {
+                            color: Some((*name).to_owned()),
+                            ..Default::default()
+                        }
+                    }
+                    // The client skips codes it doesn't know
+                    None => continue,
+                },
+            }
+            if !text.is_empty() {
+                parts.push(TextComponent {
+                    style: style.clone(),
+                    ..TextComponent::text(std::mem::take(&mut text))
+                });
+            }
+            style = next;
+        }
+        if !text.is_empty() {
+            parts.push(TextComponent {
+                style,
+                ..TextComponent::text(text)
+            });
+        }
+        match parts.len() {
+            0 => TextComponent::text(""),
+            1 if parts[0].style.is_empty() => parts.remove(0),
+            _ => TextComponent {
+                extra: parts,
+                ..TextComponent::text("")
+            },
+        }
+    }
+
+    /// Back to `§` formatted text, for the places that only take a string.
+    /// Hex colors become the closest legacy one, events and fonts are lost.
+    pub fn to_legacy(&self) -> String {
+        let mut out = String::new();
+        let mut current = Style::default();
+        self.write_legacy(&Style::default(), &mut current, &mut out);
+        out
+    }
+
+    fn write_legacy(&self, parent: &Style, current: &mut Style, out: &mut String) {
+        let style = self.style.inherit(parent);
+        let text = self.own_text();
+        if !text.is_empty() {
+            // Legacy codes can only add to the style, anything else needs a reset first
+            let visible = Style {
+                color: style.color.clone(),
+                bold: style.bold.filter(|x| *x),
+                italic: style.italic.filter(|x| *x),
+                underlined: style.underlined.filter(|x| *x),
+                strikethrough: style.strikethrough.filter(|x| *x),
+                obfuscated: style.obfuscated.filter(|x| *x),
+                ..Default::default()
+            };
+            if visible != *current {
+                if !current.is_empty() {
+                    out.push_str("§r");
+                }
+                out.push_str(&visible.legacy_codes());
+                *current = visible;
+            }
+            out.push_str(&text);
+        }
+        for child in &self.extra {
+            child.write_legacy(&style, current, out);
+        }
+    }
+
+    /// The text without any formatting.
+    pub fn to_plain(&self) -> String {
+        let mut out = self.own_text();
+        for child in &self.extra {
+            out.push_str(&child.to_plain());
+        }
+        out
+    }
+
+    /// The text of this part without its children. The client would translate keys
+    /// and keybinds, the proxy can only show the fallback or the key itself.
+    fn own_text(&self) -> String {
+        match &self.content {
+            Content::Text(text) => text.clone(),
+            Content::Translate {
+                key,
+                fallback,
+                with,
+            } => {
+                let args: Vec<String> = with.iter().map(TextComponent::to_plain).collect();
+                format_translation(fallback.as_ref().unwrap_or(key), &args)
+            }
+            Content::Keybind(key) => key.clone(),
+            Content::Other => String::new(),
+        }
+    }
+
+    /// Adds `other` after this one, without it inheriting this one's style.
+    pub fn append(&mut self, other: TextComponent) {
+        if self.style.is_empty() {
+            self.extra.push(other);
+            return;
+        }
+        let this = std::mem::take(self);
+        *self = TextComponent {
+            extra: vec![this, other],
+            ..TextComponent::text("")
+        };
+    }
+
+    /// The NBT form 1.20.3+ uses in Configuration and Play, the same shape as the json one.
+    pub fn to_nbt(&self) -> Nbt {
+        json_to_nbt(Value::from(self.clone()))
+    }
+
+    /// Just text, it serializes to a plain json string.
+    fn is_plain(&self) -> bool {
+        matches!(self.content, Content::Text(_))
+            && self.style.is_empty()
+            && self.extra.is_empty()
+            && self.other.is_empty()
+    }
+}
+
+/// A text component as a JSON string, how Status and Login (and Play before 1.20.3) send it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsonText(pub TextComponent);
+
+impl Codec for JsonText {
+    fn parse<I>(data: &mut I) -> ProxyResult<JsonText>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let json = VarString::parse(data)?.get_value();
+        serde_json::from_str(&json)
+            .map(JsonText)
+            .map_err(|err| ProxyError::Malformed(format!("text component: {err}")))
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        // A text component is plain data, serializing it can't fail
+        VarString::from(serde_json::to_string(&self.0).unwrap_or_default()).write(data)
+    }
+}
+
+/// A text component as network NBT, how Configuration and Play send it since 1.20.3.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NbtText(pub TextComponent);
+
+impl Codec for NbtText {
+    fn parse<I>(data: &mut I) -> ProxyResult<NbtText>
+    where
+        I: Iterator<Item = u8>,
+    {
+        TextComponent::try_from(nbt_to_json(Nbt::parse(data)?))
+            .map(NbtText)
+            .map_err(|err| ProxyError::Malformed(format!("text component: {err}")))
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        self.0.to_nbt().write(data)
+    }
+}
+
+/// Booleans become bytes, numbers the smallest of int, long and double that fits.
+/// Lists with mixed elements wrap them in compounds with an empty name, the client unwraps those.
+fn json_to_nbt(value: Value) -> Nbt {
+    match value {
+        Value::Null => Nbt::Compound(Vec::new()),
+        Value::Bool(x) => Nbt::Byte(x as i8),
+        Value::Number(x) => match x.as_i64() {
+            Some(x) => match i32::try_from(x) {
+                Ok(x) => Nbt::Int(x),
+                Err(_) => Nbt::Long(x),
+            },
+            None => Nbt::Double(x.as_f64().unwrap_or_default()),
+        },
+        Value::String(x) => Nbt::String(x),
+        Value::Array(list) => {
+            let list: Vec<Nbt> = list.into_iter().map(json_to_nbt).collect();
+            if list.windows(2).all(|x| x[0].id() == x[1].id()) {
+                return Nbt::List(list);
+            }
+            Nbt::List(
+                list.into_iter()
+                    .map(|x| match x {
+                        Nbt::Compound(_) => x,
+                        _ => Nbt::Compound(vec![(String::new(), x)]),
+                    })
+                    .collect(),
+            )
+        }
+        Value::Object(map) => Nbt::Compound(
+            map.into_iter()
+                .map(|(key, value)| (key, json_to_nbt(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// The way back from `json_to_nbt`: bytes that are 0 or 1 become booleans
+/// and compounds with just an empty name are unwrapped.
+fn nbt_to_json(nbt: Nbt) -> Value {
+    match nbt {
+        Nbt::Byte(x @ (0 | 1)) => Value::Bool(x == 1),
+        Nbt::Byte(x) => Value::from(x),
+        Nbt::Short(x) => Value::from(x),
+        Nbt::Int(x) => Value::from(x),
+        Nbt::Long(x) => Value::from(x),
+        Nbt::Float(x) => Value::from(x),
+        Nbt::Double(x) => Value::from(x),
+        Nbt::String(x) => Value::String(x),
+        Nbt::ByteArray(x) => Value::from(x),
+        Nbt::IntArray(x) => Value::from(x),
+        Nbt::LongArray(x) => Value::from(x),
+        Nbt::List(x) => Value::Array(x.into_iter().map(nbt_to_json).collect()),
+        Nbt::Compound(mut x) => {
+            if x.len() == 1 && x[0].0.is_empty() {
+                return nbt_to_json(x.remove(0).1);
+            }
+            Value::Object(
+                x.into_iter()
+                    .map(|(key, value)| (key, nbt_to_json(value)))
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Reads the `§r§r§g§g§b§b` after a `§x`.
+fn read_hex_color(chars: &mut std::str::Chars) -> Option<String> {
+    let mut lookahead = chars.clone();
+    let mut color = String::from("#");
+    for _ in 0..6 {
+        if lookahead.next()? != '§' {
+            return None;
+        }
+        let digit = lookahead.next()?;
+        if !digit.is_ascii_hexdigit() {
+            return None;
+        }
+        color.push(digit.to_ascii_lowercase());
+    }
+    *chars = lookahead;
+    Some(color)
+}
+
+/// The legacy code of a color name, or of the closest color for `#rrggbb`.
+fn legacy_color(color: &str) -> Option<char> {
+    if let Some((code, ..)) = COLORS.iter().find(|(_, name, _)| *name == color) {
+        return Some(*code);
+    }
+    let rgb = u32::from_str_radix(color.strip_prefix('#')?, 16).ok()?;
+    let channels = |x: u32| {
+        [
+            (x >> 16) as i32 & 0xFF,
+            (x >> 8) as i32 & 0xFF,
+            x as i32 & 0xFF,
+        ]
+    };
+    let wanted = channels(rgb);
+    COLORS
+        .iter()
+        .min_by_key(|(_, _, x)| {
+            channels(*x)
+                .iter()
+                .zip(wanted)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<i32>()
+        })
+        .map(|(code, ..)| *code)
+}
+
+/// `%s`, `%1$s` and `%%`, as far as the client's formatting goes.
+fn format_translation(format: &str, args: &[String]) -> String {
+    let mut out = String::new();
+    let mut next_arg = 0;
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            out.push('%');
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix('s') {
+            out.push_str(args.get(next_arg).map(String::as_str).unwrap_or_default());
+            next_arg += 1;
+            rest = after;
+            continue;
+        }
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        match rest[digits..].strip_prefix("$s") {
+            Some(after) if digits > 0 => {
+                let index: usize = rest[..digits].parse().unwrap_or(0);
+                out.push_str(
+                    index
+                        .checked_sub(1)
+                        .and_then(|x| args.get(x))
+                        .map(String::as_str)
+                        .unwrap_or_default(),
+                );
+                rest = after;
+            }
+            _ => out.push('%'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+impl TryFrom<Value> for TextComponent {
+    type Error = String;
+
+    /// A component is a string, an object, or a list where the rest are children of the first.
+    fn try_from(value: Value) -> Result<TextComponent, String> {
+        let mut map = match value {
+            Value::String(text) => return Ok(TextComponent::text(text)),
+            Value::Number(_) | Value::Bool(_) => return Ok(TextComponent::text(value.to_string())),
+            Value::Array(list) => {
+                let mut list = list.into_iter();
+                let mut first =
+                    TextComponent::try_from(list.next().ok_or("empty list as a text component")?)?;
+                for child in list {
+                    first.extra.push(TextComponent::try_from(child)?);
+                }
+                return Ok(first);
+            }
+            Value::Object(map) => map,
+            Value::Null => return Err("null as a text component".to_owned()),
+        };
+        let string = |map: &mut Map<String, Value>, key: &str| match map.remove(key) {
+            Some(Value::String(x)) => Ok(Some(x)),
+            Some(_) => Err(format!("`{key}` of a text component isn't a string")),
+            None => Ok(None),
+        };
+        let flag = |map: &mut Map<String, Value>, key: &str| match map.remove(key) {
+            Some(Value::Bool(x)) => Ok(Some(x)),
+            Some(_) => Err(format!("`{key}` of a text component isn't a bool")),
+            None => Ok(None),
+        };
+        let list = |map: &mut Map<String, Value>, key: &str| match map.remove(key) {
+            Some(Value::Array(list)) => list.into_iter().map(TextComponent::try_from).collect(),
+            Some(_) => Err(format!("`{key}` of a text component isn't a list")),
+            None => Ok(Vec::new()),
+        };
+
+        let content = if map.contains_key("text") {
+            match map.remove("text") {
+                Some(Value::String(x)) => Content::Text(x),
+                Some(x @ (Value::Number(_) | Value::Bool(_))) => Content::Text(x.to_string()),
+                _ => return Err("`text` of a text component isn't a string".to_owned()),
+            }
+        } else if let Some(key) = string(&mut map, "translate")? {
+            Content::Translate {
+                key,
+                fallback: string(&mut map, "fallback")?,
+                with: list(&mut map, "with")?,
+            }
+        } else if let Some(key) = string(&mut map, "keybind")? {
+            Content::Keybind(key)
+        } else if ["score", "selector", "nbt"]
+            .iter()
+            .any(|x| map.contains_key(*x))
+        {
+            Content::Other
+        } else {
+            // The client takes a component without content as empty text too
+            Content::default()
+        };
+        let style = Style {
+            color: string(&mut map, "color")?,
+            bold: flag(&mut map, "bold")?,
+            italic: flag(&mut map, "italic")?,
+            underlined: flag(&mut map, "underlined")?,
+            strikethrough: flag(&mut map, "strikethrough")?,
+            obfuscated: flag(&mut map, "obfuscated")?,
+            font: string(&mut map, "font")?,
+            insertion: string(&mut map, "insertion")?,
+        };
+        Ok(TextComponent {
+            content,
+            style,
+            extra: list(&mut map, "extra")?,
+            other: map,
+        })
+    }
+}
+
+impl From<TextComponent> for Value {
+    fn from(component: TextComponent) -> Value {
+        if component.is_plain() {
+            if let Content::Text(text) = component.content {
+                return Value::String(text);
+            }
+        }
+        let mut map = Map::new();
+        match component.content {
+            Content::Text(text) => {
+                map.insert("text".to_owned(), Value::String(text));
+            }
+            Content::Translate {
+                key,
+                fallback,
+                with,
+            } => {
+                map.insert("translate".to_owned(), Value::String(key));
+                if let Some(fallback) = fallback {
+                    map.insert("fallback".to_owned(), Value::String(fallback));
+                }
+                if !with.is_empty() {
+                    let with = with.into_iter().map(Value::from).collect();
+                    map.insert("with".to_owned(), Value::Array(with));
+                }
+            }
+            Content::Keybind(key) => {
+                map.insert("keybind".to_owned(), Value::String(key));
+            }
+            Content::Other => (),
+        }
+        let style = component.style;
+        for (key, value) in [
+            ("color", style.color),
+            ("font", style.font),
+            ("insertion", style.insertion),
+        ] {
+            if let Some(value) = value {
+                map.insert(key.to_owned(), Value::String(value));
+            }
+        }
+        for (key, value) in [
+            ("bold", style.bold),
+            ("italic", style.italic),
+            ("underlined", style.underlined),
+            ("strikethrough", style.strikethrough),
+            ("obfuscated", style.obfuscated),
+        ] {
+            if let Some(value) = value {
+                map.insert(key.to_owned(), Value::Bool(value));
+            }
+        }
+        if !component.extra.is_empty() {
+            let extra = component.extra.into_iter().map(Value::from).collect();
+            map.insert("extra".to_owned(), Value::Array(extra));
+        }
+        map.extend(component.other);
+        Value::Object(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types;
+    use serde_json::json;
+
+    fn component(value: Value) -> TextComponent {
+        TextComponent::try_from(value).unwrap()
+    }
+
+    #[test]
+    fn json_shapes() {
+        assert_eq!(component(json!("hi")), TextComponent::text("hi"));
+        assert_eq!(component(json!(3)), TextComponent::text("3"));
+        assert_eq!(component(json!({})), TextComponent::text(""));
+        // The rest of a list are children of the first
+        let list = component(json!(["a", {"text": "b", "bold": true}]));
+        assert_eq!(list.to_plain(), "ab");
+        assert_eq!(list.extra[0].style.bold, Some(true));
+        // Plain text goes back out as a string, unknown fields are kept
+        assert_eq!(Value::from(TextComponent::text("hi")), json!("hi"));
+        let event = json!({
+            "text": "click",
+            "color": "aqua",
+            "clickEvent": {"action": "open_url", "url": "https://example.com"},
+        });
+        assert_eq!(Value::from(component(event.clone())), event);
+        let score = json!({"score": {"name": "@p", "objective": "kills"}});
+        assert_eq!(component(score.clone()).content, Content::Other);
+        assert_eq!(Value::from(component(score.clone())), score);
+    }
+
+    #[test]
+    fn json_errors() {
+        for value in [
+            json!(null),
+            json!([]),
+            json!({"text": ["a"]}),
+            json!({"text": "a", "bold": "yes"}),
+            json!({"text": "a", "extra": "b"}),
+            json!({"translate": 1}),
+        ] {
+            assert!(TextComponent::try_from(value.clone()).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn append_keeps_the_style_to_the_first_part() {
+        let mut plain = TextComponent::text("a");
+        plain.append(TextComponent::from_legacy("§cb"));
+        assert_eq!(plain.to_legacy(), "a§cb");
+
+        let mut styled = TextComponent::from_legacy("§lA");
+        styled.append(TextComponent::text("B"));
+        assert_eq!(styled.to_legacy(), "§lA§rB");
+    }
+
+    fn styled(text: &str, style: Style) -> TextComponent {
+        TextComponent {
+            style,
+            ..TextComponent::text(text)
+        }
+    }
+
+    fn color(name: &str) -> Style {
+        Style {
+            color: Some(name.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn from_legacy_codes() {
+        assert_eq!(TextComponent::from_legacy("hi"), TextComponent::text("hi"));
+        assert_eq!(TextComponent::from_legacy(""), TextComponent::text(""));
+        let bold_green = Style {
+            bold: Some(true),
+            ..color("green")
+        };
+        assert_eq!(
+            TextComponent::from_legacy("§aHello §lWorld§r!"),
+            TextComponent {
+                extra: vec![
+                    styled("Hello ", color("green")),
+                    styled("World", bold_green),
+                    TextComponent::text("!"),
+                ],
+                ..TextComponent::text("")
+            }
+        );
+        // A single styled part still needs the plain parent
+        assert_eq!(
+            TextComponent::from_legacy("§Cred").extra,
+            [styled("red", color("red"))]
+        );
+    }
+
+    #[test]
+    fn from_legacy_resets() {
+        // A color drops the formatting before it
+        let parts = TextComponent::from_legacy("§l§nbold §9blue").extra;
+        assert_eq!(parts[0].style.bold, Some(true));
+        assert_eq!(parts[1], styled("blue", color("blue")));
+        // Unknown codes are skipped, a `§` at the end is dropped
+        assert_eq!(
+            TextComponent::from_legacy("a§zb§"),
+            TextComponent::text("ab")
+        );
+        assert_eq!(
+            TextComponent::from_legacy("§x§1§2§A§b§5§6hex").extra,
+            [styled("hex", color("#12ab56"))]
+        );
+        assert_eq!(TextComponent::from_legacy("§x§1§2z"), {
+            let mut parts = TextComponent::text("");
+            parts.extra = vec![styled("z", color("dark_green"))];
+            parts
+        });
+    }
+
+    #[test]
+    fn to_legacy() {
+        for legacy in [
+            "plain",
+            "§6Rusty§r §d<3",
+            "§aHello §r§a§lWorld§r!",
+            "§k§l§m§n§ox",
+        ] {
+            assert_eq!(TextComponent::from_legacy(legacy).to_legacy(), legacy);
+        }
+        // Children inherit, hex colors get the closest legacy one
+        let nested = component(json!({
+            "color": "gold",
+            "text": "",
+            "extra": ["a", {"text": "b", "bold": true}, {"text": "c", "color": "#ff5050"}],
+        }));
+        assert_eq!(nested.to_legacy(), "§6a§r§6§lb§r§cc");
+        assert_eq!(nested.to_plain(), "abc");
+        // Turning a flag off again takes a reset
+        let off =
+            component(json!({"text": "A", "bold": true, "extra": [{"text": "B", "bold": false}]}));
+        assert_eq!(off.to_legacy(), "§lA§rB");
+    }
+
+    #[test]
+    fn translations() {
+        let kicked = component(json!({
+            "translate": "multiplayer.disconnect.kicked",
+            "fallback": "%2$s kicked %1$s (%s%%)",
+            "with": ["Alex", {"text": "Tami", "color": "red"}],
+        }));
+        assert_eq!(kicked.to_plain(), "Tami kicked Alex (Alex%)");
+        let key_only = component(json!({"translate": "some.key"}));
+        assert_eq!(key_only.to_plain(), "some.key");
+    }
+
+    #[test]
+    fn json_to_nbt_types() {
+        assert_eq!(json_to_nbt(json!(true)), Nbt::Byte(1));
+        assert_eq!(json_to_nbt(json!(-5)), Nbt::Int(-5));
+        assert_eq!(json_to_nbt(json!(1i64 << 40)), Nbt::Long(1 << 40));
+        assert_eq!(json_to_nbt(json!(0.5)), Nbt::Double(0.5));
+        assert_eq!(json_to_nbt(json!("a")), Nbt::String("a".to_owned()));
+        assert_eq!(
+            json_to_nbt(json!({"bold": false, "text": "a"})),
+            Nbt::Compound(vec![
+                ("bold".to_owned(), Nbt::Byte(0)),
+                ("text".to_owned(), Nbt::String("a".to_owned())),
+            ])
+        );
+    }
+
+    #[test]
+    fn mixed_lists_are_wrapped() {
+        assert_eq!(
+            json_to_nbt(json!(["a", "b"])),
+            Nbt::List(vec![
+                Nbt::String("a".to_owned()),
+                Nbt::String("b".to_owned())
+            ])
+        );
+        assert_eq!(
+            json_to_nbt(json!(["a", {"text": "b"}, 1])),
+            Nbt::List(vec![
+                Nbt::Compound(vec![("".to_owned(), Nbt::String("a".to_owned()))]),
+                Nbt::Compound(vec![("text".to_owned(), Nbt::String("b".to_owned()))]),
+                Nbt::Compound(vec![("".to_owned(), Nbt::Int(1))]),
+            ])
+        );
+    }
+
+    #[test]
+    fn plain_text_is_a_string_tag() {
+        assert_eq!(
+            TextComponent::text("bye").to_nbt(),
+            Nbt::String("bye".to_owned())
+        );
+    }
+
+    #[test]
+    fn nbt_back_to_json() {
+        for value in [
+            json!({"text": "a", "bold": true, "extra": ["b", {"text": "c"}, 1]}),
+            json!(["a", "b"]),
+            json!({"score": {"name": "x", "value": 3}}),
+        ] {
+            assert_eq!(nbt_to_json(json_to_nbt(value.clone())), value);
+        }
+        // Only 0 and 1 were booleans
+        assert_eq!(nbt_to_json(Nbt::Byte(2)), json!(2));
+        assert_eq!(nbt_to_json(Nbt::IntArray(vec![1, 2])), json!([1, 2]));
+    }
+
+    #[test]
+    fn nbt_text_round_trips() {
+        for text in [
+            TextComponent::text("plain"),
+            TextComponent::from_legacy("§c§lKicked§r, §x§1§2§3§4§5§6hex\0🦀"),
+            component(json!({
+                "translate": "multiplayer.disconnect.kicked",
+                "fallback": "Kicked %s",
+                "with": ["Alex", {"text": "now", "italic": true}],
+                "clickEvent": {"action": "open_url", "url": "https://example.com"},
+                "extra": [{"keybind": "key.jump", "underlined": false}],
+            })),
+        ] {
+            types::round_trip(NbtText(text));
+        }
+    }
+
+    #[test]
+    fn json_and_nbt_text_round_trip() {
+        let reason = TextComponent::from_legacy("§cno §lway");
+        let data = types::round_trip(JsonText(reason.clone()));
+        let json = types::parse::<VarString>(&data).unwrap().get_value();
+        assert_eq!(json, serde_json::to_string(&reason).unwrap());
+
+        let data = types::round_trip(NbtText(reason.clone()));
+        assert_eq!(types::parse::<Nbt>(&data).unwrap(), reason.to_nbt());
+        types::round_trip(NbtText(TextComponent::text("bye")));
+
+        let not_json = VarString::from("{".to_owned()).get_data().unwrap();
+        assert!(types::parse::<JsonText>(&not_json).is_err());
+        assert!(types::parse::<NbtText>(&Nbt::List(Vec::new()).get_data().unwrap()).is_err());
+    }
+}
@@ -1,12 +1,138 @@
 use std::fmt::Display;
 
+use crate::error::{ProxyError, ProxyResult};
+
+pub mod nbt;
+
 const SEGMENT_BITS: u8 = 0x7F;
 const CONTINUE_BIT: u8 = 0x80;
 
-#[derive(Debug)]
+/// A type as it goes over the wire, `parse` reads back what `write` wrote.
+pub trait Codec: Sized {
+    fn parse<I>(data: &mut I) -> ProxyResult<Self>
+    where
+        I: Iterator<Item = u8>;
+
+    /// Fails for values the wire format can't hold, like an NBT string over 65535 bytes.
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()>;
+
+    #[cfg(test)]
+    fn get_data(&self) -> ProxyResult<Vec<u8>> {
+        let mut data = Vec::new();
+        self.write(&mut data)?;
+        Ok(data)
+    }
+}
+
+fn next_byte<I>(data: &mut I, what: &str) -> ProxyResult<u8>
+where
+    I: Iterator<Item = u8>,
+{
+    data.next()
+        .ok_or_else(|| ProxyError::Malformed(format!("{what} ended early")))
+}
+
+/// The capacity for a list of `length` elements, as read from the other side.
+/// Capped, so a huge length only costs memory once its elements actually arrive.
+pub(crate) fn list_capacity(length: usize) -> usize {
+    length.min(1024)
+}
+
+/// The fixed size numbers, big endian: Byte, Unsigned Byte, Short, Unsigned Short,
+/// Int, Long, Float and Double.
+macro_rules! big_endian {
+    ($($type:ty),*) => {$(
+        impl Codec for $type {
+            fn parse<I>(data: &mut I) -> ProxyResult<$type>
+            where
+                I: Iterator<Item = u8>,
+            {
+                let mut bytes = [0; std::mem::size_of::<$type>()];
+                for byte in bytes.iter_mut() {
+                    *byte = next_byte(data, stringify!($type))?;
+                }
+                Ok(<$type>::from_be_bytes(bytes))
+            }
+
+            fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+                data.extend_from_slice(&self.to_be_bytes());
+                Ok(())
+            }
+        }
+    )*};
+}
+
+big_endian!(i8, u8, i16, u16, i32, i64, u64, f32, f64);
+
+/// Boolean, one byte that has to be 0 or 1.
+impl Codec for bool {
+    fn parse<I>(data: &mut I) -> ProxyResult<bool>
+    where
+        I: Iterator<Item = u8>,
+    {
+        match next_byte(data, "boolean")? {
+            0 => Ok(false),
+            1 => Ok(true),
+            x => Err(ProxyError::Malformed(format!("boolean {x}"))),
+        }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        data.push(*self as u8);
+        Ok(())
+    }
+}
+
+/// Prefixed Optional: a boolean, then the value if it's true.
+impl<T: Codec> Codec for Option<T> {
+    fn parse<I>(data: &mut I) -> ProxyResult<Option<T>>
+    where
+        I: Iterator<Item = u8>,
+    {
+        match bool::parse(data)? {
+            true => Ok(Some(T::parse(data)?)),
+            false => Ok(None),
+        }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        self.is_some().write(data)?;
+        match self {
+            Some(x) => x.write(data),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Prefixed Array: a `VarInt` length, then the elements. `Vec<u8>` is a Prefixed Byte Array.
+impl<T: Codec> Codec for Vec<T> {
+    fn parse<I>(data: &mut I) -> ProxyResult<Vec<T>>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let length = VarInt::read(data)?;
+        if length < 0 {
+            return Err(ProxyError::malformed("negative array length"));
+        }
+        let mut vec = Vec::with_capacity(list_capacity(length as usize));
+        for _ in 0..length {
+            vec.push(T::parse(data)?);
+        }
+        Ok(vec)
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        VarInt::from(self.len() as i32).write(data)?;
+        for element in self {
+            element.write(data)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct VarInt {
     value: i32,
-    data: Vec<u8>,
 }
 
 impl Display for VarInt {
@@ -19,85 +145,101 @@ impl VarInt {
     pub fn get_int(&self) -> i32 {
         self.value
     }
-
-    /// Clones the data for use, the sturct is still usable.
-    pub fn get_data(&self) -> Vec<u8> {
-        self.data.clone()
+    pub fn read<I>(data: &mut I) -> ProxyResult<i32>
+    where
+        I: Iterator<Item = u8>,
+    {
+        Ok(VarInt::parse(data)?.value)
     }
-    /// Moves the data out from the struct. Struct is useless later.
-    pub fn move_data(self) -> Vec<u8> {
-        self.data
+    pub fn from(num: i32) -> VarInt {
+        VarInt { value: num }
     }
-    pub fn read<I>(data: &mut I) -> Option<i32>
+    /// Unlike the other types a `VarInt` always fits.
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut data = Vec::new();
+        // Shifting the unsigned value, so negative numbers end after 5 bytes too
+        write_var(self.value as u32 as u64, &mut data);
+        data
+    }
+}
+
+impl Codec for VarInt {
+    fn parse<I>(data: &mut I) -> ProxyResult<VarInt>
     where
         I: Iterator<Item = u8>,
     {
-        let mut value: i32 = 0;
-        let mut position = 0;
+        Ok(VarInt {
+            value: read_var(data, 5, "VarInt")? as i32,
+        })
+    }
 
-        for current_byte in data {
-            value |= ((current_byte & SEGMENT_BITS) as i32) << position;
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        data.append(&mut self.to_bytes());
+        Ok(())
+    }
+}
 
-            if current_byte & CONTINUE_BIT == 0 {
-                break;
-            }
-            position += 7;
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VarLong {
+    value: i64,
+}
 
-            if position > 32 {
-                todo!();
-            }
-        }
-        Some(value)
+#[allow(dead_code)]
+impl VarLong {
+    pub fn get_value(&self) -> i64 {
+        self.value
+    }
+    pub fn from(num: i64) -> VarLong {
+        VarLong { value: num }
     }
-    pub fn parse<I>(reader: &mut I) -> Option<VarInt>
+}
+
+impl Codec for VarLong {
+    fn parse<I>(data: &mut I) -> ProxyResult<VarLong>
     where
         I: Iterator<Item = u8>,
     {
-        let mut value: i32 = 0;
-        let mut position = 0;
-        let mut vec = Vec::new();
-
-        for current_byte in reader {
-            let current_byte = current_byte;
-            vec.push(current_byte);
-            value |= ((current_byte & SEGMENT_BITS) as i32) << position;
+        Ok(VarLong {
+            value: read_var(data, 10, "VarLong")? as i64,
+        })
+    }
 
-            if current_byte & CONTINUE_BIT == 0 {
-                break;
-            }
-            position += 7;
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        write_var(self.value as u64, data);
+        Ok(())
+    }
+}
 
-            if position > 32 {
-                return None;
-            }
+/// 7 bits per byte, least significant group first, the high bit says another one follows.
+fn read_var<I>(data: &mut I, max_bytes: u32, what: &str) -> ProxyResult<u64>
+where
+    I: Iterator<Item = u8>,
+{
+    let mut value: u64 = 0;
+    for position in 0..max_bytes {
+        let current_byte = next_byte(data, what)?;
+        value |= ((current_byte & SEGMENT_BITS) as u64) << (position * 7);
+        if current_byte & CONTINUE_BIT == 0 {
+            return Ok(value);
         }
-        Some(VarInt { value, data: vec })
     }
-    pub fn from(num: i32) -> Option<VarInt> {
-        Some(VarInt {
-            value: num,
-            data: VarInt::write_varint(num)?,
-        })
-    }
-    fn write_varint(num: i32) -> Option<Vec<u8>> {
-        let mut num = num;
-        let mut vec = Vec::new();
+    Err(ProxyError::Malformed(format!("{what} is too big")))
+}
+
+fn write_var(mut num: u64, data: &mut Vec<u8>) {
+    loop {
+        let byte = num as u8 & SEGMENT_BITS;
+        num >>= 7;
         if num == 0 {
-            vec.push(0);
-        }
-        while num != 0 {
-            vec.push(num as u8 & SEGMENT_BITS);
-            num = num >> 7;
-            if num != 0 {
-                let a = vec.pop()?;
-                vec.push(a | CONTINUE_BIT);
-            }
+            data.push(byte);
+            return;
         }
-        Some(vec)
+        data.push(byte | CONTINUE_BIT);
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct VarString {
     value: String,
 }
@@ -106,67 +248,375 @@ impl VarString {
     pub fn get_value(&self) -> String {
         self.value.clone()
     }
-    pub fn move_data(self) -> Option<Vec<u8>> {
-        let mut vec = VarInt::from(self.value.len() as i32)?.move_data();
-        vec.append(&mut (Vec::from(self.value.as_bytes())));
-        Some(vec)
+    pub fn from(string: String) -> VarString {
+        VarString { value: string }
     }
+}
 
-    pub fn get_data(&self) -> Option<Vec<u8>> {
-        let mut vec = VarInt::from(self.value.len() as i32)?.move_data();
-        vec.append(&mut (Vec::from(self.value.as_bytes())));
-        Some(vec)
+impl Codec for VarString {
+    fn parse<I>(data: &mut I) -> ProxyResult<VarString>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let bytes = Vec::<u8>::parse(data)?;
+        Ok(VarString {
+            value: String::from_utf8(bytes)
+                .map_err(|_| ProxyError::malformed("string is not utf-8"))?,
+        })
     }
 
-    pub fn from(string: String) -> VarString {
-        VarString { value: string }
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        VarInt::from(self.value.len() as i32).write(data)?;
+        data.extend_from_slice(self.value.as_bytes());
+        Ok(())
+    }
+}
+
+/// A `namespace:path` string like `minecraft:brand`, the namespace defaults to `minecraft`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Identifier {
+    namespace: String,
+    path: String,
+}
+
+impl Display for Identifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.path)
+    }
+}
+
+#[allow(dead_code)]
+impl Identifier {
+    pub fn get_namespace(&self) -> &str {
+        &self.namespace
+    }
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+    /// None if it has characters the game doesn't allow.
+    pub fn from(identifier: &str) -> Option<Identifier> {
+        let (namespace, path) = identifier
+            .split_once(':')
+            .unwrap_or(("minecraft", identifier));
+        let allowed =
+            |char: char| char.is_ascii_lowercase() || char.is_ascii_digit() || "_-.".contains(char);
+        if namespace.is_empty()
+            || !namespace.chars().all(allowed)
+            || !path.chars().all(|x| allowed(x) || x == '/')
+        {
+            return None;
+        }
+        Some(Identifier {
+            namespace: namespace.to_owned(),
+            path: path.to_owned(),
+        })
     }
-    pub fn parse<I>(data: &mut I) -> Option<VarString>
+}
+
+impl Codec for Identifier {
+    fn parse<I>(data: &mut I) -> ProxyResult<Identifier>
     where
         I: Iterator<Item = u8>,
     {
-        let length = VarInt::read(data)?;
-        let mut vec = Vec::new();
-        for _ in 0..length {
-            vec.push(data.next()?);
-        }
-        Some(VarString {
-            value: String::from_utf8(vec).ok()?,
+        let value = VarString::parse(data)?.value;
+        Identifier::from(&value)
+            .ok_or_else(|| ProxyError::Malformed(format!("identifier {value:?}")))
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        VarString::from(self.to_string()).write(data)
+    }
+}
+
+/// A block position packed in a long: x and z take 26 bits, y 12 (since 1.14).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl Codec for Position {
+    fn parse<I>(data: &mut I) -> ProxyResult<Position>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let value = i64::parse(data)?;
+        // Shifting the signed value back down keeps the sign of each part
+        Ok(Position {
+            x: (value >> 38) as i32,
+            y: (value << 52 >> 52) as i32,
+            z: (value << 26 >> 38) as i32,
         })
     }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        let value = ((self.x as i64 & 0x3FFFFFF) << 38)
+            | ((self.z as i64 & 0x3FFFFFF) << 12)
+            | (self.y as i64 & 0xFFF);
+        value.write(data)
+    }
 }
 
-pub struct UShort {
-    value: u16,
-    data: Vec<u8>,
+/// Byte Array: whatever is left of the packet, like the data of a plugin message.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ByteArray {
+    value: Vec<u8>,
 }
-impl UShort {
-    pub fn get_value(&self) -> u16 {
-        self.value
+
+#[allow(dead_code)]
+impl ByteArray {
+    pub fn get_value(&self) -> &[u8] {
+        &self.value
     }
-    pub fn get_data(&self) -> Vec<u8> {
-        self.data.clone()
+    pub fn from(bytes: Vec<u8>) -> ByteArray {
+        ByteArray { value: bytes }
     }
-    pub fn parse<I>(data: &mut I) -> Option<UShort>
+}
+
+impl Codec for ByteArray {
+    fn parse<I>(data: &mut I) -> ProxyResult<ByteArray>
     where
         I: Iterator<Item = u8>,
     {
-        let mut vec = vec![data.next()?];
-        let mut int: u16 = vec[0] as u16;
-        int = int << 8;
-        vec.push(data.next()?);
-        int |= vec[1] as u16;
-        Some(UShort {
-            value: int,
-            data: vec,
+        Ok(ByteArray {
+            value: data.collect(),
         })
     }
-    pub fn from(short: u16) -> UShort {
-        let mut vec = vec![(short >> 8) as u8];
-        vec.push(((short >> 8) << 8) as u8);
-        UShort {
-            value: short,
-            data: vec,
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        data.extend_from_slice(&self.value);
+        Ok(())
+    }
+}
+
+/// 128 bit UUID, sent as two big endian longs.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Uuid {
+    value: u128,
+}
+impl Display for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = format!("{:032x}", self.value);
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+impl Codec for Uuid {
+    fn parse<I>(data: &mut I) -> ProxyResult<Uuid>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let most = u64::parse(data)?;
+        let least = u64::parse(data)?;
+        Ok(Uuid {
+            value: (most as u128) << 64 | least as u128,
+        })
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> ProxyResult<()> {
+        data.extend_from_slice(&self.value.to_be_bytes());
+        Ok(())
+    }
+}
+
+/// Writes `value`, checks it reads back the same and uses up every byte, returns the bytes.
+#[cfg(test)]
+pub(crate) fn round_trip<T: Codec + PartialEq + std::fmt::Debug>(value: T) -> Vec<u8> {
+    let data = value.get_data().unwrap();
+    let mut reader = data.iter().copied();
+    assert_eq!(T::parse(&mut reader).unwrap(), value);
+    assert_eq!(reader.next(), None, "{value:?} left bytes behind");
+    data
+}
+
+#[cfg(test)]
+pub(crate) fn parse<T: Codec>(data: &[u8]) -> ProxyResult<T> {
+    T::parse(&mut data.iter().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn var_int() {
+        for (value, bytes) in [
+            (0, &[0x00][..]),
+            (1, &[0x01]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (25565, &[0xdd, 0xc7, 0x01]),
+            (2097151, &[0xff, 0xff, 0x7f]),
+            (i32::MAX, &[0xff, 0xff, 0xff, 0xff, 0x07]),
+            (-1, &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+            (i32::MIN, &[0x80, 0x80, 0x80, 0x80, 0x08]),
+        ] {
+            assert_eq!(round_trip(VarInt::from(value)), bytes, "{value}");
+        }
+    }
+
+    #[test]
+    fn var_int_errors() {
+        // A sixth byte is never allowed
+        assert!(parse::<VarInt>(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+        assert!(parse::<VarInt>(&[0x80, 0x80]).is_err());
+        assert!(parse::<VarInt>(&[]).is_err());
+    }
+
+    #[test]
+    fn big_endian() {
+        assert_eq!(round_trip(25565u16), [0x63, 0xdd]);
+        assert_eq!(round_trip(-2i16), [0xff, 0xfe]);
+        assert_eq!(round_trip(-1i8), [0xff]);
+        assert_eq!(round_trip(1i32), [0, 0, 0, 1]);
+        round_trip(i64::MIN);
+        round_trip(u64::MAX);
+        round_trip(-1.5f32);
+        round_trip(f64::MAX);
+        assert!(parse::<i64>(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn boolean() {
+        assert_eq!(round_trip(true), [1]);
+        assert_eq!(round_trip(false), [0]);
+        assert!(parse::<bool>(&[2]).is_err());
+    }
+
+    #[test]
+    fn prefixed_optional() {
+        assert_eq!(round_trip(None::<i32>), [0]);
+        assert_eq!(round_trip(Some(7u8)), [1, 7]);
+        assert!(parse::<Option<u8>>(&[1]).is_err());
+    }
+
+    #[test]
+    fn prefixed_array() {
+        assert_eq!(round_trip(Vec::<u8>::new()), [0]);
+        assert_eq!(round_trip(vec![1u8, 2, 3]), [3, 1, 2, 3]);
+        round_trip(vec![
+            VarString::from("a".to_owned()),
+            VarString::from("ünïcödé".to_owned()),
+        ]);
+        round_trip(vec![Some(VarInt::from(-1)), None]);
+        // Negative length
+        assert!(parse::<Vec<u8>>(&[0xff, 0xff, 0xff, 0xff, 0x0f]).is_err());
+        // Longer than the data
+        assert!(parse::<Vec<u8>>(&[0xff, 0xff, 0x7f, 1]).is_err());
+        assert!(parse::<Vec<u8>>(&[0xff, 0xff, 0xff, 0xff, 0x07]).is_err());
+    }
+
+    #[test]
+    fn list_capacity_is_capped() {
+        assert_eq!(list_capacity(3), 3);
+        assert_eq!(list_capacity(i32::MAX as usize), 1024);
+    }
+
+    #[test]
+    fn var_string() {
+        assert_eq!(
+            round_trip(VarString::from("hi".to_owned())),
+            [2, b'h', b'i']
+        );
+        round_trip(VarString::from("§a✓ 🦀".to_owned()));
+        assert!(parse::<VarString>(&[2, 0xc3, 0x28]).is_err());
+    }
+
+    #[test]
+    fn uuid() {
+        let uuid = Uuid {
+            value: 0x00010203_0405_0607_0809_0a0b0c0d0e0f,
+        };
+        assert_eq!(round_trip(uuid), (0..16).collect::<Vec<u8>>());
+        assert_eq!(uuid.to_string(), "00010203-0405-0607-0809-0a0b0c0d0e0f");
+        round_trip(Uuid { value: u128::MAX });
+        assert!(parse::<Uuid>(&[0; 15]).is_err());
+    }
+
+    #[test]
+    fn identifier() {
+        let brand = Identifier::from("minecraft:brand").unwrap();
+        assert_eq!(Identifier::from("brand"), Some(brand.clone()));
+        assert_eq!(brand.to_string(), "minecraft:brand");
+        round_trip(brand);
+        round_trip(Identifier::from("my_mod:path/to.thing-2").unwrap());
+        assert_eq!(Identifier::from("Minecraft:brand"), None);
+        assert_eq!(Identifier::from(":brand"), None);
+        assert_eq!(Identifier::from("mod:a:b"), None);
+        assert!(parse::<Identifier>(&[3, b'A', b':', b'b']).is_err());
+    }
+
+    #[test]
+    fn byte_array_takes_the_rest() {
+        let data = [5, b'f', b'a', b'b', b'r', b'i', b'c'];
+        let bytes = parse::<ByteArray>(&data).unwrap();
+        assert_eq!(bytes.get_value(), data);
+        round_trip(ByteArray { value: Vec::new() });
+    }
+
+    #[test]
+    fn var_long() {
+        for (value, bytes) in [
+            (0, &[0x00][..]),
+            (2147483648, &[0x80, 0x80, 0x80, 0x80, 0x08]),
+            (
+                i64::MAX,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+            ),
+            (
+                -1,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+            ),
+            (
+                i64::MIN,
+                &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01],
+            ),
+        ] {
+            assert_eq!(round_trip(VarLong::from(value)), bytes, "{value}");
+        }
+        // An eleventh byte is never allowed
+        let mut overlong = [0xff; 11];
+        overlong[10] = 0x01;
+        assert!(parse::<VarLong>(&overlong).is_err());
+        assert!(parse::<VarLong>(&[0x80; 10]).is_err());
+        assert!(parse::<VarLong>(&[0x80, 0x80]).is_err());
+    }
+
+    #[test]
+    fn position() {
+        // The example from the protocol docs
+        let position = Position {
+            x: 18357644,
+            y: 831,
+            z: -20882616,
+        };
+        assert_eq!(round_trip(position), 0x4607632C15B4833Fi64.to_be_bytes());
+        assert_eq!(
+            round_trip(Position {
+                x: -1,
+                y: -1,
+                z: -1
+            }),
+            [0xff; 8]
+        );
+        for (x, y, z) in [
+            (0, 0, 0),
+            (-33554432, -2048, -33554432),
+            (33554431, 2047, 33554431),
+            (-33554432, 2047, 33554431),
+            (1, -1, -2),
+        ] {
+            round_trip(Position { x, y, z });
         }
+        assert!(parse::<Position>(&[0; 7]).is_err());
     }
 }
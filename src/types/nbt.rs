@@ -0,0 +1,439 @@
+use crate::{
+    error::{ProxyError, ProxyResult},
+    types::{self, Codec},
+};
+
+/// How deep compounds and lists can nest, the same limit the game uses.
+const MAX_DEPTH: usize = 512;
+
+/// A named binary tag.
+///
+/// The network form (1.20.2+) is the tag id and the payload, the file form (`level.dat`,
+/// player data) also has a name after the id. Files are usually gzipped, that's up to the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Nbt {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// Every element has the same tag id, an empty list is a list of End tags.
+    List(Vec<Nbt>),
+    /// In the order it was read or built, names are unique.
+    Compound(Vec<(String, Nbt)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Nbt {
+    pub fn id(&self) -> u8 {
+        match self {
+            Nbt::Byte(_) => 1,
+            Nbt::Short(_) => 2,
+            Nbt::Int(_) => 3,
+            Nbt::Long(_) => 4,
+            Nbt::Float(_) => 5,
+            Nbt::Double(_) => 6,
+            Nbt::ByteArray(_) => 7,
+            Nbt::String(_) => 8,
+            Nbt::List(_) => 9,
+            Nbt::Compound(_) => 10,
+            Nbt::IntArray(_) => 11,
+            Nbt::LongArray(_) => 12,
+        }
+    }
+
+    /// The value under `name` in a compound.
+    pub fn get(&self, name: &str) -> Option<&Nbt> {
+        match self {
+            Nbt::Compound(entries) => entries
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Adds `value` to a compound, replacing an earlier one with the same name.
+    /// Does nothing for the other tags.
+    pub fn insert(&mut self, name: impl Into<String>, value: Nbt) {
+        if let Nbt::Compound(entries) = self {
+            let name = name.into();
+            match entries.iter_mut().find(|(key, _)| *key == name) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((name, value)),
+            }
+        }
+    }
+
+    /// The named file form.
+    pub fn get_named_data(&self, name: &str) -> ProxyResult<Vec<u8>> {
+        let mut vec = vec![self.id()];
+        write_string(name, &mut vec)?;
+        self.write_payload(&mut vec)?;
+        Ok(vec)
+    }
+
+    /// Reads the named file form.
+    pub fn parse_named<I>(data: &mut I) -> ProxyResult<(String, Nbt)>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let id = u8::parse(data)?;
+        if id == 0 {
+            return Err(ProxyError::malformed("nbt root is an End tag"));
+        }
+        let name = read_string(data)?;
+        Ok((name, Nbt::parse_payload(id, data, 0)?))
+    }
+
+    fn write_payload(&self, vec: &mut Vec<u8>) -> ProxyResult<()> {
+        match self {
+            Nbt::Byte(x) => x.write(vec),
+            Nbt::Short(x) => x.write(vec),
+            Nbt::Int(x) => x.write(vec),
+            Nbt::Long(x) => x.write(vec),
+            Nbt::Float(x) => x.write(vec),
+            Nbt::Double(x) => x.write(vec),
+            Nbt::ByteArray(x) => write_list(x, vec, i8::write),
+            Nbt::String(x) => write_string(x, vec),
+            Nbt::List(x) => {
+                let id = x.first().map(Nbt::id).unwrap_or(0);
+                if x.iter().any(|element| element.id() != id) {
+                    return Err(ProxyError::malformed("nbt list mixes tag ids"));
+                }
+                vec.push(id);
+                write_list(x, vec, Nbt::write_payload)
+            }
+            Nbt::Compound(x) => {
+                for (name, value) in x {
+                    vec.push(value.id());
+                    write_string(name, vec)?;
+                    value.write_payload(vec)?;
+                }
+                vec.push(0);
+                Ok(())
+            }
+            Nbt::IntArray(x) => write_list(x, vec, i32::write),
+            Nbt::LongArray(x) => write_list(x, vec, i64::write),
+        }
+    }
+
+    fn parse_payload<I>(id: u8, data: &mut I, depth: usize) -> ProxyResult<Nbt>
+    where
+        I: Iterator<Item = u8>,
+    {
+        if depth > MAX_DEPTH {
+            return Err(ProxyError::malformed("nbt is nested too deep"));
+        }
+        Ok(match id {
+            1 => Nbt::Byte(i8::parse(data)?),
+            2 => Nbt::Short(i16::parse(data)?),
+            3 => Nbt::Int(i32::parse(data)?),
+            4 => Nbt::Long(i64::parse(data)?),
+            5 => Nbt::Float(f32::parse(data)?),
+            6 => Nbt::Double(f64::parse(data)?),
+            7 => Nbt::ByteArray(read_list(data, i8::parse)?),
+            8 => Nbt::String(read_string(data)?),
+            9 => {
+                let id = u8::parse(data)?;
+                let list = read_list(data, |data| Nbt::parse_payload(id, data, depth + 1))?;
+                if id == 0 && !list.is_empty() {
+                    return Err(ProxyError::malformed("nbt list of End tags"));
+                }
+                Nbt::List(list)
+            }
+            10 => {
+                let mut entries: Vec<(String, Nbt)> = Vec::new();
+                loop {
+                    let id = u8::parse(data)?;
+                    if id == 0 {
+                        break;
+                    }
+                    let name = read_string(data)?;
+                    let value = Nbt::parse_payload(id, data, depth + 1)?;
+                    // The game keeps the last one as well
+                    entries.retain(|(key, _)| *key != name);
+                    entries.push((name, value));
+                }
+                Nbt::Compound(entries)
+            }
+            11 => Nbt::IntArray(read_list(data, i32::parse)?),
+            12 => Nbt::LongArray(read_list(data, i64::parse)?),
+            _ => {
+                return Err(ProxyError::Malformed(format!("unknown nbt tag id {id}")));
+            }
+        })
+    }
+}
+
+/// The nameless network form.
+impl Codec for Nbt {
+    fn parse<I>(data: &mut I) -> ProxyResult<Nbt>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let id = u8::parse(data)?;
+        if id == 0 {
+            return Err(ProxyError::malformed("nbt root is an End tag"));
+        }
+        Nbt::parse_payload(id, data, 0)
+    }
+
+    fn write(&self, vec: &mut Vec<u8>) -> ProxyResult<()> {
+        vec.push(self.id());
+        self.write_payload(vec)
+    }
+}
+
+/// An int length, then that many elements.
+fn read_list<I, T>(
+    data: &mut I,
+    mut element: impl FnMut(&mut I) -> ProxyResult<T>,
+) -> ProxyResult<Vec<T>>
+where
+    I: Iterator<Item = u8>,
+{
+    let length = i32::parse(data)?;
+    if length < 0 {
+        return Err(ProxyError::malformed("negative nbt length"));
+    }
+    let mut vec = Vec::with_capacity(types::list_capacity(length as usize));
+    for _ in 0..length {
+        vec.push(element(data)?);
+    }
+    Ok(vec)
+}
+
+fn write_list<T>(
+    list: &[T],
+    vec: &mut Vec<u8>,
+    element: impl Fn(&T, &mut Vec<u8>) -> ProxyResult<()>,
+) -> ProxyResult<()> {
+    (list.len() as i32).write(vec)?;
+    for x in list {
+        element(x, vec)?;
+    }
+    Ok(())
+}
+
+/// Java's modified utf-8: `\0` takes two bytes and characters outside the
+/// basic plane are two surrogates of three bytes each. At most 65535 bytes of that.
+fn write_string(string: &str, vec: &mut Vec<u8>) -> ProxyResult<()> {
+    let mut bytes = Vec::with_capacity(string.len());
+    for unit in string.encode_utf16() {
+        let encoded: &[u8] = match unit {
+            0x0001..=0x007F => &[unit as u8],
+            0x0000 | 0x0080..=0x07FF => &[0xC0 | (unit >> 6) as u8, 0x80 | (unit & 0x3F) as u8],
+            _ => &[
+                0xE0 | (unit >> 12) as u8,
+                0x80 | ((unit >> 6) & 0x3F) as u8,
+                0x80 | (unit & 0x3F) as u8,
+            ],
+        };
+        bytes.extend_from_slice(encoded);
+    }
+    if bytes.len() > u16::MAX as usize {
+        return Err(ProxyError::malformed(
+            "nbt string is longer than 65535 bytes",
+        ));
+    }
+    (bytes.len() as u16).write(vec)?;
+    vec.append(&mut bytes);
+    Ok(())
+}
+
+fn read_string<I>(data: &mut I) -> ProxyResult<String>
+where
+    I: Iterator<Item = u8>,
+{
+    let length = u16::parse(data)?;
+    let bytes = (0..length)
+        .map(|_| u8::parse(data))
+        .collect::<ProxyResult<Vec<u8>>>()?;
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.into_iter();
+    let invalid = || ProxyError::malformed("nbt string is not modified utf-8");
+    while let Some(first) = bytes.next() {
+        let mut next = || match bytes.next() {
+            Some(x) if x & 0xC0 == 0x80 => Ok((x & 0x3F) as u16),
+            _ => Err(invalid()),
+        };
+        units.push(match first {
+            0x00..=0x7F => first as u16,
+            0xC0..=0xDF => ((first & 0x1F) as u16) << 6 | next()?,
+            0xE0..=0xEF => ((first & 0x0F) as u16) << 12 | next()? << 6 | next()?,
+            _ => return Err(invalid()),
+        });
+    }
+    String::from_utf16(&units).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One of every tag, nested in a compound.
+    fn every_tag() -> Nbt {
+        Nbt::Compound(vec![
+            ("byte".to_owned(), Nbt::Byte(-1)),
+            ("short".to_owned(), Nbt::Short(i16::MIN)),
+            ("int".to_owned(), Nbt::Int(i32::MAX)),
+            ("long".to_owned(), Nbt::Long(-2)),
+            ("float".to_owned(), Nbt::Float(0.5)),
+            ("double".to_owned(), Nbt::Double(-1e300)),
+            ("bytes".to_owned(), Nbt::ByteArray(vec![-128, 0, 127])),
+            ("string".to_owned(), Nbt::String("hi".to_owned())),
+            (
+                "list".to_owned(),
+                Nbt::List(vec![Nbt::Short(1), Nbt::Short(2)]),
+            ),
+            ("empty list".to_owned(), Nbt::List(Vec::new())),
+            (
+                "compound".to_owned(),
+                Nbt::Compound(vec![("".to_owned(), Nbt::Compound(Vec::new()))]),
+            ),
+            ("ints".to_owned(), Nbt::IntArray(vec![i32::MIN, 0])),
+            ("longs".to_owned(), Nbt::LongArray(vec![i64::MAX])),
+        ])
+    }
+
+    /// `types::round_trip`, and the same as a named root tag.
+    fn round_trip(nbt: &Nbt) -> Vec<u8> {
+        let named = nbt.get_named_data("root").unwrap();
+        let mut reader = named.iter().copied();
+        assert_eq!(
+            Nbt::parse_named(&mut reader).unwrap(),
+            ("root".to_owned(), nbt.clone())
+        );
+        assert_eq!(reader.next(), None);
+        types::round_trip(nbt.clone())
+    }
+
+    #[test]
+    fn every_tag_round_trips() {
+        round_trip(&every_tag());
+        if let Nbt::Compound(entries) = every_tag() {
+            for (_, tag) in entries {
+                round_trip(&tag);
+            }
+        }
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(round_trip(&Nbt::Int(1)), [3, 0, 0, 0, 1]);
+        assert_eq!(
+            Nbt::Byte(1).get_named_data("a").unwrap(),
+            [1, 0, 1, b'a', 1],
+            "id, name, payload"
+        );
+        assert_eq!(
+            round_trip(&Nbt::Compound(vec![("a".to_owned(), Nbt::Byte(1))])),
+            [10, 1, 0, 1, b'a', 1, 0]
+        );
+        // An empty list is a list of End tags
+        assert_eq!(round_trip(&Nbt::List(Vec::new())), [9, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn modified_utf8() {
+        // `\0` takes two bytes
+        assert_eq!(
+            round_trip(&Nbt::String("a\0".to_owned())),
+            [8, 0, 3, b'a', 0xc0, 0x80]
+        );
+        // Outside the basic plane: two surrogates of three bytes each
+        assert_eq!(
+            round_trip(&Nbt::String("🦀".to_owned())),
+            [8, 0, 6, 0xed, 0xa0, 0xbe, 0xed, 0xb6, 0x80]
+        );
+        round_trip(&Nbt::String("§aü✓🦀\0".to_owned()));
+        // Real utf-8 for the crab, and an unpaired surrogate
+        assert!(parse(&[8, 0, 4, 0xf0, 0x9f, 0xa6, 0x80]).is_err());
+        assert!(parse(&[8, 0, 3, 0xed, 0xa0, 0xbe]).is_err());
+    }
+
+    #[test]
+    fn unwritable() {
+        let longest = "a".repeat(u16::MAX as usize);
+        round_trip(&Nbt::String(longest.clone()));
+        assert!(Nbt::String(longest.clone() + "a").get_data().is_err());
+        // Short in characters, but `\0` takes two bytes
+        let nulls = "\0".repeat(u16::MAX as usize / 2 + 1);
+        assert!(Nbt::String(nulls).get_data().is_err());
+        // Names count too
+        assert!(Nbt::Byte(1)
+            .get_named_data(&(longest.clone() + "a"))
+            .is_err());
+        let compound = Nbt::Compound(vec![(longest + "a", Nbt::Byte(1))]);
+        assert!(compound.get_data().is_err());
+
+        let mixed = Nbt::List(vec![Nbt::Int(1), Nbt::Long(1)]);
+        assert!(mixed.get_data().is_err());
+        // Nested in something that is fine itself
+        let nested = Nbt::Compound(vec![("list".to_owned(), Nbt::List(vec![mixed]))]);
+        assert!(nested.get_data().is_err());
+        // Lists of lists only need the outer tag ids to match
+        round_trip(&Nbt::List(vec![
+            Nbt::List(vec![Nbt::Int(1)]),
+            Nbt::List(vec![Nbt::String(String::new())]),
+        ]));
+    }
+
+    #[test]
+    fn duplicate_names_keep_the_last() {
+        let data = [10, 1, 0, 1, b'a', 1, 1, 0, 1, b'a', 2, 0];
+        assert_eq!(
+            parse(&data).unwrap(),
+            Nbt::Compound(vec![("a".to_owned(), Nbt::Byte(2))])
+        );
+    }
+
+    fn parse(data: &[u8]) -> ProxyResult<Nbt> {
+        types::parse(data)
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut nested = Nbt::Int(0);
+        for _ in 0..MAX_DEPTH {
+            nested = Nbt::List(vec![nested]);
+        }
+        round_trip(&nested);
+        // One more compound than that
+        let mut data = vec![10];
+        data.extend([10, 0, 0].repeat(MAX_DEPTH + 1));
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        // Every cut off version of a valid tag
+        let data = every_tag().get_data().unwrap();
+        for length in 0..data.len() {
+            assert!(parse(&data[..length]).is_err(), "cut at {length}");
+        }
+        let named = every_tag().get_named_data("root").unwrap();
+        for length in 0..named.len() {
+            assert!(
+                Nbt::parse_named(&mut named[..length].iter().copied()).is_err(),
+                "cut at {length}"
+            );
+        }
+        assert!(parse(&[0]).is_err(), "End tag root");
+        assert!(parse(&[13]).is_err(), "unknown tag id");
+        assert!(
+            parse(&[7, 0xff, 0xff, 0xff, 0xff]).is_err(),
+            "negative length"
+        );
+        assert!(
+            parse(&[7, 0x7f, 0xff, 0xff, 0xff, 1]).is_err(),
+            "longer than the data"
+        );
+        assert!(parse(&[9, 0, 0, 0, 0, 1]).is_err(), "list of End tags");
+    }
+}
@@ -0,0 +1,123 @@
+/// Every release since the netty rewrite (1.7) by protocol number, oldest first.
+/// Releases that didn't change the protocol share an entry.
+const RELEASES: [(i32, &[&str]); 47] = [
+    (4, &["1.7.2", "1.7.3", "1.7.4", "1.7.5"]),
+    (5, &["1.7.6", "1.7.7", "1.7.8", "1.7.9", "1.7.10"]),
+    (
+        47,
+        &[
+            "1.8", "1.8.1", "1.8.2", "1.8.3", "1.8.4", "1.8.5", "1.8.6", "1.8.7", "1.8.8", "1.8.9",
+        ],
+    ),
+    (107, &["1.9"]),
+    (108, &["1.9.1"]),
+    (109, &["1.9.2"]),
+    (110, &["1.9.3", "1.9.4"]),
+    (210, &["1.10", "1.10.1", "1.10.2"]),
+    (315, &["1.11"]),
+    (316, &["1.11.1", "1.11.2"]),
+    (335, &["1.12"]),
+    (338, &["1.12.1"]),
+    (340, &["1.12.2"]),
+    (393, &["1.13"]),
+    (401, &["1.13.1"]),
+    (404, &["1.13.2"]),
+    (477, &["1.14"]),
+    (480, &["1.14.1"]),
+    (485, &["1.14.2"]),
+    (490, &["1.14.3"]),
+    (498, &["1.14.4"]),
+    (573, &["1.15"]),
+    (575, &["1.15.1"]),
+    (578, &["1.15.2"]),
+    (735, &["1.16"]),
+    (736, &["1.16.1"]),
+    (751, &["1.16.2"]),
+    (753, &["1.16.3"]),
+    (754, &["1.16.4", "1.16.5"]),
+    (755, &["1.17"]),
+    (756, &["1.17.1"]),
+    (757, &["1.18", "1.18.1"]),
+    (758, &["1.18.2"]),
+    (759, &["1.19"]),
+    (760, &["1.19.1", "1.19.2"]),
+    (761, &["1.19.3"]),
+    (762, &["1.19.4"]),
+    (763, &["1.20", "1.20.1"]),
+    (764, &["1.20.2"]),
+    (765, &["1.20.3", "1.20.4"]),
+    (766, &["1.20.5", "1.20.6"]),
+    (767, &["1.21", "1.21.1"]),
+    (768, &["1.21.2", "1.21.3"]),
+    (769, &["1.21.4"]),
+    (770, &["1.21.5"]),
+    (771, &["1.21.6"]),
+    (772, &["1.21.7", "1.21.8"]),
+];
+
+fn releases(protocol_version: i32) -> Option<&'static [&'static str]> {
+    RELEASES
+        .iter()
+        .find(|(protocol, _)| *protocol == protocol_version)
+        .map(|(_, names)| *names)
+}
+
+/// The first release with `protocol_version`, like `1.21` for 767.
+pub fn name(protocol_version: i32) -> Option<&'static str> {
+    releases(protocol_version)?.first().copied()
+}
+
+/// The last release with `protocol_version`, like `1.21.1` for 767.
+pub fn last_name(protocol_version: i32) -> Option<&'static str> {
+    releases(protocol_version)?.last().copied()
+}
+
+/// The protocol of the release `name`, like 767 for `1.21.1`.
+pub fn protocol(name: &str) -> Option<i32> {
+    RELEASES
+        .iter()
+        .find(|(_, names)| names.contains(&name))
+        .map(|(protocol, _)| *protocol)
+}
+
+/// `1.21 (767)` for the logs, just the number for snapshots and unknown versions.
+pub fn describe(protocol_version: i32) -> String {
+    match name(protocol_version) {
+        Some(name) => format!("{name} ({protocol_version})"),
+        None => format!("protocol {protocol_version}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_protocols() {
+        assert_eq!(name(767), Some("1.21"));
+        assert_eq!(last_name(767), Some("1.21.1"));
+        assert_eq!(name(47), Some("1.8"));
+        assert_eq!(last_name(47), Some("1.8.9"));
+        assert_eq!(name(766), Some("1.20.5"));
+        assert_eq!(name(1000), None);
+        assert_eq!(protocol("1.21.1"), Some(767));
+        assert_eq!(protocol("1.7.2"), Some(4));
+        assert_eq!(protocol("1.21.8"), Some(772));
+        assert_eq!(protocol("1.22"), None);
+        assert_eq!(describe(767), "1.21 (767)");
+        assert_eq!(describe(0x40000100), "protocol 1073742080");
+    }
+
+    #[test]
+    fn releases_are_in_order() {
+        for pair in RELEASES.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{} before {}", pair[0].0, pair[1].0);
+        }
+        for (protocol_version, names) in RELEASES {
+            assert!(!names.is_empty());
+            for name in names {
+                assert_eq!(protocol(name), Some(protocol_version));
+            }
+        }
+    }
+}